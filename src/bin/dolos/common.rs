@@ -1,9 +1,13 @@
+use dolos::ledger::{network::NetworkParams, pparams, time::ChainTime};
 use dolos::{state, wal};
 use miette::{Context as _, IntoDiagnostic};
 use pallas::ledger::configs::alonzo::GenesisFile as AlonzoFile;
 use pallas::ledger::configs::byron::GenesisFile as ByronFile;
 use pallas::ledger::configs::shelley::GenesisFile as ShelleyFile;
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
@@ -11,15 +15,79 @@ use tracing_subscriber::{filter::Targets, prelude::*};
 
 use dolos::prelude::*;
 
-use crate::{GenesisConfig, LoggingConfig};
+use crate::{GenesisConfig, LoggingConfig, StorageConfig};
 
 pub type Stores = (wal::redb::WalStore, state::LedgerStore);
 
-pub fn open_wal(config: &crate::Config) -> Result<wal::redb::WalStore, Error> {
-    let root = &config.storage.path;
+fn check_writable(dir: &Path) -> Result<(), Error> {
+    let probe = dir.join(".dolos-write-check");
+
+    std::fs::File::create(&probe).map_err(|err| {
+        Error::config(format!(
+            "storage path {} is not writable: {err}",
+            dir.display()
+        ))
+    })?;
+
+    let _ = std::fs::remove_file(probe);
+
+    Ok(())
+}
+
+/// Checks that the storage root can be used before opening any store
+///
+/// The root is created on demand, so it's fine if it doesn't exist yet as long
+/// as its parent does. Catching these here gives a clear message instead of an
+/// opaque error from the storage engine.
+pub fn validate_storage(config: &StorageConfig) -> Result<(), Error> {
+    if config.path.is_none() {
+        warn!(
+            path = crate::DEFAULT_STORAGE_PATH,
+            "storage path not set, using default"
+        );
+    }
+
+    let root = config.path();
+
+    if root.exists() {
+        if !root.is_dir() {
+            return Err(Error::config(format!(
+                "storage path {} is not a directory",
+                root.display()
+            )));
+        }
 
+        return check_writable(root);
+    }
+
+    let parent = match root.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+
+    if !parent.is_dir() {
+        return Err(Error::config(format!(
+            "parent directory of storage path {} doesn't exist",
+            root.display()
+        )));
+    }
+
+    check_writable(parent)
+}
+
+/// Validates the storage config and creates the storage root if needed
+fn ensure_storage_root(config: &crate::Config) -> Result<&Path, Error> {
+    validate_storage(&config.storage)?;
+
+    let root = config.storage.path();
     std::fs::create_dir_all(root).map_err(Error::storage)?;
 
+    Ok(root)
+}
+
+pub fn open_wal(config: &crate::Config) -> Result<wal::redb::WalStore, Error> {
+    let root = ensure_storage_root(config)?;
+
     let wal = wal::redb::WalStore::open(root.join("wal"), config.storage.wal_cache)
         .map_err(Error::storage)?;
 
@@ -27,25 +95,98 @@ pub fn open_wal(config: &crate::Config) -> Result<wal::redb::WalStore, Error> {
 }
 
 pub fn define_ledger_path(config: &crate::Config) -> Result<PathBuf, Error> {
-    let root = &config.storage.path;
-    std::fs::create_dir_all(root).map_err(Error::storage)?;
+    let root = ensure_storage_root(config)?;
 
     let ledger = root.join("ledger");
 
     Ok(ledger)
 }
 
-pub fn open_data_stores(config: &crate::Config) -> Result<Stores, Error> {
-    let root = &config.storage.path;
+/// Opens a ledger split between the immutable path of the config and a
+/// volatile db in the storage root
+fn open_split_ledger(
+    config: &crate::Config,
+    root: &Path,
+    immutable: &Path,
+) -> Result<state::LedgerStore, Error> {
+    if config.storage.datum_index || config.storage.script_ref_index || config.storage.slot_index {
+        warn!("optional indexes are not supported by a split ledger, skipping");
+    }
 
-    std::fs::create_dir_all(root).map_err(Error::storage)?;
+    let ledger = state::redb::split::LedgerStore::open(
+        immutable,
+        root.join("ledger-volatile"),
+        config.storage.ledger_cache,
+    )
+    .map_err(Error::storage)?;
+
+    Ok(ledger.into())
+}
+
+fn open_ledger(config: &crate::Config, root: &Path) -> Result<state::LedgerStore, Error> {
+    if let Some(immutable) = &config.storage.immutable_path {
+        return open_split_ledger(config, root, immutable);
+    }
+
+    let ledger = state::redb::LedgerStore::open(root.join("ledger"), config.storage.ledger_cache)
+        .map_err(Error::storage)?;
+
+    if config.storage.datum_index {
+        match ledger.enable_datum_index() {
+            Err(state::LedgerError::InvalidStoreVersion) => {
+                warn!("datum index is not supported by this ledger schema, skipping")
+            }
+            x => x.map_err(Error::storage)?,
+        }
+    }
+
+    if config.storage.script_ref_index {
+        match ledger.enable_script_ref_index() {
+            Err(state::LedgerError::InvalidStoreVersion) => {
+                warn!("reference script index is not supported by this ledger schema, skipping")
+            }
+            x => x.map_err(Error::storage)?,
+        }
+    }
+
+    if config.storage.slot_index {
+        match ledger.enable_slot_index() {
+            Err(state::LedgerError::InvalidStoreVersion) => {
+                warn!("slot index is not supported by this ledger schema, skipping")
+            }
+            x => x.map_err(Error::storage)?,
+        }
+    }
+
+    Ok(ledger.into())
+}
+
+pub fn open_data_stores(config: &crate::Config) -> Result<Stores, Error> {
+    let root = ensure_storage_root(config)?;
 
     let wal = wal::redb::WalStore::open(root.join("wal"), config.storage.wal_cache)
         .map_err(Error::storage)?;
 
-    let ledger = state::redb::LedgerStore::open(root.join("ledger"), config.storage.ledger_cache)
-        .map_err(Error::storage)?
-        .into();
+    let mut ledger = open_ledger(config, root)?;
+
+    if let Some(capacity) = config.storage.query_cache {
+        ledger = ledger.with_query_cache(capacity);
+    }
+
+    if let Some(capacity) = config.storage.decode_cache {
+        ledger = ledger.with_decode_cache(capacity);
+    }
+
+    if let Some(slots) = config.storage.finalize_batch {
+        ledger = ledger.with_finalize_batch(slots);
+    }
+
+    if config.storage.delta_log {
+        let log = state::delta_log::WriteAheadLog::open(root.join("ledger.log"))
+            .map_err(Error::storage)?;
+
+        ledger = ledger.with_write_ahead_log(log);
+    }
 
     Ok((wal, ledger))
 }
@@ -109,6 +250,23 @@ pub fn open_genesis_files(config: &GenesisConfig) -> miette::Result<GenesisFiles
     Ok((byron_genesis, shelley_genesis, alonzo_genesis))
 }
 
+/// Slot timing of the network, with the configured override applied
+pub fn chain_time(config: &GenesisConfig, genesis: &pparams::Genesis) -> miette::Result<ChainTime> {
+    let network = NetworkParams::from_genesis(genesis.byron, genesis.shelley)
+        .into_diagnostic()
+        .context("resolving network params")?;
+
+    let time = ChainTime::from(&network);
+
+    match &config.time_override {
+        Some(values) => time
+            .with_override(values)
+            .into_diagnostic()
+            .context("applying genesis time override"),
+        None => Ok(time),
+    }
+}
+
 #[inline]
 #[cfg(unix)]
 async fn wait_for_exit_signal() {
@@ -166,3 +324,58 @@ pub async fn run_pipeline(pipeline: gasket::daemon::Daemon, exit: CancellationTo
 pub fn spawn_pipeline(pipeline: gasket::daemon::Daemon, exit: CancellationToken) -> JoinHandle<()> {
     tokio::spawn(run_pipeline(pipeline, exit))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage_at(path: &Path) -> StorageConfig {
+        StorageConfig {
+            path: Some(path.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn storage_path_defaults() {
+        let config = StorageConfig {
+            path: None,
+            ..Default::default()
+        };
+
+        assert_eq!(config.path(), Path::new(crate::DEFAULT_STORAGE_PATH));
+    }
+
+    #[test]
+    fn storage_path_can_be_created() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        // an existing directory
+        assert!(validate_storage(&storage_at(tmp.path())).is_ok());
+
+        // a missing directory with an existing parent
+        assert!(validate_storage(&storage_at(&tmp.path().join("data"))).is_ok());
+
+        // the probe file is cleaned up
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn storage_path_without_parent() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("missing").join("data");
+
+        let err = validate_storage(&storage_at(&path)).unwrap_err();
+        assert!(err.to_string().contains("doesn't exist"));
+    }
+
+    #[test]
+    fn storage_path_is_a_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("data");
+        std::fs::write(&path, b"").unwrap();
+
+        let err = validate_storage(&storage_at(&path)).unwrap_err();
+        assert!(err.to_string().contains("not a directory"));
+    }
+}
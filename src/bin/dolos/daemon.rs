@@ -33,9 +33,20 @@ pub async fn run(config: super::Config, _args: &Args) -> miette::Result<()> {
 
     // We need new file handled for the separate process.
     let (byron, shelley, alonzo) = crate::common::open_genesis_files(&config.genesis)?;
+
+    let chain_time = crate::common::chain_time(
+        &config.genesis,
+        &dolos::ledger::pparams::Genesis {
+            byron: &byron,
+            shelley: &shelley,
+            alonzo: &alonzo,
+        },
+    )?;
+
     let serve = tokio::spawn(dolos::serve::serve(
         config.serve,
         (alonzo, byron, shelley),
+        chain_time,
         wal.clone(),
         ledger.clone(),
         mempool.clone(),
@@ -51,6 +62,11 @@ pub async fn run(config: super::Config, _args: &Args) -> miette::Result<()> {
     serve.context("serve thread")?;
     relay.into_diagnostic().context("relay thread")?;
 
+    ledger
+        .flush()
+        .into_diagnostic()
+        .context("flushing ledger")?;
+
     warn!("shutdown complete");
 
     Ok(())
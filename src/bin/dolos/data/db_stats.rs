@@ -0,0 +1,123 @@
+use dolos::ledger::{pparams, ChainPoint};
+use dolos::state::{LedgerStats, LedgerStore};
+use miette::{Context, IntoDiagnostic};
+use serde_json::json;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// print the stats as json instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+fn point_to_json(point: &Option<ChainPoint>) -> serde_json::Value {
+    match point {
+        Some(ChainPoint(slot, hash)) => json!({ "slot": slot, "hash": hash.to_string() }),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn point_to_string(point: &Option<ChainPoint>) -> String {
+    match point {
+        Some(ChainPoint(slot, hash)) => format!("{slot} ({hash})"),
+        None => "-".into(),
+    }
+}
+
+fn tip_protocol_version(
+    config: &crate::Config,
+    ledger: &LedgerStore,
+    tip: &Option<ChainPoint>,
+) -> miette::Result<Option<usize>> {
+    let Some(ChainPoint(slot, _)) = tip else {
+        return Ok(None);
+    };
+
+    let (byron, shelley, alonzo) = crate::common::open_genesis_files(&config.genesis)?;
+
+    let genesis = pparams::Genesis {
+        byron: &byron,
+        shelley: &shelley,
+        alonzo: &alonzo,
+    };
+
+    let (epoch, _) = crate::common::chain_time(&config.genesis, &genesis)?.slot_to_epoch(*slot);
+
+    // the full history can be large, it's folded as it's read
+    let updates = ledger
+        .stream_pparams(*slot)
+        .into_diagnostic()
+        .context("retrieving pparams")?;
+
+    let pparams = pparams::fold_pparams_streamed(&genesis, updates, epoch)
+        .into_diagnostic()
+        .context("folding pparams")?;
+
+    Ok(Some(pparams.protocol_version()))
+}
+
+fn print_table(stats: &LedgerStats, protocol: Option<usize>) {
+    println!("utxos:            {}", stats.utxo_count);
+    println!("first cursor:     {}", point_to_string(&stats.first_cursor));
+    println!("cursor:           {}", point_to_string(&stats.cursor));
+
+    match protocol {
+        Some(x) => println!("protocol version: {x}"),
+        None => println!("protocol version: -"),
+    }
+
+    if stats.tables.is_empty() {
+        return;
+    }
+
+    println!("---");
+    println!("{:<16} {:>14} {:>16}", "table", "entries", "stored bytes");
+
+    for table in stats.tables.iter() {
+        println!(
+            "{:<16} {:>14} {:>16}",
+            table.name, table.entries, table.stored_bytes
+        );
+    }
+}
+
+pub fn run(config: &crate::Config, args: &Args) -> miette::Result<()> {
+    crate::common::setup_tracing(&config.logging)?;
+
+    let (_, ledger) = crate::common::open_data_stores(config)?;
+
+    let stats = ledger
+        .stats()
+        .into_diagnostic()
+        .context("computing ledger stats")?;
+
+    let protocol = tip_protocol_version(config, &ledger, &stats.cursor)?;
+
+    if args.json {
+        let tables: Vec<_> = stats
+            .tables
+            .iter()
+            .map(|x| {
+                json!({
+                    "name": x.name,
+                    "entries": x.entries,
+                    "stored_bytes": x.stored_bytes,
+                })
+            })
+            .collect();
+
+        let json = json!({
+            "utxo_count": stats.utxo_count,
+            "first_cursor": point_to_json(&stats.first_cursor),
+            "cursor": point_to_json(&stats.cursor),
+            "protocol_version": protocol,
+            "tables": tables,
+        });
+
+        println!("{}", serde_json::to_string_pretty(&json).into_diagnostic()?);
+    } else {
+        print_table(&stats, protocol);
+    }
+
+    Ok(())
+}
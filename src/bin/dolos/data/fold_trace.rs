@@ -0,0 +1,130 @@
+use dolos::ledger::{pparams, ChainPoint, PParamsBody};
+use itertools::Itertools;
+use miette::{bail, Context, IntoDiagnostic};
+use pallas::ledger::traverse::MultiEraUpdate;
+use serde_json::json;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// epoch up to which the params are folded
+    #[arg(long)]
+    epoch: u64,
+
+    /// print the trace as json instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+/// Fields that only change when the protocol version moves forward
+const HARDFORK_FIELDS: [&str; 3] = ["era", "protocol_version", "block_version"];
+
+fn is_hardfork(changes: &[pparams::ChangedField]) -> bool {
+    changes.iter().any(|x| HARDFORK_FIELDS.contains(&x.name))
+}
+
+pub fn run(config: &crate::Config, args: &Args) -> miette::Result<()> {
+    crate::common::setup_tracing(&config.logging)?;
+
+    let (_, ledger) = crate::common::open_data_stores(config)?;
+
+    let Some(ChainPoint(tip, _)) = ledger
+        .cursor()
+        .into_diagnostic()
+        .context("reading ledger cursor")?
+    else {
+        bail!("ledger is empty, there are no proposals to fold");
+    };
+
+    let (byron, shelley, alonzo) = crate::common::open_genesis_files(&config.genesis)?;
+
+    let genesis = pparams::Genesis {
+        byron: &byron,
+        shelley: &shelley,
+        alonzo: &alonzo,
+    };
+
+    let updates = ledger
+        .get_pparams(tip)
+        .into_diagnostic()
+        .context("retrieving pparams")?;
+
+    let updates: Vec<_> = updates
+        .iter()
+        .map(|PParamsBody(era, cbor)| -> miette::Result<MultiEraUpdate> {
+            MultiEraUpdate::decode_for_era(*era, cbor).into_diagnostic()
+        })
+        .try_collect()?;
+
+    // proposals for the epoch after the tip can still be submitted, so only
+    // the params up to the tip epoch are final
+    let (covered_epoch, _) =
+        crate::common::chain_time(&config.genesis, &genesis)?.slot_to_epoch(tip);
+
+    let history = pparams::pparams_history(&genesis, &updates, args.epoch)
+        .into_diagnostic()
+        .context("folding pparams")?;
+
+    if args.json {
+        let epochs: Vec<_> = history
+            .iter()
+            .map(|(epoch, changes)| {
+                let hardfork = is_hardfork(changes);
+
+                let changes: Vec<_> = changes
+                    .iter()
+                    .map(|x| json!({ "name": x.name, "from": x.from, "to": x.to }))
+                    .collect();
+
+                json!({
+                    "epoch": epoch,
+                    "hardfork": hardfork,
+                    "provisional": *epoch > covered_epoch,
+                    "changes": changes,
+                })
+            })
+            .collect();
+
+        let json = json!({
+            "target_epoch": args.epoch,
+            "ledger_tip": tip,
+            "covered_epoch": covered_epoch,
+            "epochs": epochs,
+        });
+
+        println!("{}", serde_json::to_string_pretty(&json).into_diagnostic()?);
+
+        return Ok(());
+    }
+
+    println!("ledger tip at slot {tip}, proposals are complete up to epoch {covered_epoch}");
+
+    if args.epoch > covered_epoch {
+        println!(
+            "epochs after {covered_epoch} are provisional, proposals for them might not be synced yet"
+        );
+    }
+
+    for (epoch, changes) in history.iter() {
+        println!("---");
+
+        let kind = if is_hardfork(changes) {
+            "hard fork"
+        } else {
+            "enacted proposals"
+        };
+
+        let note = if *epoch > covered_epoch {
+            " (provisional)"
+        } else {
+            ""
+        };
+
+        println!("epoch {epoch}: {kind}{note}");
+
+        for change in changes {
+            println!("  {}: {} -> {}", change.name, change.from, change.to);
+        }
+    }
+
+    Ok(())
+}
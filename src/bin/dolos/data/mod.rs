@@ -1,8 +1,12 @@
 use clap::{Parser, Subcommand};
 
+mod db_stats;
 mod dump_wal;
 mod find_seq;
+mod fold_trace;
 mod summary;
+mod utxo;
+mod verify_utxos;
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
@@ -12,6 +16,14 @@ pub enum Command {
     DumpWal(dump_wal::Args),
     /// finds the WAL seq for a block
     FindSeq(find_seq::Args),
+    /// resolves and prints a utxo from the ledger
+    Utxo(utxo::Args),
+    /// shows size and health stats of the ledger db
+    DbStats(db_stats::Args),
+    /// checks that every stored utxo decodes for its era
+    VerifyUtxos(verify_utxos::Args),
+    /// prints the hard forks and param changes epoch by epoch
+    FoldTrace(fold_trace::Args),
 }
 
 #[derive(Debug, Parser)]
@@ -25,6 +37,10 @@ pub fn run(config: &super::Config, args: &Args) -> miette::Result<()> {
         Command::Summary(x) => summary::run(config, x)?,
         Command::DumpWal(x) => dump_wal::run(config, x)?,
         Command::FindSeq(x) => find_seq::run(config, x)?,
+        Command::Utxo(x) => utxo::run(config, x)?,
+        Command::DbStats(x) => db_stats::run(config, x)?,
+        Command::VerifyUtxos(x) => verify_utxos::run(config, x)?,
+        Command::FoldTrace(x) => fold_trace::run(config, x)?,
     }
 
     Ok(())
@@ -0,0 +1,70 @@
+use dolos::ledger::{EraCbor, TxoRef};
+use miette::{Context, IntoDiagnostic};
+use pallas::ledger::{primitives::babbage::PseudoDatumOption, traverse::MultiEraOutput};
+use serde_json::json;
+use std::str::FromStr;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// reference of the utxo to resolve as <txhash>#<index>
+    txo: String,
+}
+
+fn output_to_json(txo: &TxoRef, cbor: &EraCbor) -> miette::Result<serde_json::Value> {
+    let output = MultiEraOutput::try_from(cbor)
+        .into_diagnostic()
+        .context("decoding utxo")?;
+
+    let address = output
+        .address()
+        .into_diagnostic()
+        .context("decoding address")?;
+
+    let assets: Vec<_> = output
+        .non_ada_assets()
+        .iter()
+        .flat_map(|batch| batch.assets())
+        .map(|asset| {
+            json!({
+                "policy": asset.policy().to_string(),
+                "name": hex::encode(asset.name()),
+                "amount": asset.output_coin(),
+            })
+        })
+        .collect();
+
+    let datum = match output.datum() {
+        Some(PseudoDatumOption::Hash(x)) => json!({ "hash": x.to_string() }),
+        Some(PseudoDatumOption::Data(x)) => json!({ "inline": hex::encode(x.0.raw_cbor()) }),
+        None => serde_json::Value::Null,
+    };
+
+    Ok(json!({
+        "ref": txo.to_string(),
+        "era": format!("{:?}", cbor.0),
+        "address": address.to_string(),
+        "lovelace": output.lovelace_amount(),
+        "assets": assets,
+        "datum": datum,
+    }))
+}
+
+pub fn run(config: &crate::Config, args: &Args) -> miette::Result<()> {
+    crate::common::setup_tracing(&config.logging)?;
+
+    let txo = TxoRef::from_str(&args.txo).into_diagnostic()?;
+
+    let (_, ledger) = crate::common::open_data_stores(config)?;
+
+    let cbor = ledger
+        .get_utxo(txo.clone())
+        .into_diagnostic()
+        .context("resolving utxo")?
+        .ok_or(miette::miette!("utxo {txo} not found in ledger"))?;
+
+    let json = output_to_json(&txo, &cbor)?;
+
+    println!("{}", serde_json::to_string_pretty(&json).into_diagnostic()?);
+
+    Ok(())
+}
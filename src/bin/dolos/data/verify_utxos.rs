@@ -0,0 +1,27 @@
+use miette::{Context, IntoDiagnostic};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {}
+
+pub fn run(config: &crate::Config, _args: &Args) -> miette::Result<()> {
+    crate::common::setup_tracing(&config.logging)?;
+
+    let (_, ledger) = crate::common::open_data_stores(config)?;
+
+    let mismatches = ledger
+        .verify_utxos()
+        .into_diagnostic()
+        .context("verifying utxos")?;
+
+    for mismatch in mismatches.iter() {
+        println!("{}: {}", mismatch.txo, mismatch.reason);
+    }
+
+    if !mismatches.is_empty() {
+        miette::bail!("found {} inconsistent utxos", mismatches.len());
+    }
+
+    println!("all utxos are consistent");
+
+    Ok(())
+}
@@ -20,13 +20,17 @@ pub fn run(config: &crate::Config, _args: &Args, feedback: &Feedback) -> miette:
 
     let (byron, shelley, _) = crate::common::open_genesis_files(&config.genesis)?;
 
+    let network = ledger::network::NetworkParams::from_genesis(&byron, &shelley)
+        .into_diagnostic()
+        .context("resolving network params")?;
+
     let wal = crate::common::open_wal(config).context("opening WAL store")?;
 
     let light = dolos::state::redb::LedgerStore::in_memory_v2_light()
         .into_diagnostic()
         .context("creating in-memory state store")?;
 
-    let mut light = dolos::state::LedgerStore::Redb(light);
+    let mut light = dolos::state::LedgerStore::from(light);
 
     if light
         .is_empty()
@@ -35,7 +39,9 @@ pub fn run(config: &crate::Config, _args: &Args, feedback: &Feedback) -> miette:
     {
         debug!("importing genesis");
 
-        let delta = dolos::ledger::compute_origin_delta(&byron);
+        let delta = dolos::ledger::compute_genesis_delta(&byron, &shelley)
+            .into_diagnostic()
+            .context("computing genesis utxos")?;
 
         light
             .apply(&[delta])
@@ -81,7 +87,7 @@ pub fn run(config: &crate::Config, _args: &Args, feedback: &Feedback) -> miette:
             .into_diagnostic()
             .context("decoding blocks")?;
 
-        dolos::state::apply_block_batch(&blocks, &mut light, &byron, &shelley)
+        dolos::state::apply_block_batch(&blocks, &mut light, &network, &config.sync.delta_limits)
             .into_diagnostic()
             .context("importing blocks to ledger store")?;
 
@@ -94,7 +100,7 @@ pub fn run(config: &crate::Config, _args: &Args, feedback: &Feedback) -> miette:
         .into_diagnostic()
         .context("opening ledger db")?;
 
-    let disk = dolos::state::LedgerStore::Redb(disk);
+    let disk = dolos::state::LedgerStore::from(disk);
 
     let pb = feedback.indeterminate_progress_bar();
     pb.set_message("copying memory ledger into disc");
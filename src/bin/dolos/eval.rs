@@ -95,7 +95,9 @@ pub fn run(config: &super::Config, args: &Args) -> miette::Result<()> {
         },
         &updates,
         args.epoch,
-    );
+    )
+    .into_diagnostic()
+    .context("folding pparams")?;
 
     let context = ValidationContext {
         block_slot: args.block_slot,
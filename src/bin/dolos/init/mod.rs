@@ -172,6 +172,8 @@ impl ConfigEditor {
                 self.0.serve.grpc = dolos::serve::grpc::Config {
                     listen_address: "[::]:50051".into(),
                     tls_client_ca_root: None,
+                    query_filter: None,
+                    max_response_bytes: None,
                 }
                 .into();
             } else {
@@ -63,9 +63,14 @@ struct Cli {
     config: Option<std::path::PathBuf>,
 }
 
+/// Storage root used when the config doesn't define one
+const DEFAULT_STORAGE_PATH: &str = "data";
+
 #[derive(Serialize, Deserialize)]
 pub struct StorageConfig {
-    path: std::path::PathBuf,
+    /// Root directory of the stores, defaults to `data` in the working dir
+    #[serde(default)]
+    path: Option<std::path::PathBuf>,
 
     /// Size (in Mb) of memory allocated for WAL caching
     wal_cache: Option<usize>,
@@ -75,15 +80,60 @@ pub struct StorageConfig {
 
     #[allow(dead_code)]
     wal_size: Option<u64>,
+
+    /// Index utxos by datum hash to allow datum lookups
+    #[serde(default)]
+    datum_index: bool,
+
+    /// Index utxos by reference script hash to allow script lookups
+    #[serde(default)]
+    script_ref_index: bool,
+
+    /// Index utxos by the slot that produced them to allow range lookups
+    #[serde(default)]
+    slot_index: bool,
+
+    /// Max number of filtered utxo query results to memoize between blocks
+    query_cache: Option<usize>,
+
+    /// Max number of decoded utxos kept for the balance queries
+    decode_cache: Option<usize>,
+
+    /// Max number of slots compacted per write transaction when finalizing
+    finalize_batch: Option<usize>,
+
+    /// Keeps the immutable portion of the ledger in a separate db at this path
+    #[serde(default)]
+    immutable_path: Option<std::path::PathBuf>,
+
+    /// Append every ledger delta to a log before committing it
+    #[serde(default)]
+    delta_log: bool,
+}
+
+impl StorageConfig {
+    fn path(&self) -> &std::path::Path {
+        self.path
+            .as_deref()
+            .unwrap_or(std::path::Path::new(DEFAULT_STORAGE_PATH))
+    }
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
-            path: PathBuf::from("data"),
+            path: Some(PathBuf::from(DEFAULT_STORAGE_PATH)),
             wal_cache: None,
             ledger_cache: None,
             wal_size: None,
+            datum_index: false,
+            script_ref_index: false,
+            slot_index: false,
+            query_cache: None,
+            decode_cache: None,
+            finalize_batch: None,
+            immutable_path: None,
+            delta_log: false,
         }
     }
 }
@@ -95,6 +145,9 @@ pub struct GenesisConfig {
     alonzo_path: PathBuf,
     // TODO: add hash of genesis for runtime verification
     // hash: String,
+    /// slot timing that takes precedence over the genesis values
+    #[serde(default)]
+    time_override: Option<dolos::ledger::time::TimeOverride>,
 }
 
 impl Default for GenesisConfig {
@@ -103,6 +156,7 @@ impl Default for GenesisConfig {
             byron_path: PathBuf::from("byron.json"),
             shelley_path: PathBuf::from("shelley.json"),
             alonzo_path: PathBuf::from("alonzo.json"),
+            time_override: None,
         }
     }
 }
@@ -1,5 +1,5 @@
 use log::warn;
-use miette::Context;
+use miette::{Context, IntoDiagnostic};
 
 #[derive(Debug, clap::Args)]
 pub struct Args {}
@@ -13,17 +13,32 @@ pub async fn run(config: super::Config, _args: &Args) -> miette::Result<()> {
     let mempool = dolos::mempool::Mempool::new();
     let exit = crate::common::hook_exit_token();
 
+    let chain_time = crate::common::chain_time(
+        &config.genesis,
+        &dolos::ledger::pparams::Genesis {
+            byron: &byron,
+            shelley: &shelley,
+            alonzo: &alonzo,
+        },
+    )?;
+
     dolos::serve::serve(
         config.serve,
         (alonzo, byron, shelley),
+        chain_time,
         wal,
-        ledger,
+        ledger.clone(),
         mempool,
         exit,
     )
     .await
     .context("serving clients")?;
 
+    ledger
+        .flush()
+        .into_diagnostic()
+        .context("flushing ledger")?;
+
     warn!("shutdown complete");
 
     Ok(())
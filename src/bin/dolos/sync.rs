@@ -1,11 +1,117 @@
-use miette::{Context, IntoDiagnostic};
+use dolos::{
+    state,
+    wal::{self, ReadUtils as _, WalReader as _, WalWriter as _},
+};
+use itertools::Itertools as _;
+use miette::{bail, Context, IntoDiagnostic};
+use pallas::network::{facades::PeerClient, miniprotocols::Point};
+use std::path::{Path, PathBuf};
+use tracing::info;
 
 #[derive(Debug, clap::Args)]
-pub struct Args {}
+pub struct Args {
+    /// import the wal and ledger of a dolos data directory before syncing
+    #[arg(long)]
+    from_snapshot: Option<PathBuf>,
+}
+
+/// Checks that the upstream node knows about the point
+async fn check_upstream_intersect(
+    upstream: &dolos::model::UpstreamConfig,
+    point: Point,
+) -> miette::Result<()> {
+    let mut peer = PeerClient::connect(&upstream.peer_address, upstream.network_magic)
+        .await
+        .into_diagnostic()
+        .context("connecting to upstream")?;
+
+    let (found, _) = peer
+        .chainsync()
+        .find_intersect(vec![point.clone()])
+        .await
+        .into_diagnostic()
+        .context("finding intersect")?;
+
+    if found.is_none() {
+        bail!("snapshot point {point:?} is not part of the upstream chain");
+    }
+
+    Ok(())
+}
+
+fn import_snapshot(config: &super::Config, path: &Path) -> miette::Result<()> {
+    let source_wal = wal::redb::WalStore::open(path.join("wal"), None)
+        .into_diagnostic()
+        .context("opening snapshot wal")?;
+
+    let source_ledger: state::LedgerStore =
+        state::redb::LedgerStore::open(path.join("ledger"), None)
+            .into_diagnostic()
+            .context("opening snapshot ledger")?
+            .into();
+
+    let cursor = source_ledger
+        .cursor()
+        .into_diagnostic()
+        .context("reading snapshot ledger cursor")?
+        .ok_or(miette::miette!("snapshot ledger is empty"))?;
+
+    let (_, tip) = source_wal
+        .find_tip()
+        .into_diagnostic()
+        .context("reading snapshot wal tip")?
+        .ok_or(miette::miette!("snapshot wal is empty"))?;
+
+    let point = Point::from(cursor);
+
+    if tip != wal::ChainPoint::from(point.clone()) {
+        bail!("snapshot ledger cursor doesn't match the wal tip");
+    }
+
+    info!(?point, "checking snapshot point against upstream");
 
-pub fn run(config: &super::Config, _args: &Args) -> miette::Result<()> {
+    tokio::runtime::Runtime::new()
+        .into_diagnostic()?
+        .block_on(check_upstream_intersect(&config.upstream, point))?;
+
+    let (mut wal, ledger) = crate::common::open_data_stores(config)?;
+
+    let is_empty = wal.is_empty().into_diagnostic()? && ledger.is_empty().into_diagnostic()?;
+
+    if !is_empty {
+        bail!("can't import a snapshot with data already available");
+    }
+
+    source_ledger
+        .copy(&ledger)
+        .into_diagnostic()
+        .context("copying snapshot ledger")?;
+
+    // the target wal already starts with its own origin mark
+    let entries = source_wal
+        .crawl_from(None)
+        .into_diagnostic()
+        .context("crawling snapshot wal")?
+        .skip_processed(Some(0));
+
+    for chunk in entries.chunks(1000).into_iter() {
+        wal.append_entries(chunk.map(|(_, x)| x))
+            .into_diagnostic()
+            .context("copying snapshot wal")?;
+    }
+
+    info!(?tip, "snapshot imported");
+
+    Ok(())
+}
+
+pub fn run(config: &super::Config, args: &Args) -> miette::Result<()> {
     crate::common::setup_tracing(&config.logging)?;
 
+    if let Some(path) = &args.from_snapshot {
+        import_snapshot(config, path).context("importing snapshot")?;
+    }
+
     let (wal, ledger) = crate::common::open_data_stores(config)?;
 
     let mempool = dolos::mempool::Mempool::new();
@@ -16,7 +122,7 @@ pub fn run(config: &super::Config, _args: &Args) -> miette::Result<()> {
         &config.sync,
         &config.upstream,
         wal,
-        ledger,
+        ledger.clone(),
         byron,
         shelley,
         mempool,
@@ -27,5 +133,10 @@ pub fn run(config: &super::Config, _args: &Args) -> miette::Result<()> {
 
     gasket::daemon::Daemon::new(sync).block();
 
+    ledger
+        .flush()
+        .into_diagnostic()
+        .context("flushing ledger")?;
+
     Ok(())
 }
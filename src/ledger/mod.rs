@@ -1,21 +1,44 @@
 use pallas::ledger::configs::{byron, shelley};
 use pallas::ledger::traverse::{Era, MultiEraBlock};
+use pallas::network::miniprotocols::Point;
 use pallas::{crypto::hash::Hash, ledger::traverse::MultiEraOutput};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
 use thiserror::Error;
 
+pub mod network;
 pub mod pparams;
+pub mod time;
+
+#[cfg(test)]
+pub mod testing;
 //pub mod validate;
 
 pub type TxHash = Hash<32>;
 pub type TxoIdx = u32;
 pub type BlockSlot = u64;
 pub type BlockHash = Hash<32>;
+pub type BlockHeight = u64;
 pub type TxOrder = usize;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub struct EraCbor(pub Era, pub Vec<u8>);
+/// Serializes an era as its numeric tag, the same one used by the stores
+mod era_tag {
+    use pallas::ledger::traverse::Era;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(era: &Era, serializer: S) -> Result<S::Ok, S::Error> {
+        u16::from(*era).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Era, D::Error> {
+        let tag = u16::deserialize(deserializer)?;
+        Era::try_from(tag).map_err(|_| D::Error::custom(format!("invalid era tag {tag}")))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct EraCbor(#[serde(with = "era_tag")] pub Era, pub Vec<u8>);
 
 impl From<(Era, Vec<u8>)> for EraCbor {
     fn from(value: (Era, Vec<u8>)) -> Self {
@@ -45,7 +68,7 @@ impl<'a> TryFrom<&'a EraCbor> for MultiEraOutput<'a> {
 
 pub type UtxoBody<'a> = MultiEraOutput<'a>;
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub struct TxoRef(pub TxHash, pub TxoIdx);
 
 impl From<(TxHash, TxoIdx)> for TxoRef {
@@ -60,20 +83,249 @@ impl From<TxoRef> for (TxHash, TxoIdx) {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Error)]
+#[error("invalid utxo reference {0:?}, expected <txhash>#<index>")]
+pub struct InvalidTxoRef(pub String);
+
+impl FromStr for TxoRef {
+    type Err = InvalidTxoRef;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidTxoRef(s.to_owned());
+
+        let (hash, idx) = s.split_once('#').ok_or_else(invalid)?;
+        let hash = TxHash::from_str(hash).map_err(|_| invalid())?;
+        let idx = TxoIdx::from_str(idx).map_err(|_| invalid())?;
+
+        Ok(TxoRef(hash, idx))
+    }
+}
+
+impl std::fmt::Display for TxoRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{}", self.0, self.1)
+    }
+}
+
+/// A point in the chain, ordered by slot and then by hash
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Hash, Clone, Serialize, Deserialize)]
 pub struct ChainPoint(pub BlockSlot, pub BlockHash);
 
-#[derive(Debug)]
-pub struct PParamsBody(pub Era, pub Vec<u8>);
+impl ChainPoint {
+    /// A sentinel representing the point before any block
+    ///
+    /// There's no real block with an all-zero hash, which makes it safe to use
+    /// slot 0 for the sentinel even in chains with a block at slot 0.
+    pub fn origin() -> Self {
+        ChainPoint(0, Hash::new([0; 32]))
+    }
+
+    pub fn is_origin(&self) -> bool {
+        *self == Self::origin()
+    }
+}
+
+impl From<Point> for ChainPoint {
+    fn from(value: Point) -> Self {
+        match value {
+            Point::Origin => ChainPoint::origin(),
+            Point::Specific(slot, hash) => ChainPoint(slot, hash.as_slice().into()),
+        }
+    }
+}
+
+impl From<ChainPoint> for Point {
+    fn from(value: ChainPoint) -> Self {
+        if value.is_origin() {
+            return Point::Origin;
+        }
+
+        Point::Specific(value.0, value.1.to_vec())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PParamsBody(#[serde(with = "era_tag")] pub Era, pub Vec<u8>);
+
+/// Returns the raw CBOR of the inline datum of an utxo, if any
+pub fn inline_datum(
+    utxo: &EraCbor,
+) -> Result<Option<Vec<u8>>, pallas::codec::minicbor::decode::Error> {
+    use pallas::ledger::primitives::babbage::PseudoDatumOption;
+
+    let output = MultiEraOutput::try_from(utxo)?;
+
+    let datum = match output.datum() {
+        Some(PseudoDatumOption::Data(x)) => Some(x.0.raw_cbor().to_vec()),
+        _ => None,
+    };
+
+    Ok(datum)
+}
+
+/// Returns the hash of the reference script carried by an utxo, if any
+///
+/// Native scripts are hashed over their CBOR and Plutus scripts over their
+/// flat bytes, in both cases prefixed with the language tag, which matches the
+/// hash used by txs to refer to the script.
+pub fn reference_script_hash(
+    utxo: &EraCbor,
+) -> Result<Option<Hash<28>>, pallas::codec::minicbor::decode::Error> {
+    use pallas::codec::minicbor::{data::Type, Decoder};
+
+    let mut d = Decoder::new(&utxo.1);
+
+    // legacy outputs are encoded as arrays and can't carry a script ref
+    let mut remaining = match d.datatype()? {
+        Type::Map | Type::MapIndef => d.map()?,
+        _ => return Ok(None),
+    };
+
+    loop {
+        match remaining {
+            Some(0) => return Ok(None),
+            Some(x) => remaining = Some(x - 1),
+            None if d.datatype()? == Type::Break => return Ok(None),
+            None => (),
+        }
+
+        if d.u8()? != 3 {
+            d.skip()?;
+            continue;
+        }
+
+        // script_ref = #6.24(bytes .cbor [type, script])
+        d.tag()?;
+        let script = d.bytes()?;
+
+        let mut d = Decoder::new(script);
+        d.array()?;
+        let tag = d.u8()?;
+
+        let body = match tag {
+            0 => {
+                let start = d.position();
+                d.skip()?;
+                &script[start..d.position()]
+            }
+            _ => d.bytes()?,
+        };
+
+        return Ok(Some(pallas::crypto::hash::Hasher::<224>::hash_tagged(
+            body, tag,
+        )));
+    }
+}
+
+/// Utxos returned by queries, iterated in ascending `TxoRef` order
+pub type UtxoMap = BTreeMap<TxoRef, EraCbor>;
+
+/// Utxo references returned by queries, iterated in ascending `TxoRef` order
+pub type UtxoSet = BTreeSet<TxoRef>;
+
+#[derive(Debug, Error)]
+#[error("can't decode output of utxo {txo}")]
+pub struct UtxoDecodeError {
+    pub txo: TxoRef,
+    #[source]
+    pub source: pallas::codec::minicbor::decode::Error,
+}
+
+/// A utxo with its stored body decoded into an output of its era
+///
+/// The output borrows from the `EraCbor` it was decoded from, it gives access
+/// to address, value, datum, etc. without having to decode the body by hand.
+pub struct ResolvedUtxo<'a> {
+    pub txo: &'a TxoRef,
+    pub output: MultiEraOutput<'a>,
+}
+
+impl<'a> ResolvedUtxo<'a> {
+    pub fn decode(txo: &'a TxoRef, body: &'a EraCbor) -> Result<Self, UtxoDecodeError> {
+        let output = MultiEraOutput::try_from(body).map_err(|source| UtxoDecodeError {
+            txo: txo.clone(),
+            source,
+        })?;
 
-pub type UtxoMap = HashMap<TxoRef, EraCbor>;
+        Ok(Self { txo, output })
+    }
+}
 
-pub type UtxoSet = HashSet<TxoRef>;
+/// Decodes every utxo of the map, failing on the first undecodable body
+pub fn resolve_utxos(utxos: &UtxoMap) -> Result<Vec<ResolvedUtxo<'_>>, UtxoDecodeError> {
+    utxos
+        .iter()
+        .map(|(txo, body)| ResolvedUtxo::decode(txo, body))
+        .collect()
+}
+
+/// The utxos referenced by a tx, grouped by the role of the input
+///
+/// Inputs that couldn't be resolved are absent from their group, callers
+/// decide whether that's an error (eg: a missing reference input).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ResolvedInputs {
+    pub spend: UtxoMap,
+    pub collateral: UtxoMap,
+    pub reference: UtxoMap,
+}
+
+/// Lovelace and native assets held by a set of utxos
+///
+/// Assets are keyed by policy id and asset name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Balance {
+    pub lovelace: u128,
+    pub assets: HashMap<(Hash<28>, Vec<u8>), u128>,
+}
 
 #[derive(Debug, Error)]
 pub enum BrokenInvariant {
     #[error("missing utxo {0:?}")]
     MissingUtxo(TxoRef),
+
+    #[error("delta for block {0:?} exceeds size limits ({1} produced, {2} consumed)")]
+    DeltaTooLarge(Option<ChainPoint>, usize, usize),
+
+    #[error("utxo {0} is both produced and consumed by the same delta")]
+    ProducedAndConsumed(TxoRef),
+
+    #[error("utxo {0} is already stored with a different body")]
+    ConflictingUtxo(TxoRef),
+
+    #[error("block {0:?} consumes utxo {1}, which isn't in the ledger")]
+    UnresolvedInput(Option<ChainPoint>, TxoRef),
+
+    #[error("block {0:?} consumes utxo {1} with a body that differs from the stored one")]
+    MismatchedInput(Option<ChainPoint>, TxoRef),
+}
+
+/// Upper bounds for the amount of data a single delta can carry
+///
+/// A block can't legitimately produce or consume more than a few thousand
+/// outputs (block body size is bounded by protocol params), so these limits
+/// act as a guard against crafted or corrupt blocks that would otherwise make
+/// us allocate and write an unbounded amount of data in a single write
+/// transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeltaLimits {
+    pub max_produced: usize,
+    pub max_consumed: usize,
+    /// Checks the consumed utxos against the store before applying, see
+    /// `state::LedgerStore::apply_with_limits`. Off by default, it costs an
+    /// extra read of every input.
+    pub check_inputs: bool,
+}
+
+impl Default for DeltaLimits {
+    fn default() -> Self {
+        Self {
+            max_produced: 100_000,
+            max_consumed: 100_000,
+            check_inputs: false,
+        }
+    }
 }
 
 /// A slice of the ledger relevant for a specific task
@@ -86,7 +338,7 @@ pub struct LedgerSlice {
     pub resolved_inputs: HashMap<TxoRef, EraCbor>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct LedgerDelta {
     pub new_position: Option<ChainPoint>,
     pub undone_position: Option<ChainPoint>,
@@ -95,6 +347,124 @@ pub struct LedgerDelta {
     pub recovered_stxi: HashMap<TxoRef, EraCbor>,
     pub undone_utxo: HashMap<TxoRef, EraCbor>,
     pub new_pparams: Vec<PParamsBody>,
+    /// Height of the block at `new_position`, if known
+    pub new_height: Option<BlockHeight>,
+}
+
+/// Layout of `LedgerDelta` before it carried the block height, found in
+/// stores and logs written by older versions
+#[derive(Deserialize)]
+struct LegacyLedgerDelta {
+    new_position: Option<ChainPoint>,
+    undone_position: Option<ChainPoint>,
+    produced_utxo: HashMap<TxoRef, EraCbor>,
+    consumed_utxo: HashMap<TxoRef, EraCbor>,
+    recovered_stxi: HashMap<TxoRef, EraCbor>,
+    undone_utxo: HashMap<TxoRef, EraCbor>,
+    new_pparams: Vec<PParamsBody>,
+}
+
+impl From<LegacyLedgerDelta> for LedgerDelta {
+    fn from(value: LegacyLedgerDelta) -> Self {
+        Self {
+            new_position: value.new_position,
+            undone_position: value.undone_position,
+            produced_utxo: value.produced_utxo,
+            consumed_utxo: value.consumed_utxo,
+            recovered_stxi: value.recovered_stxi,
+            undone_utxo: value.undone_utxo,
+            new_pparams: value.new_pparams,
+            new_height: None,
+        }
+    }
+}
+
+impl LedgerDelta {
+    /// Decodes a bincode encoded delta
+    ///
+    /// Deltas encoded before the block height was recorded lack the trailing
+    /// field, those are decoded with an unknown height.
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes).or_else(|err| {
+            bincode::deserialize::<LegacyLedgerDelta>(bytes)
+                .map(Into::into)
+                .map_err(|_| err)
+        })
+    }
+
+    /// Checks that the amount of utxos carried by the delta is within limits
+    pub fn check_limits(&self, limits: &DeltaLimits) -> Result<(), BrokenInvariant> {
+        let produced = self.produced_utxo.len() + self.recovered_stxi.len();
+        let consumed = self.consumed_utxo.len() + self.undone_utxo.len();
+
+        if produced > limits.max_produced || consumed > limits.max_consumed {
+            return Err(BrokenInvariant::DeltaTooLarge(
+                self.new_position.clone(),
+                produced,
+                consumed,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that no utxo is both added and removed by the delta
+    ///
+    /// Backends apply each side of the delta independently, so an overlap
+    /// would leave the utxo in a state that depends on the order of the
+    /// writes. Outputs created and spent within the same block must be netted
+    /// out when computing the delta instead.
+    pub fn check_overlap(&self) -> Result<(), BrokenInvariant> {
+        let overlap = self
+            .produced_utxo
+            .keys()
+            .find(|x| self.consumed_utxo.contains_key(x))
+            .or_else(|| {
+                self.recovered_stxi
+                    .keys()
+                    .find(|x| self.undone_utxo.contains_key(x))
+            });
+
+        match overlap {
+            Some(txo) => Err(BrokenInvariant::ProducedAndConsumed(txo.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Upper bound for the size of an encoded block
+///
+/// Block bodies are bounded by the `max_block_body_size` param, which is in
+/// the tens of KB on every known network. A buffer this large can only come
+/// from corrupt data.
+pub const MAX_BLOCK_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum BlockDecodeError {
+    #[error("block of {0} bytes exceeds the 4MB limit, data is likely corrupt")]
+    TooLarge(usize),
+
+    #[error("can't decode block of {len} bytes, data might be truncated or corrupt")]
+    Malformed {
+        len: usize,
+        #[source]
+        source: pallas::ledger::traverse::Error,
+    },
+}
+
+/// Decodes a block coming from a peer or from disk
+///
+/// Unlike a bare `MultiEraBlock::decode`, buffers over `MAX_BLOCK_BYTES` are
+/// rejected before decoding and the error carries the length of the input.
+pub fn decode_block(bytes: &[u8]) -> Result<MultiEraBlock<'_>, BlockDecodeError> {
+    if bytes.len() > MAX_BLOCK_BYTES {
+        return Err(BlockDecodeError::TooLarge(bytes.len()));
+    }
+
+    MultiEraBlock::decode(bytes).map_err(|source| BlockDecodeError::Malformed {
+        len: bytes.len(),
+        source,
+    })
 }
 
 /// Computes the ledger delta of applying a particular block.
@@ -117,6 +487,7 @@ pub fn compute_delta(
 ) -> Result<LedgerDelta, BrokenInvariant> {
     let mut delta = LedgerDelta {
         new_position: Some(ChainPoint(block.slot(), block.hash())),
+        new_height: Some(block.number()),
         ..Default::default()
     };
 
@@ -147,6 +518,11 @@ pub fn compute_delta(
         }
     }
 
+    // outputs spent by a later tx of the same block never reach the ledger
+    delta
+        .consumed_utxo
+        .retain(|txo, _| delta.produced_utxo.remove(txo).is_none());
+
     // check block-level updates (because of f#!@#@ byron)
     if let Some(update) = block.update() {
         delta
@@ -188,6 +564,11 @@ pub fn compute_undo_delta(
         }
     }
 
+    // outputs spent by a later tx of the same block were never in the ledger
+    delta
+        .recovered_stxi
+        .retain(|txo, _| delta.undone_utxo.remove(txo).is_none());
+
     Ok(delta)
 }
 
@@ -213,21 +594,60 @@ pub fn compute_origin_delta(byron: &pallas::ledger::configs::byron::GenesisFile)
     delta
 }
 
-/// Computes the latest immutable slot
+/// Computes the utxos funded by the `initialFunds` of the Shelley genesis
 ///
-/// Takes the latest known tip, reads the relevant genesis config values and
-/// uses the security window guarantee formula from consensus to calculate the
-/// latest slot that can be considered immutable. This is used mainly to define
-/// which slots can be finalized in the ledger store (aka: compaction).
-pub fn lastest_immutable_slot(
-    tip: BlockSlot,
+/// As defined by the ledger spec, each entry becomes the first output of a
+/// pseudo tx whose id is the hash of the address bytes. Outputs are encoded as
+/// Shelley outputs holding only lovelace.
+pub fn shelley_genesis_utxos(
+    shelley: &shelley::GenesisFile,
+) -> Result<UtxoMap, pallas::ledger::addresses::Error> {
+    use pallas::codec::minicbor::Encoder;
+
+    let mut out = UtxoMap::new();
+
+    for (address, amount) in shelley.initial_funds.iter().flatten() {
+        let address = pallas::ledger::addresses::Address::from_hex(address)?.to_vec();
+
+        let tx = pallas::crypto::hash::Hasher::<256>::hash(&address);
+
+        let mut body = Encoder::new(Vec::new());
+        body.array(2).unwrap();
+        body.bytes(&address).unwrap();
+        body.u64(*amount).unwrap();
+
+        out.insert(TxoRef(tx, 0), EraCbor(Era::Shelley, body.into_writer()));
+    }
+
+    Ok(out)
+}
+
+/// Computes the delta that seeds the ledger with every genesis utxo
+///
+/// Includes the Byron genesis distribution (see [`compute_origin_delta`]) and
+/// the Shelley `initialFunds`, which are only used by networks that start
+/// directly on Shelley.
+pub fn compute_genesis_delta(
     byron: &byron::GenesisFile,
     shelley: &shelley::GenesisFile,
-) -> BlockSlot {
-    let security_window =
-        (3.0 * byron.protocol_consts.k as f32) / (shelley.active_slots_coeff.unwrap());
+) -> Result<LedgerDelta, pallas::ledger::addresses::Error> {
+    let mut delta = compute_origin_delta(byron);
+
+    delta.produced_utxo.extend(shelley_genesis_utxos(shelley)?);
 
-    tip.saturating_sub(security_window.ceil() as u64)
+    Ok(delta)
+}
+
+/// Resolves the security parameter (k) of the network from genesis
+///
+/// The Shelley genesis `securityParam` is the source of truth, Byron's
+/// protocol consts are only used as fallback for genesis files that don't
+/// define it.
+pub fn security_param(byron: &byron::GenesisFile, shelley: &shelley::GenesisFile) -> u64 {
+    shelley
+        .security_param
+        .map(|k| k as u64)
+        .unwrap_or(byron.protocol_consts.k as u64)
 }
 
 #[cfg(test)]
@@ -296,6 +716,22 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_security_param_from_genesis() {
+        let root = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+
+        for (network, expected) in [("sync-mainnet", 2160), ("sync-preview", 432)] {
+            let path = root.join("examples").join(network);
+
+            let byron =
+                pallas::ledger::configs::byron::from_file(&path.join("byron.json")).unwrap();
+            let shelley =
+                pallas::ledger::configs::shelley::from_file(&path.join("shelley.json")).unwrap();
+
+            assert_eq!(security_param(&byron, &shelley), expected);
+        }
+    }
+
     #[test]
     fn test_preview_genesis_utxos() {
         let path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
@@ -349,6 +785,181 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_delta_records_block_height() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let context = fake_slice_for_block(&block);
+
+        let delta = super::compute_delta(&block, context).unwrap();
+
+        assert_eq!(
+            delta.new_position,
+            Some(ChainPoint(block.slot(), block.hash()))
+        );
+        assert_eq!(delta.new_height, Some(block.number()));
+
+        let decoded = LedgerDelta::from_bincode(&bincode::serialize(&delta).unwrap()).unwrap();
+        assert_eq!(decoded.new_height, Some(block.number()));
+
+        // deltas written before the height was recorded
+        let legacy = bincode::serialize(&(
+            &delta.new_position,
+            &delta.undone_position,
+            &delta.produced_utxo,
+            &delta.consumed_utxo,
+            &delta.recovered_stxi,
+            &delta.undone_utxo,
+            &delta.new_pparams,
+        ))
+        .unwrap();
+
+        let decoded = LedgerDelta::from_bincode(&legacy).unwrap();
+        assert_eq!(decoded.new_position, delta.new_position);
+        assert_eq!(decoded.produced_utxo, delta.produced_utxo);
+        assert_eq!(decoded.new_height, None);
+    }
+
+    #[test]
+    fn test_decode_truncated_block() {
+        let cbor = load_test_block("alonzo27.block");
+        assert!(decode_block(&cbor).is_ok());
+
+        let truncated = &cbor[..cbor.len() / 2];
+
+        match decode_block(truncated) {
+            Err(BlockDecodeError::Malformed { len, .. }) => assert_eq!(len, truncated.len()),
+            _ => panic!("truncated block should fail to decode"),
+        }
+
+        let oversized = vec![0u8; MAX_BLOCK_BYTES + 1];
+
+        assert!(matches!(
+            decode_block(&oversized),
+            Err(BlockDecodeError::TooLarge(len)) if len == MAX_BLOCK_BYTES + 1
+        ));
+    }
+
+    #[test]
+    fn test_epoch_boundary_delta() {
+        // synthetic mainnet EBB for epoch 1, built from the byron cddl
+        let cbor = load_test_block("byron_ebb.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+
+        assert!(matches!(block, MultiEraBlock::EpochBoundary(_)));
+        assert_eq!(block.slot(), 21_600);
+
+        let context = fake_slice_for_block(&block);
+        let delta = super::compute_delta(&block, context).unwrap();
+
+        assert_eq!(
+            delta.new_position,
+            Some(ChainPoint(block.slot(), block.hash()))
+        );
+
+        assert!(delta.produced_utxo.is_empty());
+        assert!(delta.consumed_utxo.is_empty());
+        assert!(delta.new_pparams.is_empty());
+
+        let mut store: crate::state::LedgerStore = crate::state::memory::LedgerStore::new().into();
+        store.apply(&[delta]).unwrap();
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(block.slot(), block.hash()))
+        );
+
+        // the first block of the epoch shares the slot of the EBB
+        let next = testing::fake_produce_delta(block.slot(), vec![]);
+        let next_position = next.new_position.clone();
+        store.apply(&[next]).unwrap();
+
+        assert_eq!(store.cursor().unwrap(), next_position);
+    }
+
+    #[test]
+    fn test_point_conversion_roundtrip() {
+        let origin = ChainPoint::from(Point::Origin);
+        assert!(origin.is_origin());
+        assert_eq!(Point::from(origin), Point::Origin);
+
+        let hash = Hash::new([7; 32]);
+        let specific = Point::Specific(42, hash.to_vec());
+
+        let point = ChainPoint::from(specific.clone());
+        assert_eq!(point, ChainPoint(42, hash));
+        assert_eq!(Point::from(point), specific);
+
+        // a real block at slot 0 must not be confused with origin
+        let genesis_block = ChainPoint(0, hash);
+        assert_eq!(
+            Point::from(genesis_block.clone()),
+            Point::Specific(0, hash.to_vec())
+        );
+        assert_eq!(
+            ChainPoint::from(Point::Specific(0, hash.to_vec())),
+            genesis_block
+        );
+    }
+
+    #[test]
+    fn test_chain_point_ordering() {
+        let origin = ChainPoint::origin();
+        assert!(origin.is_origin());
+
+        let a = ChainPoint(0, Hash::new([1; 32]));
+        let b = ChainPoint(10, Hash::new([0; 32]));
+        let c = ChainPoint(10, Hash::new([2; 32]));
+        let d = ChainPoint(20, Hash::new([1; 32]));
+
+        assert!(!a.is_origin());
+        assert!(origin < a);
+        assert!(a < b);
+        assert!(b < c);
+        assert!(c < d);
+
+        let mut points = vec![d.clone(), b.clone(), origin.clone(), c.clone(), a.clone()];
+        points.sort();
+        assert_eq!(points, vec![origin, a, b, c, d]);
+    }
+
+    #[test]
+    fn test_txo_ref_from_str() {
+        let hash = "0ae3da29711600e94a33fb7441d2e76876a9a1e98b5ebdefbf2e3bc535617616";
+
+        let txo = TxoRef::from_str(&format!("{hash}#3")).unwrap();
+        assert_eq!(txo, TxoRef(Hash::from_str(hash).unwrap(), 3));
+        assert_eq!(txo.to_string(), format!("{hash}#3"));
+
+        assert!(TxoRef::from_str(hash).is_err());
+        assert!(TxoRef::from_str(&format!("{hash}#x")).is_err());
+        assert!(TxoRef::from_str("abcd#0").is_err());
+    }
+
+    #[test]
+    fn test_delta_limits() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let context = fake_slice_for_block(&block);
+
+        let delta = super::compute_delta(&block, context).unwrap();
+
+        assert!(delta.check_limits(&DeltaLimits::default()).is_ok());
+
+        let tight = DeltaLimits {
+            max_produced: delta.produced_utxo.len() - 1,
+            ..Default::default()
+        };
+
+        match delta.check_limits(&tight) {
+            Err(BrokenInvariant::DeltaTooLarge(position, produced, _)) => {
+                assert_eq!(position, delta.new_position);
+                assert_eq!(produced, delta.produced_utxo.len());
+            }
+            _ => panic!("expected delta to exceed limits"),
+        }
+    }
+
     #[test]
     fn test_undo_block() {
         // nice block with several txs, it includes chaining edge case
@@ -369,4 +980,46 @@ mod tests {
 
         assert_eq!(apply.new_position, undo.undone_position);
     }
+
+    #[test]
+    fn test_chained_outputs_are_netted() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let context = fake_slice_for_block(&block);
+
+        let apply = super::compute_delta(&block, context.clone()).unwrap();
+        let undo = super::compute_undo_delta(&block, context).unwrap();
+
+        assert!(apply.check_overlap().is_ok());
+        assert!(undo.check_overlap().is_ok());
+
+        // the block chains txs, so some of the outputs must have been netted out
+        let produced: usize = block.txs().iter().map(|tx| tx.produces().len()).sum();
+        assert!(apply.produced_utxo.len() < produced);
+        assert_eq!(apply.produced_utxo.len(), undo.undone_utxo.len());
+    }
+
+    #[test]
+    fn resolved_utxo_accessors() {
+        use super::testing::*;
+
+        let address = fake_enterprise_address(&[7u8; 28]);
+
+        let mut utxos = UtxoMap::new();
+        utxos.insert(fake_txo(1, 0), fake_utxo(&address, 1_500_000));
+
+        let resolved = resolve_utxos(&utxos).unwrap();
+        assert_eq!(resolved.len(), 1);
+
+        let utxo = &resolved[0];
+        assert_eq!(utxo.txo, &fake_txo(1, 0));
+        assert_eq!(utxo.output.address().unwrap().to_vec(), address);
+        assert_eq!(utxo.output.lovelace_amount(), 1_500_000);
+
+        // errors point at the offending utxo
+        utxos.insert(fake_txo(2, 0), EraCbor(Era::Babbage, vec![0xff]));
+
+        let err = resolve_utxos(&utxos).err().unwrap();
+        assert_eq!(err.txo, fake_txo(2, 0));
+    }
 }
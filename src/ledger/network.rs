@@ -0,0 +1,276 @@
+use pallas::ledger::configs::{byron, shelley};
+use thiserror::Error;
+use tracing::warn;
+
+use super::time::{Epoch, UnixTime};
+use super::BlockSlot;
+
+pub const MAINNET_MAGIC: u64 = 764824073;
+pub const PREPROD_MAGIC: u64 = 1;
+pub const PREVIEW_MAGIC: u64 = 2;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NetworkError {
+    #[error("genesis doesn't define {0}")]
+    MissingValue(&'static str),
+
+    #[error("genesis {0} must be greater than zero")]
+    ZeroValue(&'static str),
+
+    #[error("active slots coefficient must be within (0, 1], found {0}")]
+    InvalidActiveSlotsCoeff(f32),
+}
+
+/// Network-wide constants, resolved once from the genesis files
+///
+/// Everything that depends on k, the slot timing or the system start of the
+/// network should read it from here instead of going back to genesis. Byron
+/// values are kept apart since the known networks started on Byron and
+/// convert their early slots with them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkParams {
+    pub network_magic: u64,
+    /// The security parameter (k), max amount of blocks that can be rolled back
+    pub security_param: u64,
+    pub active_slots_coeff: f32,
+    /// Wall-clock time of slot 0
+    pub system_start: UnixTime,
+    pub byron_slot_length: u64,
+    pub byron_epoch_length: u64,
+    pub shelley_slot_length: u64,
+    pub shelley_epoch_length: u64,
+    /// Epoch at which the network transitioned from Byron to Shelley
+    ///
+    /// The hard fork epoch isn't part of the genesis files, it depends on the
+    /// update proposals adopted by the network. Known public networks use
+    /// their historical value, custom networks are assumed to start directly
+    /// on Shelley, which is how devnets are usually configured.
+    pub shelley_start_epoch: Epoch,
+}
+
+impl NetworkParams {
+    pub fn mainnet() -> Self {
+        Self {
+            network_magic: MAINNET_MAGIC,
+            security_param: 2160,
+            active_slots_coeff: 0.05,
+            system_start: 1506203091,
+            byron_slot_length: 20,
+            byron_epoch_length: 21600,
+            shelley_slot_length: 1,
+            shelley_epoch_length: 432000,
+            shelley_start_epoch: 208,
+        }
+    }
+
+    pub fn preprod() -> Self {
+        Self {
+            network_magic: PREPROD_MAGIC,
+            security_param: 2160,
+            active_slots_coeff: 0.05,
+            system_start: 1654041600,
+            byron_slot_length: 20,
+            byron_epoch_length: 21600,
+            shelley_slot_length: 1,
+            shelley_epoch_length: 432000,
+            shelley_start_epoch: 4,
+        }
+    }
+
+    pub fn preview() -> Self {
+        Self {
+            network_magic: PREVIEW_MAGIC,
+            security_param: 432,
+            active_slots_coeff: 0.05,
+            system_start: 1666656000,
+            byron_slot_length: 20,
+            byron_epoch_length: 4320,
+            shelley_slot_length: 1,
+            shelley_epoch_length: 86400,
+            shelley_start_epoch: 0,
+        }
+    }
+
+    /// Returns the constants of a known public network by its magic
+    pub fn known(network_magic: u64) -> Option<Self> {
+        match network_magic {
+            MAINNET_MAGIC => Some(Self::mainnet()),
+            PREPROD_MAGIC => Some(Self::preprod()),
+            PREVIEW_MAGIC => Some(Self::preview()),
+            _ => None,
+        }
+    }
+
+    /// Resolves the constants of the network described by the genesis files
+    ///
+    /// Values missing from genesis are an error instead of falling back to
+    /// mainnet defaults. If the magic belongs to a known network but the
+    /// values don't match its constants, the genesis values are used and a
+    /// warning is logged, the files are probably from a different network.
+    pub fn from_genesis(
+        byron: &byron::GenesisFile,
+        shelley: &shelley::GenesisFile,
+    ) -> Result<Self, NetworkError> {
+        let network_magic = shelley
+            .network_magic
+            .ok_or(NetworkError::MissingValue("networkMagic"))? as u64;
+
+        let active_slots_coeff = shelley
+            .active_slots_coeff
+            .ok_or(NetworkError::MissingValue("activeSlotsCoeff"))?;
+
+        if !(active_slots_coeff > 0.0 && active_slots_coeff <= 1.0) {
+            return Err(NetworkError::InvalidActiveSlotsCoeff(active_slots_coeff));
+        }
+
+        let shelley_slot_length = shelley
+            .slot_length
+            .ok_or(NetworkError::MissingValue("slotLength"))?
+            as u64;
+
+        let shelley_epoch_length = shelley
+            .epoch_length
+            .ok_or(NetworkError::MissingValue("epochLength"))?
+            as u64;
+
+        let byron_k = byron.protocol_consts.k as u64;
+
+        if let Some(shelley_k) = shelley.security_param {
+            if shelley_k as u64 != byron_k {
+                warn!(
+                    shelley_k,
+                    byron_k, "security param diverges between genesis files, using shelley value"
+                );
+            }
+        }
+
+        let params = Self {
+            network_magic,
+            security_param: super::security_param(byron, shelley),
+            active_slots_coeff,
+            system_start: byron.start_time,
+            // byron slot duration is expressed in milliseconds
+            byron_slot_length: byron.block_version_data.slot_duration / 1000,
+            byron_epoch_length: byron_k * 10,
+            shelley_slot_length,
+            shelley_epoch_length,
+            shelley_start_epoch: Self::known(network_magic)
+                .map(|x| x.shelley_start_epoch)
+                .unwrap_or_default(),
+        };
+
+        params.validate()?;
+
+        if let Some(known) = Self::known(network_magic) {
+            if known != params {
+                warn!(
+                    network_magic,
+                    "genesis values don't match the known constants of the network"
+                );
+            }
+        }
+
+        Ok(params)
+    }
+
+    fn validate(&self) -> Result<(), NetworkError> {
+        let lengths = [
+            ("securityParam", self.security_param),
+            ("slotDuration", self.byron_slot_length),
+            ("protocolConsts.k", self.byron_epoch_length),
+            ("slotLength", self.shelley_slot_length),
+            ("epochLength", self.shelley_epoch_length),
+        ];
+
+        match lengths.iter().find(|(_, value)| *value == 0) {
+            Some((name, _)) => Err(NetworkError::ZeroValue(*name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Amount of slots after which a block can be considered immutable
+    ///
+    /// Uses the security window guarantee formula from consensus, 3k/f.
+    pub fn security_window(&self) -> u64 {
+        let window = (3.0 * self.security_param as f32) / self.active_slots_coeff;
+
+        window.ceil() as u64
+    }
+
+    /// Returns the latest slot that can be considered immutable at the tip
+    pub fn latest_immutable_slot(&self, tip: BlockSlot) -> BlockSlot {
+        tip.saturating_sub(self.security_window())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_genesis(network: &str) -> (byron::GenesisFile, shelley::GenesisFile) {
+        let path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("examples")
+            .join(network);
+
+        (
+            byron::from_file(&path.join("byron.json")).unwrap(),
+            shelley::from_file(&path.join("shelley.json")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn known_networks_match_genesis() {
+        for (network, magic) in [
+            ("sync-mainnet", MAINNET_MAGIC),
+            ("sync-preprod", PREPROD_MAGIC),
+            ("sync-preview", PREVIEW_MAGIC),
+        ] {
+            let (byron, shelley) = load_genesis(network);
+
+            let params = NetworkParams::from_genesis(&byron, &shelley).unwrap();
+
+            assert_eq!(Some(params), NetworkParams::known(magic), "{network}");
+        }
+    }
+
+    #[test]
+    fn custom_network_from_genesis() {
+        let (mut byron, mut shelley) = load_genesis("sync-preview");
+
+        byron.start_time = 1_700_000_000;
+        shelley.network_magic = Some(42);
+        shelley.epoch_length = Some(500);
+
+        let params = NetworkParams::from_genesis(&byron, &shelley).unwrap();
+
+        assert_eq!(NetworkParams::known(42), None);
+        assert_eq!(params.system_start, 1_700_000_000);
+        assert_eq!(params.shelley_epoch_length, 500);
+        assert_eq!(params.shelley_start_epoch, 0);
+        assert_eq!(params.security_window(), 25920);
+        assert_eq!(params.latest_immutable_slot(30000), 4080);
+        assert_eq!(params.latest_immutable_slot(100), 0);
+
+        shelley.epoch_length = None;
+
+        assert_eq!(
+            NetworkParams::from_genesis(&byron, &shelley),
+            Err(NetworkError::MissingValue("epochLength"))
+        );
+
+        shelley.epoch_length = Some(0);
+
+        assert_eq!(
+            NetworkParams::from_genesis(&byron, &shelley),
+            Err(NetworkError::ZeroValue("epochLength"))
+        );
+
+        shelley.epoch_length = Some(500);
+        shelley.active_slots_coeff = Some(1.5);
+
+        assert_eq!(
+            NetworkParams::from_genesis(&byron, &shelley),
+            Err(NetworkError::InvalidActiveSlotsCoeff(1.5))
+        );
+    }
+}
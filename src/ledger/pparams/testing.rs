@@ -0,0 +1,158 @@
+use std::sync::OnceLock;
+
+use super::*;
+
+const GENESIS_PATH: &str = "src/ledger/pparams/test_data/mainnet/genesis";
+
+fn load_json<T: serde::de::DeserializeOwned>(name: &str) -> T {
+    let file = std::fs::File::open(format!("{GENESIS_PATH}/{name}")).unwrap();
+    serde_json::from_reader(file).unwrap()
+}
+
+/// Owned genesis files, tests can tweak them before borrowing a [`Genesis`]
+pub struct GenesisFiles {
+    pub byron: byron::GenesisFile,
+    pub shelley: shelley::GenesisFile,
+    pub alonzo: alonzo::GenesisFile,
+}
+
+impl GenesisFiles {
+    /// The mainnet genesis files used as baseline for the fixtures
+    pub fn mainnet() -> Self {
+        Self {
+            byron: load_json("byron_genesis.json"),
+            shelley: load_json("shelley_genesis.json"),
+            alonzo: load_json("alonzo_genesis.json"),
+        }
+    }
+
+    pub fn genesis(&self) -> Genesis<'_> {
+        Genesis {
+            byron: &self.byron,
+            shelley: &self.shelley,
+            alonzo: &self.alonzo,
+        }
+    }
+}
+
+/// The mainnet genesis, loaded once and shared by the tests that don't tweak it
+pub fn mainnet_genesis() -> Genesis<'static> {
+    static FILES: OnceLock<GenesisFiles> = OnceLock::new();
+
+    FILES.get_or_init(GenesisFiles::mainnet).genesis()
+}
+
+/// The mainnet genesis along with the given shelley-style update proposals
+pub fn shelley_fixture(cbors: &[Vec<u8>]) -> (Genesis<'static>, Vec<MultiEraUpdate<'_>>) {
+    let updates = cbors
+        .iter()
+        .map(|x| MultiEraUpdate::decode_for_era(Era::Shelley, x).unwrap())
+        .collect();
+
+    (mainnet_genesis(), updates)
+}
+
+/// The decentralization param as a `(numerator, denominator)` pair
+pub fn decentralization(pparams: &MultiEraProtocolParameters) -> (u64, u64) {
+    match pparams {
+        MultiEraProtocolParameters::Shelley(x) => {
+            let d = &x.decentralization_constant;
+            (d.numerator, d.denominator)
+        }
+        x => panic!("unexpected era {x:?}"),
+    }
+}
+
+macro_rules! set_field {
+    ($pparams:expr, $field:ident, $value:expr) => {
+        match &mut $pparams {
+            MultiEraProtocolParameters::Shelley(x) => x.$field = $value,
+            MultiEraProtocolParameters::Alonzo(x) => x.$field = $value,
+            MultiEraProtocolParameters::Babbage(x) => x.$field = $value,
+            MultiEraProtocolParameters::Conway(x) => x.$field = $value,
+            _ => panic!("{} is not available in this era", stringify!($field)),
+        }
+    };
+}
+
+/// Builds protocol params fixtures for a given era
+///
+/// Each era starts from a valid baseline bootstrapped from the mainnet
+/// genesis files (as if the network had just hard-forked into it), only the
+/// fields of interest need to be overridden.
+pub struct PParamsBuilder(MultiEraProtocolParameters);
+
+impl PParamsBuilder {
+    pub fn byron() -> Self {
+        let byron = GenesisFiles::mainnet().byron;
+        Self(MultiEraProtocolParameters::Byron(bootstrap_byron_pparams(
+            &byron,
+        )))
+    }
+
+    pub fn shelley() -> Self {
+        let files = GenesisFiles::mainnet();
+        let mut pparams = bootstrap_shelley_pparams(&files.shelley);
+        pparams.protocol_version = (2, 0);
+
+        Self(MultiEraProtocolParameters::Shelley(pparams))
+    }
+
+    pub fn alonzo() -> Self {
+        let files = GenesisFiles::mainnet();
+        let mut pparams =
+            bootstrap_alonzo_pparams(bootstrap_shelley_pparams(&files.shelley), &files.alonzo)
+                .unwrap();
+        pparams.protocol_version = (5, 0);
+
+        Self(MultiEraProtocolParameters::Alonzo(pparams))
+    }
+
+    pub fn babbage() -> Self {
+        let MultiEraProtocolParameters::Alonzo(alonzo) = Self::alonzo().0 else {
+            unreachable!()
+        };
+
+        let mut pparams = bootstrap_babbage_pparams(alonzo);
+        pparams.protocol_version = (7, 0);
+
+        Self(MultiEraProtocolParameters::Babbage(pparams))
+    }
+
+    pub fn conway() -> Self {
+        let MultiEraProtocolParameters::Babbage(babbage) = Self::babbage().0 else {
+            unreachable!()
+        };
+
+        let mut pparams = bootstrap_conway_pparams(babbage);
+        pparams.protocol_version = (9, 0);
+
+        Self(MultiEraProtocolParameters::Conway(pparams))
+    }
+
+    pub fn protocol_version(mut self, major: u64, minor: u64) -> Self {
+        set_field!(self.0, protocol_version, (major, minor));
+        self
+    }
+
+    pub fn min_fee(mut self, a: u32, b: u32) -> Self {
+        set_field!(self.0, minfee_a, a);
+        set_field!(self.0, minfee_b, b);
+        self
+    }
+
+    pub fn max_transaction_size(mut self, size: u32) -> Self {
+        set_field!(self.0, max_transaction_size, size);
+        self
+    }
+
+    /// Escape hatch to override any field not covered by the other methods
+    pub fn with(mut self, f: impl FnOnce(&mut MultiEraProtocolParameters)) -> Self {
+        f(&mut self.0);
+        self
+    }
+
+    pub fn build(self) -> MultiEraProtocolParameters {
+        self.0
+    }
+}
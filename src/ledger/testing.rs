@@ -0,0 +1,131 @@
+use pallas::codec::minicbor::Encoder;
+
+use super::*;
+
+pub fn slot_to_hash(slot: u64) -> BlockHash {
+    let mut hasher = pallas::crypto::hash::Hasher::<256>::new();
+    hasher.input(&(slot as i32).to_le_bytes());
+    hasher.finalize()
+}
+
+pub fn fake_txo(seed: u8, idx: TxoIdx) -> TxoRef {
+    TxoRef(Hash::new([seed; 32]), idx)
+}
+
+/// A base address (payment key + stake key) on testnet
+pub fn fake_base_address(payment: &[u8; 28], stake: &[u8; 28]) -> Vec<u8> {
+    let mut out = vec![0b0000_0000];
+    out.extend(payment);
+    out.extend(stake);
+    out
+}
+
+/// An enterprise address (payment key, no stake part) on testnet
+pub fn fake_enterprise_address(payment: &[u8; 28]) -> Vec<u8> {
+    let mut out = vec![0b0110_0000];
+    out.extend(payment);
+    out
+}
+
+/// A Babbage post-alonzo output with the given address and lovelace amount
+pub fn fake_utxo(address: &[u8], lovelace: u64) -> EraCbor {
+    fake_utxo_with_assets(address, lovelace, &[])
+}
+
+/// A Babbage post-alonzo output holding lovelace and native assets
+pub fn fake_utxo_with_assets(
+    address: &[u8],
+    lovelace: u64,
+    assets: &[([u8; 28], &[u8], u64)],
+) -> EraCbor {
+    let mut e = Encoder::new(Vec::new());
+
+    e.map(2).unwrap();
+    e.u8(0).unwrap().bytes(address).unwrap();
+    e.u8(1).unwrap();
+
+    if assets.is_empty() {
+        e.u64(lovelace).unwrap();
+    } else {
+        e.array(2).unwrap().u64(lovelace).unwrap();
+        e.map(assets.len() as u64).unwrap();
+
+        for (policy, name, amount) in assets {
+            e.bytes(policy).unwrap();
+            e.map(1).unwrap();
+            e.bytes(name).unwrap().u64(*amount).unwrap();
+        }
+    }
+
+    EraCbor(Era::Babbage, e.into_writer())
+}
+
+/// A Babbage post-alonzo output holding an inline datum
+pub fn fake_utxo_with_inline_datum(address: &[u8], lovelace: u64, datum: &[u8]) -> EraCbor {
+    let mut e = Encoder::new(Vec::new());
+
+    e.map(3).unwrap();
+    e.u8(0).unwrap().bytes(address).unwrap();
+    e.u8(1).unwrap().u64(lovelace).unwrap();
+    e.u8(2).unwrap().array(2).unwrap().u8(1).unwrap();
+    e.tag(pallas::codec::minicbor::data::Tag::Cbor)
+        .unwrap()
+        .bytes(datum)
+        .unwrap();
+
+    EraCbor(Era::Babbage, e.into_writer())
+}
+
+/// A Babbage post-alonzo output carrying a Plutus V2 reference script
+pub fn fake_utxo_with_script_ref(address: &[u8], lovelace: u64, script: &[u8]) -> EraCbor {
+    let mut inner = Encoder::new(Vec::new());
+    inner
+        .array(2)
+        .unwrap()
+        .u8(2)
+        .unwrap()
+        .bytes(script)
+        .unwrap();
+
+    let mut e = Encoder::new(Vec::new());
+
+    e.map(3).unwrap();
+    e.u8(0).unwrap().bytes(address).unwrap();
+    e.u8(1).unwrap().u64(lovelace).unwrap();
+    e.u8(3).unwrap();
+    e.tag(pallas::codec::minicbor::data::Tag::Cbor)
+        .unwrap()
+        .bytes(&inner.into_writer())
+        .unwrap();
+
+    EraCbor(Era::Babbage, e.into_writer())
+}
+
+/// A delta for the given slot that only produces the provided utxos
+pub fn fake_produce_delta(slot: u64, produced: Vec<(TxoRef, EraCbor)>) -> LedgerDelta {
+    LedgerDelta {
+        new_position: Some(ChainPoint(slot, slot_to_hash(slot))),
+        produced_utxo: produced.into_iter().collect(),
+        ..Default::default()
+    }
+}
+
+/// A shelley update where each genesis key proposes the given protocol major
+/// version for the epoch
+pub fn shelley_update_block(epoch: u64, proposals: &[(u8, u64)]) -> PParamsBody {
+    let mut e = Encoder::new(Vec::new());
+
+    e.array(2).unwrap();
+    e.map(proposals.len() as u64).unwrap();
+
+    for (key, major) in proposals {
+        e.bytes(&[*key; 28]).unwrap();
+        e.map(1).unwrap();
+        e.u8(14).unwrap();
+        e.array(2).unwrap().u64(*major).unwrap().u64(0).unwrap();
+    }
+
+    e.u64(epoch).unwrap();
+
+    PParamsBody(Era::Shelley, e.into_writer())
+}
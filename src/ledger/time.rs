@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::network::{NetworkError, NetworkParams};
+use super::pparams::Genesis;
+use super::BlockSlot;
+
+/// Wall-clock time as seconds since the unix epoch
+pub type UnixTime = u64;
+
+pub type Epoch = u64;
+
+/// The system start of the network, as defined by the Byron genesis
+///
+/// This is the wall-clock time of slot 0. Every network defines its own, so
+/// conversions between slots and time must never assume mainnet's value.
+pub fn system_start(genesis: &Genesis) -> UnixTime {
+    // the start time is read as is, other genesis values being invalid
+    // doesn't change it
+    NetworkParams::from_genesis(genesis.byron, genesis.shelley)
+        .map(|x| x.system_start)
+        .unwrap_or(genesis.byron.start_time)
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TimeError {
+    #[error("slot length override must be greater than zero")]
+    ZeroSlotLength,
+
+    #[error("epoch length override must be greater than zero")]
+    ZeroEpochLength,
+}
+
+/// Slot timing values that take precedence over the ones from genesis
+///
+/// Meant for devnets running compressed epochs that aren't expressed in the
+/// genesis files. Only the Shelley timing is overridden, custom networks start
+/// directly on Shelley.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeOverride {
+    /// Slots per epoch
+    pub epoch_length: Option<u64>,
+    /// Seconds per slot
+    pub slot_length: Option<u64>,
+}
+
+/// Slot timing of a network, used to convert between slots, epochs and time
+///
+/// Byron and Shelley based eras use different slot and epoch lengths, the
+/// conversion accounts for both using the epoch at which the network moved
+/// to Shelley.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainTime {
+    pub system_start: UnixTime,
+    pub byron_slot_length: u64,
+    pub byron_epoch_length: u64,
+    pub shelley_slot_length: u64,
+    pub shelley_epoch_length: u64,
+    pub shelley_start_epoch: Epoch,
+}
+
+impl From<&NetworkParams> for ChainTime {
+    fn from(network: &NetworkParams) -> Self {
+        Self {
+            system_start: network.system_start,
+            byron_slot_length: network.byron_slot_length,
+            byron_epoch_length: network.byron_epoch_length,
+            shelley_slot_length: network.shelley_slot_length,
+            shelley_epoch_length: network.shelley_epoch_length,
+            shelley_start_epoch: network.shelley_start_epoch,
+        }
+    }
+}
+
+impl ChainTime {
+    pub fn from_genesis(genesis: &Genesis) -> Result<Self, NetworkError> {
+        let network = NetworkParams::from_genesis(genesis.byron, genesis.shelley)?;
+
+        Ok(Self::from(&network))
+    }
+
+    /// Replaces the Shelley timing with the values present in the override
+    pub fn with_override(mut self, values: &TimeOverride) -> Result<Self, TimeError> {
+        if let Some(slot_length) = values.slot_length {
+            if slot_length == 0 {
+                return Err(TimeError::ZeroSlotLength);
+            }
+
+            self.shelley_slot_length = slot_length;
+        }
+
+        if let Some(epoch_length) = values.epoch_length {
+            if epoch_length == 0 {
+                return Err(TimeError::ZeroEpochLength);
+            }
+
+            self.shelley_epoch_length = epoch_length;
+        }
+
+        Ok(self)
+    }
+
+    fn shelley_start_slot(&self) -> BlockSlot {
+        self.shelley_start_epoch * self.byron_epoch_length
+    }
+
+    fn shelley_start_time(&self) -> UnixTime {
+        self.system_start + self.shelley_start_slot() * self.byron_slot_length
+    }
+
+    /// Returns the wall-clock time at the start of the slot
+    pub fn slot_to_time(&self, slot: BlockSlot) -> UnixTime {
+        let shelley_start = self.shelley_start_slot();
+
+        if slot < shelley_start {
+            self.system_start + slot * self.byron_slot_length
+        } else {
+            self.shelley_start_time() + (slot - shelley_start) * self.shelley_slot_length
+        }
+    }
+
+    /// Returns the slot in progress at the given wall-clock time
+    ///
+    /// Returns `None` if the time is before the system start of the network.
+    pub fn time_to_slot(&self, time: UnixTime) -> Option<BlockSlot> {
+        if time < self.system_start {
+            return None;
+        }
+
+        let shelley_start = self.shelley_start_time();
+
+        let slot = if time < shelley_start {
+            (time - self.system_start) / self.byron_slot_length
+        } else {
+            self.shelley_start_slot() + (time - shelley_start) / self.shelley_slot_length
+        };
+
+        Some(slot)
+    }
+
+    /// Returns the epoch of the slot and the position of the slot in the epoch
+    pub fn slot_to_epoch(&self, slot: BlockSlot) -> (Epoch, u64) {
+        let shelley_start = self.shelley_start_slot();
+
+        if slot < shelley_start {
+            (
+                slot / self.byron_epoch_length,
+                slot % self.byron_epoch_length,
+            )
+        } else {
+            let relative = slot - shelley_start;
+
+            (
+                self.shelley_start_epoch + relative / self.shelley_epoch_length,
+                relative % self.shelley_epoch_length,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::pparams::testing::GenesisFiles;
+
+    #[test]
+    fn mainnet_shelley_boundary() {
+        let files = GenesisFiles::mainnet();
+
+        let time = ChainTime::from_genesis(&files.genesis()).unwrap();
+
+        // first shelley slot on mainnet
+        assert_eq!(time.slot_to_time(4492800), 1596059091);
+        assert_eq!(time.time_to_slot(1596059091), Some(4492800));
+        assert_eq!(time.slot_to_epoch(4492800), (208, 0));
+        assert_eq!(time.slot_to_epoch(4492799), (207, 21599));
+    }
+
+    #[test]
+    fn custom_network_start_time() {
+        let mut files = GenesisFiles::mainnet();
+
+        let start = 1_700_000_000;
+        files.byron.start_time = start;
+        files.shelley.network_magic = Some(42);
+
+        let genesis = files.genesis();
+
+        let time = ChainTime::from_genesis(&genesis).unwrap();
+
+        assert_eq!(system_start(&genesis), start);
+        assert_eq!(time.system_start, start);
+
+        // custom networks start directly on shelley
+        assert_eq!(time.slot_to_time(0), start);
+        assert_eq!(time.slot_to_time(10), start + 10);
+        assert_eq!(time.time_to_slot(start + 10), Some(10));
+        assert_eq!(time.time_to_slot(start - 1), None);
+        assert_eq!(time.slot_to_epoch(432001), (1, 1));
+    }
+
+    #[test]
+    fn devnet_time_override() {
+        let mut files = GenesisFiles::mainnet();
+
+        let start = 1_700_000_000;
+        files.byron.start_time = start;
+        files.shelley.network_magic = Some(42);
+
+        let genesis = files.genesis();
+
+        let values = TimeOverride {
+            epoch_length: Some(100),
+            slot_length: Some(2),
+        };
+
+        let time = ChainTime::from_genesis(&genesis)
+            .unwrap()
+            .with_override(&values)
+            .unwrap();
+
+        assert_eq!(time.slot_to_epoch(99), (0, 99));
+        assert_eq!(time.slot_to_epoch(250), (2, 50));
+        assert_eq!(time.slot_to_time(10), start + 20);
+        assert_eq!(time.time_to_slot(start + 21), Some(10));
+
+        // a partial override keeps the genesis value for the rest
+        let partial = TimeOverride {
+            epoch_length: Some(100),
+            slot_length: None,
+        };
+
+        let time = ChainTime::from_genesis(&genesis)
+            .unwrap()
+            .with_override(&partial)
+            .unwrap();
+
+        assert_eq!(time.shelley_slot_length, 1);
+
+        let zero_slot = TimeOverride {
+            epoch_length: None,
+            slot_length: Some(0),
+        };
+
+        assert_eq!(
+            ChainTime::from_genesis(&genesis)
+                .unwrap()
+                .with_override(&zero_slot),
+            Err(TimeError::ZeroSlotLength)
+        );
+
+        let zero_epoch = TimeOverride {
+            epoch_length: Some(0),
+            slot_length: None,
+        };
+
+        assert_eq!(
+            ChainTime::from_genesis(&genesis)
+                .unwrap()
+                .with_override(&zero_epoch),
+            Err(TimeError::ZeroEpochLength)
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Restricts which keys can be queried through the gRPC query service
+//!
+//! Keys are the raw bytes (hex encoded in the config) of the criteria used by
+//! utxo searches: full addresses, payment / delegation parts, policy ids and
+//! asset subjects. Matching is exact, an allowed address doesn't allow its
+//! payment part or vice versa.
+
+use pallas::interop::utxorpc::spec as u5c;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tonic::Status;
+
+use crate::prelude::*;
+
+use u5c::query::any_utxo_pattern::UtxoPattern;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Config {
+    /// if not empty, only these keys can be queried
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// keys that can't be queried
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+fn decode_keys(keys: &[String]) -> Result<HashSet<Vec<u8>>, Error> {
+    keys.iter()
+        .map(|x| hex::decode(x).map_err(|_| Error::config(format!("invalid filter key {x}"))))
+        .collect()
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct QueryFilter {
+    allow: HashSet<Vec<u8>>,
+    deny: HashSet<Vec<u8>>,
+}
+
+impl QueryFilter {
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        Ok(Self {
+            allow: decode_keys(&config.allow)?,
+            deny: decode_keys(&config.deny)?,
+        })
+    }
+
+    fn is_scoped(&self) -> bool {
+        !self.allow.is_empty()
+    }
+
+    /// Checks if a lookup by utxo reference is permitted
+    ///
+    /// References aren't tied to any key, so they can't be checked against an
+    /// allowlist and are rejected when one is configured.
+    pub fn check_refs(&self) -> Result<(), Status> {
+        if self.is_scoped() {
+            return Err(Status::permission_denied(
+                "utxo lookup by reference is not available on this endpoint",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the criteria of a utxo search against the filter
+    ///
+    /// A search naming any denied key is rejected. With an allowlist, at least
+    /// one of the criteria must be allowed; since the results are the
+    /// intersection of all criteria, that's enough to keep them in scope. The
+    /// denylist doesn't filter the results, a search by an allowed key could
+    /// still return utxos at a denied address.
+    pub fn check_pattern(&self, pattern: &u5c::query::AnyUtxoPattern) -> Result<(), Status> {
+        let keys = match &pattern.utxo_pattern {
+            Some(UtxoPattern::Cardano(x)) => pattern_keys(x),
+            _ => vec![],
+        };
+
+        if let Some(key) = keys.iter().find(|x| self.deny.contains(**x)) {
+            return Err(Status::permission_denied(format!(
+                "queries for {} are not allowed",
+                hex::encode(key)
+            )));
+        }
+
+        if self.is_scoped() && !keys.iter().any(|x| self.allow.contains(*x)) {
+            return Err(Status::permission_denied(
+                "query criteria are out of the scope of this endpoint",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn pattern_keys(pattern: &u5c::cardano::TxOutputPattern) -> Vec<&[u8]> {
+    let mut keys: Vec<&[u8]> = vec![];
+
+    if let Some(x) = &pattern.address {
+        keys.push(&x.exact_address[..]);
+        keys.push(&x.payment_part[..]);
+        keys.push(&x.delegation_part[..]);
+    }
+
+    if let Some(x) = &pattern.asset {
+        keys.push(&x.policy_id[..]);
+        keys.push(&x.asset_name[..]);
+    }
+
+    keys.retain(|x| !x.is_empty());
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy_pattern(policy: &[u8]) -> u5c::query::AnyUtxoPattern {
+        u5c::query::AnyUtxoPattern {
+            utxo_pattern: Some(UtxoPattern::Cardano(u5c::cardano::TxOutputPattern {
+                address: None,
+                asset: Some(u5c::cardano::AssetPattern {
+                    policy_id: policy.to_vec().into(),
+                    ..Default::default()
+                }),
+            })),
+        }
+    }
+
+    fn address_pattern(address: &[u8]) -> u5c::query::AnyUtxoPattern {
+        u5c::query::AnyUtxoPattern {
+            utxo_pattern: Some(UtxoPattern::Cardano(u5c::cardano::TxOutputPattern {
+                address: Some(u5c::cardano::AddressPattern {
+                    exact_address: address.to_vec().into(),
+                    ..Default::default()
+                }),
+                asset: None,
+            })),
+        }
+    }
+
+    fn filter(allow: &[&[u8]], deny: &[&[u8]]) -> QueryFilter {
+        QueryFilter::from_config(&Config {
+            allow: allow.iter().map(hex::encode).collect(),
+            deny: deny.iter().map(hex::encode).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn unrestricted_by_default() {
+        let filter = QueryFilter::default();
+
+        assert!(filter.check_pattern(&policy_pattern(&[1; 28])).is_ok());
+        assert!(filter.check_refs().is_ok());
+    }
+
+    #[test]
+    fn allowlist_scopes_queries() {
+        let filter = filter(&[&[1; 28]], &[]);
+
+        assert!(filter.check_pattern(&policy_pattern(&[1; 28])).is_ok());
+
+        let err = filter.check_pattern(&policy_pattern(&[2; 28])).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        let err = filter
+            .check_pattern(&address_pattern(&[3; 29]))
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        let err = filter.check_refs().unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn denylist_rejects_queries() {
+        let filter = filter(&[], &[&[3; 29]]);
+
+        assert!(filter.check_pattern(&address_pattern(&[4; 29])).is_ok());
+        assert!(filter.check_refs().is_ok());
+
+        let err = filter
+            .check_pattern(&address_pattern(&[3; 29]))
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        let config = Config {
+            allow: vec!["not-hex".into()],
+            deny: vec![],
+        };
+
+        assert!(QueryFilter::from_config(&config).is_err());
+    }
+}
@@ -1,10 +1,13 @@
 use pallas::interop::utxorpc::spec as u5c;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 use tonic::transport::{Certificate, Server, ServerTlsConfig};
 use tracing::info;
 
+use crate::ledger::pparams::PParamsFolder;
+use crate::ledger::time::ChainTime;
 use crate::mempool::Mempool;
 use crate::prelude::*;
 use crate::state::LedgerStore;
@@ -13,6 +16,7 @@ use crate::wal::redb::WalStore;
 use super::GenesisFiles;
 
 mod convert;
+pub mod filter;
 mod query;
 mod submit;
 mod sync;
@@ -22,11 +26,21 @@ mod watch;
 pub struct Config {
     pub listen_address: String,
     pub tls_client_ca_root: Option<PathBuf>,
+
+    /// restricts the keys that can be queried
+    #[serde(default)]
+    pub query_filter: Option<filter::Config>,
+
+    /// max size in bytes of a query response, defaults to 4 MiB
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
 }
 
 pub async fn serve(
     config: Config,
-    genesis_files: GenesisFiles,
+    genesis_files: Arc<GenesisFiles>,
+    chain_time: ChainTime,
+    folder: Arc<PParamsFolder>,
     wal: WalStore,
     ledger: LedgerStore,
     mempool: Mempool,
@@ -34,10 +48,26 @@ pub async fn serve(
 ) -> Result<(), Error> {
     let addr = config.listen_address.parse().unwrap();
 
+    let filter = match &config.query_filter {
+        Some(x) => filter::QueryFilter::from_config(x)?,
+        None => filter::QueryFilter::default(),
+    };
+
     let sync_service = sync::SyncServiceImpl::new(wal.clone(), ledger.clone());
     let sync_service = u5c::sync::sync_service_server::SyncServiceServer::new(sync_service);
 
-    let query_service = query::QueryServiceImpl::new(ledger.clone(), genesis_files);
+    let max_response_bytes = config
+        .max_response_bytes
+        .unwrap_or(query::DEFAULT_MAX_RESPONSE_BYTES);
+
+    let query_service = query::QueryServiceImpl::new(
+        ledger.clone(),
+        genesis_files,
+        chain_time,
+        folder,
+        filter,
+        max_response_bytes,
+    );
     let query_service = u5c::query::query_service_server::QueryServiceServer::new(query_service);
 
     let watch_service = watch::WatchServiceImpl::new(wal.clone(), ledger.clone());
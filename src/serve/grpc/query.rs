@@ -1,45 +1,64 @@
 use crate::{
     ledger::{
-        pparams::{self, Genesis},
-        EraCbor, PParamsBody, TxoRef,
+        pparams::{self, Genesis, PParamsFolder},
+        time::ChainTime,
+        ChainPoint, EraCbor, TxoRef, UtxoSet,
     },
     serve::{utils::apply_mask, GenesisFiles},
     state::{LedgerError, LedgerStore},
 };
 use itertools::Itertools as _;
+use pallas::crypto::hash::Hash;
 use pallas::interop::utxorpc::spec as u5c;
-use pallas::ledger::{
-    configs::{alonzo, byron, shelley},
-    traverse::{MultiEraOutput, MultiEraUpdate},
-};
-use pallas::{
-    interop::utxorpc::{self as interop, spec::query::any_utxo_pattern::UtxoPattern},
-    ledger::traverse::wellknown::GenesisValues,
-};
-use std::collections::HashSet;
+use pallas::interop::utxorpc::{self as interop, spec::query::any_utxo_pattern::UtxoPattern};
+use pallas::ledger::traverse::MultiEraOutput;
+use prost::Message as _;
+use std::sync::Arc;
 use tonic::{Request, Response, Status};
 use tracing::info;
 
+use super::filter::QueryFilter;
+
+/// Matches the default max message size that tonic clients accept
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
 pub struct QueryServiceImpl {
     ledger: LedgerStore,
     mapper: interop::Mapper<LedgerStore>,
-    alonzo_genesis_file: alonzo::GenesisFile,
-    byron_genesis_file: byron::GenesisFile,
-    shelley_genesis_file: shelley::GenesisFile,
+    genesis_files: Arc<GenesisFiles>,
+    chain_time: ChainTime,
+    folder: Arc<PParamsFolder>,
+    filter: QueryFilter,
+    max_response_bytes: usize,
 }
 
 impl QueryServiceImpl {
-    pub fn new(ledger: LedgerStore, genesis_files: GenesisFiles) -> Self {
+    pub fn new(
+        ledger: LedgerStore,
+        genesis_files: Arc<GenesisFiles>,
+        chain_time: ChainTime,
+        folder: Arc<PParamsFolder>,
+        filter: QueryFilter,
+        max_response_bytes: usize,
+    ) -> Self {
         Self {
             ledger: ledger.clone(),
-            alonzo_genesis_file: genesis_files.0,
-            byron_genesis_file: genesis_files.1,
-            shelley_genesis_file: genesis_files.2,
+            genesis_files,
+            chain_time,
+            folder,
             mapper: interop::Mapper::new(ledger),
+            filter,
+            max_response_bytes,
         }
     }
 }
 
+fn response_too_big(limit: usize, hint: &str) -> Status {
+    Status::resource_exhausted(format!(
+        "response exceeds the limit of {limit} bytes, {hint}"
+    ))
+}
+
 impl From<LedgerError> for Status {
     fn from(value: LedgerError) -> Self {
         Status::internal(value.to_string())
@@ -47,14 +66,10 @@ impl From<LedgerError> for Status {
 }
 
 trait IntoSet {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status>;
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status>;
 }
 
-fn intersect(
-    ledger: &LedgerStore,
-    a: impl IntoSet,
-    b: impl IntoSet,
-) -> Result<HashSet<TxoRef>, Status> {
+fn intersect(ledger: &LedgerStore, a: impl IntoSet, b: impl IntoSet) -> Result<UtxoSet, Status> {
     let a = a.into_set(ledger)?;
     let b = b.into_set(ledger)?;
 
@@ -74,7 +89,7 @@ impl ByAddressQuery {
 }
 
 impl IntoSet for ByAddressQuery {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         Ok(ledger.get_utxo_by_address(&self.0)?)
     }
 }
@@ -92,7 +107,7 @@ impl ByPaymentQuery {
 }
 
 impl IntoSet for ByPaymentQuery {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         Ok(ledger.get_utxo_by_payment(&self.0)?)
     }
 }
@@ -110,13 +125,13 @@ impl ByDelegationQuery {
 }
 
 impl IntoSet for ByDelegationQuery {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         Ok(ledger.get_utxo_by_stake(&self.0)?)
     }
 }
 
 impl IntoSet for u5c::cardano::AddressPattern {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         let exact = ByAddressQuery::maybe_from(self.exact_address);
         let payment = ByPaymentQuery::maybe_from(self.payment_part);
         let delegation = ByDelegationQuery::maybe_from(self.delegation_part);
@@ -126,7 +141,7 @@ impl IntoSet for u5c::cardano::AddressPattern {
             (None, Some(x), None) => x.into_set(ledger),
             (None, None, Some(x)) => x.into_set(ledger),
             (None, Some(a), Some(b)) => intersect(ledger, a, b),
-            (None, None, None) => Ok(HashSet::default()),
+            (None, None, None) => Ok(UtxoSet::default()),
             _ => Err(Status::invalid_argument("conflicting address criteria")),
         }
     }
@@ -145,7 +160,7 @@ impl ByPolicyQuery {
 }
 
 impl IntoSet for ByPolicyQuery {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         Ok(ledger.get_utxo_by_policy(&self.0)?)
     }
 }
@@ -163,41 +178,41 @@ impl ByAssetQuery {
 }
 
 impl IntoSet for ByAssetQuery {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         Ok(ledger.get_utxo_by_asset(&self.0)?)
     }
 }
 
 impl IntoSet for u5c::cardano::AssetPattern {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         let by_policy = ByPolicyQuery::maybe_from(self.policy_id);
         let by_asset = ByAssetQuery::maybe_from(self.asset_name);
 
         match (by_policy, by_asset) {
             (Some(x), None) => x.into_set(ledger),
             (None, Some(x)) => x.into_set(ledger),
-            (None, None) => Ok(HashSet::default()),
+            (None, None) => Ok(UtxoSet::default()),
             _ => Err(Status::invalid_argument("conflicting asset criteria")),
         }
     }
 }
 
 impl IntoSet for u5c::cardano::TxOutputPattern {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         match (self.address, self.asset) {
             (None, Some(x)) => x.into_set(ledger),
             (Some(x), None) => x.into_set(ledger),
             (Some(a), Some(b)) => intersect(ledger, a, b),
-            (None, None) => Ok(HashSet::default()),
+            (None, None) => Ok(UtxoSet::default()),
         }
     }
 }
 
 impl IntoSet for u5c::query::AnyUtxoPattern {
-    fn into_set(self, ledger: &LedgerStore) -> Result<HashSet<TxoRef>, Status> {
+    fn into_set(self, ledger: &LedgerStore) -> Result<UtxoSet, Status> {
         match self.utxo_pattern {
             Some(UtxoPattern::Cardano(x)) => x.into_set(ledger),
-            _ => Ok(HashSet::new()),
+            _ => Ok(UtxoSet::new()),
         }
     }
 }
@@ -207,22 +222,187 @@ fn from_u5c_txoref(txo: u5c::query::TxoRef) -> Result<TxoRef, Status> {
     Ok(TxoRef(hash, txo.index))
 }
 
+/// Request metadata key used to select how utxos are encoded in responses
+const OUTPUT_ENCODING_KEY: &str = "dolos-output-encoding";
+
+/// The shape of the utxo data returned by queries
+///
+/// The UTxO RPC messages can carry both the raw CBOR of an output and a
+/// structured representation. Decoding the output is wasted effort for clients
+/// that bring their own CBOR library, so the raw form is the default and the
+/// structured one is opt-in per request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputEncoding {
+    /// Raw CBOR of the output, as stored in the ledger
+    #[default]
+    Cbor,
+    /// Structured output decoded via pallas traverse
+    Parsed,
+}
+
+impl OutputEncoding {
+    fn from_metadata(metadata: &tonic::metadata::MetadataMap) -> Result<Self, Status> {
+        let Some(value) = metadata.get(OUTPUT_ENCODING_KEY) else {
+            return Ok(Self::default());
+        };
+
+        match value.to_str() {
+            Ok("cbor") => Ok(Self::Cbor),
+            Ok("parsed") => Ok(Self::Parsed),
+            _ => Err(Status::invalid_argument(format!(
+                "invalid {OUTPUT_ENCODING_KEY}, expected 'cbor' or 'parsed'"
+            ))),
+        }
+    }
+}
+
 fn into_u5c_utxo(
     txo: &TxoRef,
     body: &EraCbor,
     mapper: &interop::Mapper<LedgerStore>,
+    encoding: OutputEncoding,
 ) -> Result<u5c::query::AnyUtxoData, pallas::codec::minicbor::decode::Error> {
-    let parsed = MultiEraOutput::try_from(body)?;
-    let parsed = mapper.map_tx_output(&parsed);
+    let txo_ref = Some(u5c::query::TxoRef {
+        hash: txo.0.to_vec().into(),
+        index: txo.1,
+    });
+
+    let out = match encoding {
+        OutputEncoding::Cbor => u5c::query::AnyUtxoData {
+            txo_ref,
+            native_bytes: body.1.clone().into(),
+            parsed_state: None,
+        },
+        OutputEncoding::Parsed => {
+            let parsed = MultiEraOutput::try_from(body)?;
+            let parsed = mapper.map_tx_output(&parsed);
+
+            u5c::query::AnyUtxoData {
+                txo_ref,
+                native_bytes: Default::default(),
+                parsed_state: Some(u5c::query::any_utxo_data::ParsedState::Cardano(parsed)),
+            }
+        }
+    };
+
+    Ok(out)
+}
 
-    Ok(u5c::query::AnyUtxoData {
-        txo_ref: Some(u5c::query::TxoRef {
-            hash: txo.0.to_vec().into(),
-            index: txo.1,
-        }),
-        native_bytes: body.1.clone().into(),
-        parsed_state: Some(u5c::query::any_utxo_data::ParsedState::Cardano(parsed)),
-    })
+/// Request metadata keys used to read search results in pages
+///
+/// The UTxO RPC search messages have no pagination fields, so pages are
+/// requested and chained through metadata instead. A page holds up to
+/// `dolos-page-size` utxos, fewer if they don't fit in a single response. If
+/// there's more to read, the response metadata carries a `dolos-next-page`
+/// token, to be sent back as `dolos-page-token` to get the next page.
+const PAGE_SIZE_KEY: &str = "dolos-page-size";
+const PAGE_TOKEN_KEY: &str = "dolos-page-token";
+const NEXT_PAGE_KEY: &str = "dolos-next-page";
+
+/// Page size used when a request only carries a page token
+const DEFAULT_PAGE_SIZE: usize = 1_000;
+
+/// A page of search results, starting at the `start` utxo (inclusive)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Page {
+    size: usize,
+    start: Option<TxoRef>,
+}
+
+impl Page {
+    /// Reads the page requested by the metadata, `None` if the request isn't
+    /// paginated
+    fn from_metadata(metadata: &tonic::metadata::MetadataMap) -> Result<Option<Self>, Status> {
+        let size = metadata.get(PAGE_SIZE_KEY);
+        let token = metadata.get(PAGE_TOKEN_KEY);
+
+        if size.is_none() && token.is_none() {
+            return Ok(None);
+        }
+
+        let size = match size {
+            Some(x) => x
+                .to_str()
+                .ok()
+                .and_then(|x| x.parse::<usize>().ok())
+                .filter(|x| *x > 0)
+                .ok_or_else(|| {
+                    Status::invalid_argument(format!(
+                        "invalid {PAGE_SIZE_KEY}, expected a positive integer"
+                    ))
+                })?,
+            None => DEFAULT_PAGE_SIZE,
+        };
+
+        let start = token
+            .map(|x| {
+                x.to_str()
+                    .ok()
+                    .and_then(decode_page_token)
+                    .ok_or_else(|| Status::invalid_argument(format!("invalid {PAGE_TOKEN_KEY}")))
+            })
+            .transpose()?;
+
+        Ok(Some(Self { size, start }))
+    }
+}
+
+/// Page tokens are the first utxo of the page, as `{tx hash}#{index}`
+fn encode_page_token(txo: &TxoRef) -> String {
+    format!("{}#{}", hex::encode(txo.0), txo.1)
+}
+
+fn decode_page_token(token: &str) -> Option<TxoRef> {
+    let (hash, index) = token.split_once('#')?;
+    let hash: [u8; 32] = hex::decode(hash).ok()?.try_into().ok()?;
+
+    Some(TxoRef(Hash::new(hash), index.parse().ok()?))
+}
+
+/// The items of a utxo response, along with the first utxo left out
+struct Collected {
+    items: Vec<u5c::query::AnyUtxoData>,
+    next: Option<TxoRef>,
+}
+
+/// Maps utxos into response items while they fit within the byte budget
+///
+/// The encoded size of each item is added up as it's mapped, so an oversized
+/// result is cut short instead of being fully encoded first. Items are field 1
+/// of both the read and the search responses.
+fn collect_items<'a>(
+    utxos: impl IntoIterator<Item = (&'a TxoRef, &'a EraCbor)>,
+    mapper: &interop::Mapper<LedgerStore>,
+    encoding: OutputEncoding,
+    budget: usize,
+) -> Result<Collected, Status> {
+    let mut items = vec![];
+    let mut size = 0;
+
+    for (txo, body) in utxos {
+        let item = into_u5c_utxo(txo, body, mapper, encoding)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        size += prost::encoding::message::encoded_len(1, &item);
+
+        if size > budget {
+            return Ok(Collected {
+                items,
+                next: Some(txo.clone()),
+            });
+        }
+
+        items.push(item);
+    }
+
+    Ok(Collected { items, next: None })
+}
+
+fn into_u5c_point(point: ChainPoint) -> u5c::query::ChainPoint {
+    u5c::query::ChainPoint {
+        slot: point.0,
+        hash: point.1.to_vec().into(),
+    }
 }
 
 #[async_trait::async_trait]
@@ -241,32 +421,18 @@ impl u5c::query::query_service_server::QueryService for QueryServiceImpl {
         };
 
         let updates = self.ledger.get_pparams(curr_point.0)?;
-        let updates: Vec<_> = updates
-            .iter()
-            .map(|PParamsBody(era, cbor)| -> Result<MultiEraUpdate, Status> {
-                MultiEraUpdate::decode_for_era(*era, cbor)
-                    .map_err(|e| Status::internal(e.to_string()))
-            })
-            .try_collect()?;
 
         let genesis = Genesis {
-            alonzo: &self.alonzo_genesis_file,
-            byron: &self.byron_genesis_file,
-            shelley: &self.shelley_genesis_file,
-        };
-
-        let network_magic = match self.shelley_genesis_file.network_magic {
-            Some(magic) => magic.into(),
-            None => return Err(Status::internal("networkMagic missing in shelley genesis.")),
+            alonzo: &self.genesis_files.0,
+            byron: &self.genesis_files.1,
+            shelley: &self.genesis_files.2,
         };
 
-        let genesis_values = match GenesisValues::from_magic(network_magic) {
-            Some(genesis_values) => genesis_values,
-            None => return Err(Status::internal("Invalid networdMagic.")),
-        };
-
-        let (epoch, _) = genesis_values.absolute_slot_to_relative(curr_point.0);
-        let pparams = pparams::fold_pparams(&genesis, &updates, epoch);
+        let (epoch, _) = self.chain_time.slot_to_epoch(curr_point.0);
+        let pparams = self
+            .folder
+            .fold(&genesis, &updates, epoch)
+            .map_err(|e| Status::internal(e.to_string()))?;
 
         let mut response = u5c::query::ReadParamsResponse {
             values: Some(u5c::query::AnyChainParams {
@@ -275,10 +441,7 @@ impl u5c::query::query_service_server::QueryService for QueryServiceImpl {
                 )
                 .into(),
             }),
-            ledger_tip: Some(u5c::query::ChainPoint {
-                slot: curr_point.0,
-                hash: curr_point.1.to_vec().into(),
-            }),
+            ledger_tip: Some(into_u5c_point(curr_point)),
         };
 
         if let Some(mask) = message.field_mask {
@@ -304,53 +467,63 @@ impl u5c::query::query_service_server::QueryService for QueryServiceImpl {
         &self,
         request: Request<u5c::query::ReadUtxosRequest>,
     ) -> Result<Response<u5c::query::ReadUtxosResponse>, Status> {
+        let encoding = OutputEncoding::from_metadata(request.metadata())?;
         let message = request.into_inner();
 
         info!("received new grpc query");
 
+        self.filter.check_refs()?;
+
         let keys: Vec<_> = message
             .keys
             .into_iter()
             .map(from_u5c_txoref)
             .try_collect()?;
 
-        let utxos = self
+        let (utxos, cursor) = self
             .ledger
-            .get_utxos(keys)
+            .get_utxos_at_cursor(keys)
             .map_err(|e| Status::internal(e.to_string()))?;
 
-        let items: Vec<_> = utxos
-            .iter()
-            .map(|(k, v)| into_u5c_utxo(k, v, &self.mapper))
-            .try_collect()
-            .map_err(|e| Status::internal(e.to_string()))?;
+        let mut response = u5c::query::ReadUtxosResponse {
+            items: vec![],
+            ledger_tip: cursor.map(into_u5c_point),
+        };
 
-        let cursor = self
-            .ledger
-            .cursor()
-            .map_err(|e| Status::internal(e.to_string()))?
-            .map(|p| u5c::query::ChainPoint {
-                slot: p.0,
-                hash: p.1.to_vec().into(),
-            });
+        let budget = self
+            .max_response_bytes
+            .saturating_sub(response.encoded_len());
 
-        Ok(Response::new(u5c::query::ReadUtxosResponse {
-            items,
-            ledger_tip: cursor,
-        }))
+        let collected = collect_items(&utxos, &self.mapper, encoding, budget)?;
+
+        if collected.next.is_some() {
+            return Err(response_too_big(
+                self.max_response_bytes,
+                "request fewer utxos at a time",
+            ));
+        }
+
+        response.items = collected.items;
+
+        Ok(Response::new(response))
     }
 
     async fn search_utxos(
         &self,
         request: Request<u5c::query::SearchUtxosRequest>,
     ) -> Result<Response<u5c::query::SearchUtxosResponse>, Status> {
+        let encoding = OutputEncoding::from_metadata(request.metadata())?;
+        let page = Page::from_metadata(request.metadata())?;
         let message = request.into_inner();
 
         info!("received new grpc query");
 
         let set = match message.predicate {
             Some(x) => match x.r#match {
-                Some(x) => x.into_set(&self.ledger)?,
+                Some(x) => {
+                    self.filter.check_pattern(&x)?;
+                    x.into_set(&self.ledger)?
+                }
                 _ => {
                     return Err(Status::invalid_argument(
                         "only 'match' predicate is supported by Dolos",
@@ -364,29 +537,335 @@ impl u5c::query::query_service_server::QueryService for QueryServiceImpl {
             }
         };
 
-        let utxos = self
+        // only the utxos of the requested page are fetched, the next page
+        // starts at the first match left out of this one
+        let (keys, next) = match &page {
+            Some(Page { size, start }) => {
+                let mut matches = match start {
+                    Some(x) => set.range(x..),
+                    None => set.range::<TxoRef, _>(..),
+                };
+
+                let keys = matches.by_ref().take(*size).cloned().collect_vec();
+
+                (keys, matches.next().cloned())
+            }
+            None => (set.into_iter().collect_vec(), None),
+        };
+
+        // the index lookup happens before, utxos spent in between are left
+        // out of the result instead of being reported at a newer cursor
+        let (utxos, cursor) = self
             .ledger
-            .get_utxos(set.into_iter().collect_vec())
+            .get_utxos_at_cursor(keys)
             .map_err(|e| Status::internal(e.to_string()))?;
 
-        let items: Vec<_> = utxos
-            .iter()
-            .map(|(k, v)| into_u5c_utxo(k, v, &self.mapper))
-            .try_collect()
-            .map_err(|e| Status::internal(e.to_string()))?;
+        let mut response = u5c::query::SearchUtxosResponse {
+            items: vec![],
+            ledger_tip: cursor.map(into_u5c_point),
+        };
 
-        let cursor = self
-            .ledger
-            .cursor()
-            .map_err(|e| Status::internal(e.to_string()))?
-            .map(|p| u5c::query::ChainPoint {
-                slot: p.0,
-                hash: p.1.to_vec().into(),
-            });
+        let budget = self
+            .max_response_bytes
+            .saturating_sub(response.encoded_len());
+
+        let collected = collect_items(&utxos, &self.mapper, encoding, budget)?;
+
+        // a page that doesn't fit is cut short, unless not even the first utxo
+        // fits in the response
+        let next = match (collected.next, &page) {
+            (None, _) => next,
+            (Some(_), None) => {
+                return Err(response_too_big(
+                    self.max_response_bytes,
+                    &format!("narrow down the search or set {PAGE_SIZE_KEY} to read it in pages"),
+                ))
+            }
+            (Some(_), Some(_)) if collected.items.is_empty() => {
+                return Err(response_too_big(
+                    self.max_response_bytes,
+                    "a single utxo doesn't fit in the response",
+                ))
+            }
+            (Some(x), Some(_)) => Some(x),
+        };
+
+        response.items = collected.items;
+
+        let mut response = Response::new(response);
+
+        if let Some(next) = next {
+            let token = encode_page_token(&next)
+                .parse()
+                .map_err(|_| Status::internal("invalid page token"))?;
+
+            response.metadata_mut().insert(NEXT_PAGE_KEY, token);
+        }
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+    use crate::state::memory;
+
+    #[test]
+    fn output_encoding_from_metadata() {
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        assert_eq!(
+            OutputEncoding::from_metadata(&metadata).unwrap(),
+            OutputEncoding::Cbor
+        );
+
+        metadata.insert(OUTPUT_ENCODING_KEY, "parsed".parse().unwrap());
+        assert_eq!(
+            OutputEncoding::from_metadata(&metadata).unwrap(),
+            OutputEncoding::Parsed
+        );
+
+        metadata.insert(OUTPUT_ENCODING_KEY, "json".parse().unwrap());
+        assert!(OutputEncoding::from_metadata(&metadata).is_err());
+    }
+
+    #[test]
+    fn utxo_encoding_shape() {
+        let mapper = interop::Mapper::new(LedgerStore::from(memory::LedgerStore::new()));
+
+        let txo = fake_txo(1, 0);
+        let body = fake_utxo(&fake_enterprise_address(&[1u8; 28]), 1_000_000);
+
+        let raw = into_u5c_utxo(&txo, &body, &mapper, OutputEncoding::Cbor).unwrap();
+        assert_eq!(raw.native_bytes.as_ref(), body.1.as_slice());
+        assert!(raw.parsed_state.is_none());
+
+        let parsed = into_u5c_utxo(&txo, &body, &mapper, OutputEncoding::Parsed).unwrap();
+        assert!(parsed.native_bytes.is_empty());
+        assert!(parsed.parsed_state.is_some());
+    }
+
+    #[tokio::test]
+    async fn responses_carry_the_cursor_of_the_read() {
+        use u5c::query::query_service_server::QueryService as _;
+
+        let mut ledger = LedgerStore::from(memory::LedgerStore::new());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let files = pparams::testing::GenesisFiles::mainnet();
+        let chain_time = ChainTime::from_genesis(&files.genesis()).unwrap();
+
+        let service = QueryServiceImpl::new(
+            ledger.clone(),
+            Arc::new((files.alonzo, files.byron, files.shelley)),
+            chain_time,
+            Arc::new(PParamsFolder::new(pparams::DEFAULT_FOLD_CACHE_EPOCHS)),
+            QueryFilter::default(),
+            DEFAULT_MAX_RESPONSE_BYTES,
+        );
+
+        let request = || {
+            Request::new(u5c::query::ReadUtxosRequest {
+                keys: vec![u5c::query::TxoRef {
+                    hash: fake_txo(1, 0).0.to_vec().into(),
+                    index: 0,
+                }],
+                ..Default::default()
+            })
+        };
+
+        for slot in 1..=2u8 {
+            let produced = vec![(fake_txo(slot, 0), fake_utxo(&address, 1_000_000))];
+            ledger
+                .apply(&[fake_produce_delta(slot as u64, produced)])
+                .unwrap();
+
+            let response = service.read_utxos(request()).await.unwrap().into_inner();
+
+            let expected = ledger.cursor().unwrap().map(into_u5c_point);
+
+            assert_eq!(response.ledger_tip, expected);
+            assert_eq!(response.ledger_tip.unwrap().slot, slot as u64);
+            assert_eq!(response.items.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_response_is_rejected() {
+        use u5c::query::query_service_server::QueryService as _;
+
+        let mut ledger = LedgerStore::from(memory::LedgerStore::new());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced: Vec<_> = (0..10u8)
+            .map(|x| (fake_txo(x, 0), fake_utxo(&address, 1_000_000)))
+            .collect();
+
+        ledger.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let request = || {
+            Request::new(u5c::query::ReadUtxosRequest {
+                keys: (0..10u8)
+                    .map(|x| u5c::query::TxoRef {
+                        hash: fake_txo(x, 0).0.to_vec().into(),
+                        index: 0,
+                    })
+                    .collect(),
+                ..Default::default()
+            })
+        };
+
+        let service = test_service(&ledger, DEFAULT_MAX_RESPONSE_BYTES);
+
+        let response = service.read_utxos(request()).await.unwrap();
+        assert_eq!(response.into_inner().items.len(), 10);
+
+        let service = test_service(&ledger, 256);
+
+        let err = service.read_utxos(request()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+    }
+
+    fn test_service(ledger: &LedgerStore, max_response_bytes: usize) -> QueryServiceImpl {
+        let files = pparams::testing::GenesisFiles::mainnet();
+        let chain_time = ChainTime::from_genesis(&files.genesis()).unwrap();
+
+        QueryServiceImpl::new(
+            ledger.clone(),
+            Arc::new((files.alonzo, files.byron, files.shelley)),
+            chain_time,
+            Arc::new(PParamsFolder::new(pparams::DEFAULT_FOLD_CACHE_EPOCHS)),
+            QueryFilter::default(),
+            max_response_bytes,
+        )
+    }
+
+    fn search_request(
+        address: &[u8],
+        metadata: &[(&'static str, String)],
+    ) -> Request<u5c::query::SearchUtxosRequest> {
+        let pattern = u5c::cardano::TxOutputPattern {
+            address: Some(u5c::cardano::AddressPattern {
+                exact_address: address.to_vec().into(),
+                ..Default::default()
+            }),
+            asset: None,
+        };
+
+        let mut request = Request::new(u5c::query::SearchUtxosRequest {
+            predicate: Some(u5c::query::UtxoPredicate {
+                r#match: Some(u5c::query::AnyUtxoPattern {
+                    utxo_pattern: Some(UtxoPattern::Cardano(pattern)),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        for (key, value) in metadata {
+            request.metadata_mut().insert(*key, value.parse().unwrap());
+        }
+
+        request
+    }
+
+    /// Follows the page tokens until the end, returning the size of each page
+    async fn read_pages(
+        service: &QueryServiceImpl,
+        address: &[u8],
+        page_size: usize,
+    ) -> (Vec<usize>, Vec<TxoRef>) {
+        use u5c::query::query_service_server::QueryService as _;
+
+        let mut pages = vec![];
+        let mut seen = vec![];
+        let mut token: Option<String> = None;
+
+        loop {
+            let mut metadata = vec![(PAGE_SIZE_KEY, page_size.to_string())];
+            metadata.extend(token.map(|x| (PAGE_TOKEN_KEY, x)));
+
+            let response = service
+                .search_utxos(search_request(address, &metadata))
+                .await
+                .unwrap();
+
+            token = response
+                .metadata()
+                .get(NEXT_PAGE_KEY)
+                .map(|x| x.to_str().unwrap().to_owned());
+
+            let items = response.into_inner().items;
+            pages.push(items.len());
+
+            for item in items {
+                let txo = item.txo_ref.unwrap();
+                seen.push(TxoRef(
+                    Hash::new(txo.hash[..].try_into().unwrap()),
+                    txo.index,
+                ));
+            }
+
+            if token.is_none() {
+                break;
+            }
+        }
+
+        (pages, seen)
+    }
+
+    #[tokio::test]
+    async fn search_results_are_paginated() {
+        use u5c::query::query_service_server::QueryService as _;
+
+        let mut ledger = LedgerStore::from(memory::LedgerStore::new());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced: Vec<_> = (0..10u8)
+            .map(|x| (fake_txo(x, 0), fake_utxo(&address, 1_000_000)))
+            .collect();
+
+        ledger.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let expected: Vec<_> = (0..10u8).map(|x| fake_txo(x, 0)).collect();
+
+        let service = test_service(&ledger, DEFAULT_MAX_RESPONSE_BYTES);
+
+        let response = service
+            .search_utxos(search_request(&address, &[]))
+            .await
+            .unwrap();
+
+        assert!(response.metadata().get(NEXT_PAGE_KEY).is_none());
+        assert_eq!(response.into_inner().items.len(), 10);
+
+        let (pages, seen) = read_pages(&service, &address, 4).await;
+        assert_eq!(pages, vec![4, 4, 2]);
+        assert_eq!(seen, expected);
+
+        // a result that doesn't fit points at pagination, pages that don't fit
+        // are cut short and continue on the next one
+        let service = test_service(&ledger, 300);
+
+        let err = service
+            .search_utxos(search_request(&address, &[]))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        assert!(err.message().contains(PAGE_SIZE_KEY));
+
+        let (pages, seen) = read_pages(&service, &address, 4).await;
+        assert!(pages.len() > 3);
+        assert!(pages.iter().all(|x| *x > 0 && *x < 4));
+        assert_eq!(seen, expected);
 
-        Ok(Response::new(u5c::query::SearchUtxosResponse {
-            items,
-            ledger_tip: cursor,
-        }))
+        let invalid = search_request(&address, &[(PAGE_TOKEN_KEY, "nope".into())]);
+        let err = service.search_utxos(invalid).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
     }
 }
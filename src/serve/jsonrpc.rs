@@ -0,0 +1,290 @@
+//! A minimal JSON-RPC 2.0 endpoint over HTTP
+//!
+//! Maps a small set of methods onto the ledger store getters, for integrators
+//! that don't speak gRPC. Requests are sent as a `POST` with a single JSON-RPC
+//! object as body, batches are not supported.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use pallas::interop::utxorpc as interop;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::ledger::{
+    pparams::{Genesis, PParamsFolder},
+    time::ChainTime,
+    PParamsBody,
+};
+use crate::prelude::*;
+use crate::state::{LedgerError, LedgerStore};
+
+use super::GenesisFiles;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub listen_address: String,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn invalid_params(message: impl ToString) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    fn internal(message: impl ToString) -> Self {
+        Self::new(INTERNAL_ERROR, message)
+    }
+}
+
+impl From<LedgerError> for RpcError {
+    fn from(value: LedgerError) -> Self {
+        Self::internal(value)
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    /// raw address bytes, hex encoded
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct PParamsParams {
+    /// defaults to the ledger tip
+    slot: Option<u64>,
+}
+
+struct Context {
+    ledger: LedgerStore,
+    genesis_files: Arc<GenesisFiles>,
+    chain_time: ChainTime,
+    folder: Arc<PParamsFolder>,
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // methods without required params accept a missing params member
+    let params = match params {
+        Value::Null => json!({}),
+        x => x,
+    };
+
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+fn tip_slot(ledger: &LedgerStore) -> Result<u64, RpcError> {
+    match ledger.cursor()? {
+        Some(point) => Ok(point.0),
+        None => Err(RpcError::internal("uninitialized ledger")),
+    }
+}
+
+fn get_utxos_by_address(ctx: &Context, params: Value) -> Result<Value, RpcError> {
+    let params: AddressParams = parse_params(params)?;
+    let address = hex::decode(params.address).map_err(RpcError::invalid_params)?;
+
+    let refs = ctx.ledger.get_utxo_by_address(&address)?;
+    let utxos = ctx.ledger.get_utxos(refs.into_iter().collect())?;
+
+    let utxos = utxos
+        .iter()
+        .map(|(txo, body)| {
+            json!({
+                "tx_hash": txo.0.to_string(),
+                "index": txo.1,
+                "era": u16::from(body.0),
+                "cbor": hex::encode(&body.1),
+            })
+        })
+        .collect();
+
+    Ok(Value::Array(utxos))
+}
+
+fn get_pparams(ctx: &Context, params: Value) -> Result<Value, RpcError> {
+    let params: PParamsParams = parse_params(params)?;
+
+    let slot = match params.slot {
+        Some(x) => x,
+        None => tip_slot(&ctx.ledger)?,
+    };
+
+    let updates = ctx
+        .ledger
+        .get_pparams(slot)?
+        .into_iter()
+        .map(|PParamsBody(era, cbor)| {
+            json!({
+                "era": u16::from(era),
+                "cbor": hex::encode(cbor),
+            })
+        })
+        .collect();
+
+    Ok(Value::Array(updates))
+}
+
+fn get_protocol_params(ctx: &Context, _params: Value) -> Result<Value, RpcError> {
+    let slot = tip_slot(&ctx.ledger)?;
+
+    let updates = ctx.ledger.get_pparams(slot)?;
+
+    let genesis = Genesis {
+        alonzo: &ctx.genesis_files.0,
+        byron: &ctx.genesis_files.1,
+        shelley: &ctx.genesis_files.2,
+    };
+
+    let (epoch, _) = ctx.chain_time.slot_to_epoch(slot);
+
+    let pparams = ctx
+        .folder
+        .fold(&genesis, &updates, epoch)
+        .map_err(RpcError::internal)?;
+
+    let protocol_version = pparams.protocol_version();
+
+    let mapper = interop::Mapper::new(ctx.ledger.clone());
+    let params = serde_json::to_value(mapper.map_pparams(pparams)).map_err(RpcError::internal)?;
+
+    Ok(json!({
+        "slot": slot,
+        "epoch": epoch,
+        "protocol_version": protocol_version,
+        "params": params,
+    }))
+}
+
+fn dispatch(ctx: &Context, request: RpcRequest) -> Result<Value, RpcError> {
+    match request.method.as_str() {
+        "getUtxosByAddress" => get_utxos_by_address(ctx, request.params),
+        "getPParams" => get_pparams(ctx, request.params),
+        "getProtocolParams" => get_protocol_params(ctx, request.params),
+        x => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {x}"),
+        )),
+    }
+}
+
+fn rpc_response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(x) => json!({ "jsonrpc": "2.0", "id": id, "result": x }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+async fn handle(ctx: Arc<Context>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST {
+        let res = Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+
+        return Ok(res);
+    }
+
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(x) => x,
+        Err(err) => {
+            let res = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(err.to_string()))
+                .unwrap();
+
+            return Ok(res);
+        }
+    };
+
+    let response = match serde_json::from_slice::<Value>(&body) {
+        Err(err) => rpc_response(Value::Null, Err(RpcError::new(PARSE_ERROR, err))),
+        Ok(value) => match serde_json::from_value::<RpcRequest>(value) {
+            Err(err) => rpc_response(Value::Null, Err(RpcError::new(INVALID_REQUEST, err))),
+            Ok(request) => {
+                let id = request.id.clone();
+
+                // ledger reads block, keep them off the executor threads
+                let result = tokio::task::spawn_blocking(move || dispatch(&ctx, request))
+                    .await
+                    .unwrap_or_else(|err| Err(RpcError::internal(err)));
+
+                rpc_response(id, result)
+            }
+        },
+    };
+
+    let res = Response::builder()
+        .header("content-type", "application/json")
+        .body(Body::from(response.to_string()))
+        .unwrap();
+
+    Ok(res)
+}
+
+pub async fn serve(
+    config: Config,
+    genesis_files: Arc<GenesisFiles>,
+    chain_time: ChainTime,
+    folder: Arc<PParamsFolder>,
+    ledger: LedgerStore,
+    exit: CancellationToken,
+) -> Result<(), Error> {
+    let addr = config.listen_address.parse().map_err(Error::config)?;
+
+    let ctx = Arc::new(Context {
+        ledger,
+        genesis_files,
+        chain_time,
+        folder,
+    });
+
+    let make_svc = make_service_fn(move |_| {
+        let ctx = ctx.clone();
+
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(ctx.clone(), req))) }
+    });
+
+    info!("serving via JSON-RPC on address: {}", config.listen_address);
+
+    Server::try_bind(&addr)
+        .map_err(Error::server)?
+        .serve(make_svc)
+        .with_graceful_shutdown(exit.cancelled())
+        .await
+        .map_err(Error::server)?;
+
+    Ok(())
+}
@@ -1,10 +1,13 @@
-use futures_util::future::try_join;
+use futures_util::future::try_join3;
 use miette::{Context, IntoDiagnostic};
 use pallas::ledger::configs::{alonzo, byron, shelley};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 use tracing::info;
 
+use crate::ledger::pparams::{self, PParamsFolder};
+use crate::ledger::time::ChainTime;
 use crate::mempool::Mempool;
 use crate::state::LedgerStore;
 use crate::wal::redb::WalStore;
@@ -12,6 +15,9 @@ use crate::wal::redb::WalStore;
 pub mod grpc;
 pub mod utils;
 
+#[cfg(feature = "jsonrpc")]
+pub mod jsonrpc;
+
 #[cfg(unix)]
 pub mod o7s_unix;
 
@@ -28,6 +34,13 @@ pub use o7s_win as o7s;
 pub struct Config {
     pub grpc: Option<grpc::Config>,
     pub ouroboros: Option<o7s::Config>,
+
+    #[cfg(feature = "jsonrpc")]
+    pub jsonrpc: Option<jsonrpc::Config>,
+
+    /// max number of epochs whose folded pparams are kept between queries
+    #[serde(default)]
+    pub pparams_cache: Option<usize>,
 }
 
 pub type GenesisFiles = (
@@ -38,25 +51,38 @@ pub type GenesisFiles = (
 
 /// Serve remote requests
 ///
-/// Uses specified config to start listening for network connections on either
-/// gRPC, Ouroboros or both protocols.
+/// Uses specified config to start listening for network connections on any
+/// combination of gRPC, Ouroboros and (if the feature is enabled) JSON-RPC.
+/// Slot to epoch conversions use `chain_time`, which might carry overrides on
+/// top of the genesis values.
 pub async fn serve(
     config: Config,
     genesis_files: GenesisFiles,
+    chain_time: ChainTime,
     wal: WalStore,
     ledger: LedgerStore,
     mempool: Mempool,
     exit: CancellationToken,
 ) -> miette::Result<()> {
+    let genesis_files = Arc::new(genesis_files);
+
+    let folder = Arc::new(PParamsFolder::new(
+        config
+            .pparams_cache
+            .unwrap_or(pparams::DEFAULT_FOLD_CACHE_EPOCHS),
+    ));
+
     let grpc = async {
         if let Some(cfg) = config.grpc {
             info!("found gRPC config");
 
             grpc::serve(
                 cfg,
-                genesis_files,
+                genesis_files.clone(),
+                chain_time.clone(),
+                folder.clone(),
                 wal.clone(),
-                ledger,
+                ledger.clone(),
                 mempool,
                 exit.clone(),
             )
@@ -81,7 +107,31 @@ pub async fn serve(
         }
     };
 
-    try_join(grpc, o7s).await?;
+    #[cfg(feature = "jsonrpc")]
+    let jsonrpc = async {
+        if let Some(cfg) = config.jsonrpc {
+            info!("found JSON-RPC config");
+
+            jsonrpc::serve(
+                cfg,
+                genesis_files.clone(),
+                chain_time.clone(),
+                folder.clone(),
+                ledger.clone(),
+                exit.clone(),
+            )
+            .await
+            .into_diagnostic()
+            .context("serving JSON-RPC")
+        } else {
+            Ok(())
+        }
+    };
+
+    #[cfg(not(feature = "jsonrpc"))]
+    let jsonrpc = async { Ok::<_, miette::Report>(()) };
+
+    try_join3(grpc, o7s, jsonrpc).await?;
 
     Ok(())
 }
@@ -0,0 +1,418 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use pallas::crypto::hash::Hash;
+use pallas::ledger::traverse::MultiEraOutput;
+
+use crate::ledger::{ChainPoint, EraCbor, LedgerDelta, TxoRef, UtxoSet};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct Inner<T> {
+    entries: HashMap<TxoRef, Arc<T>>,
+    order: VecDeque<TxoRef>,
+    stats: DecodeCacheStats,
+}
+
+/// A bounded cache of decoded utxos keyed by their tx output reference
+///
+/// `MultiEraOutput` borrows from the CBOR it was decoded from, so the cache
+/// doesn't hold the traverse struct itself. Instead, callers provide a decode
+/// function that projects the output into an owned value (eg: a u5c
+/// `TxOutput`) which is memoized and handed out as a shared reference.
+///
+/// Entries are evicted in insertion order once the capacity is reached.
+/// Callers must call [`DecodeCache::invalidate`] with the same deltas being
+/// applied to the ledger store so that stale entries are never served after an
+/// apply or a rollback.
+pub struct DecodeCache<T> {
+    capacity: usize,
+    inner: Mutex<Inner<T>>,
+}
+
+impl<T> DecodeCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner {
+                entries: HashMap::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+                stats: DecodeCacheStats::default(),
+            }),
+        }
+    }
+
+    pub fn get(&self, txo: &TxoRef) -> Option<Arc<T>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let found = inner.entries.get(txo).cloned();
+
+        match found {
+            Some(_) => inner.stats.hits += 1,
+            None => inner.stats.misses += 1,
+        }
+
+        found
+    }
+
+    /// Returns the cached value for the utxo, decoding and storing it if absent
+    pub fn get_or_decode<E>(
+        &self,
+        txo: &TxoRef,
+        cbor: &EraCbor,
+        decode: impl FnOnce(&EraCbor) -> Result<T, E>,
+    ) -> Result<Arc<T>, E> {
+        if let Some(found) = self.get(txo) {
+            return Ok(found);
+        }
+
+        // decoding happens outside of the lock, a concurrent decode of the same
+        // utxo is harmless since both values are equivalent
+        let decoded = Arc::new(decode(cbor)?);
+
+        self.insert(txo.clone(), decoded.clone());
+
+        Ok(decoded)
+    }
+
+    /// Stores a value decoded by the caller, evicting the oldest if full
+    pub fn insert(&self, txo: TxoRef, value: Arc<T>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.entries.insert(txo.clone(), value).is_none() {
+            inner.order.push_back(txo);
+        }
+
+        while inner.entries.len() > self.capacity {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+
+            if inner.entries.remove(&oldest).is_some() {
+                inner.stats.evictions += 1;
+            }
+        }
+
+        // invalidated keys leave stale slots in the order queue, compact it
+        // before it grows unbounded
+        if inner.order.len() > self.capacity * 2 {
+            let Inner { entries, order, .. } = &mut *inner;
+            order.retain(|x| entries.contains_key(x));
+        }
+    }
+
+    /// Removes any entry touched by the deltas
+    pub fn invalidate(&self, deltas: &[LedgerDelta]) {
+        let mut inner = self.inner.lock().unwrap();
+
+        for delta in deltas {
+            let touched = delta
+                .produced_utxo
+                .keys()
+                .chain(delta.consumed_utxo.keys())
+                .chain(delta.recovered_stxi.keys())
+                .chain(delta.undone_utxo.keys());
+
+            for txo in touched {
+                if inner.entries.remove(txo).is_some() {
+                    inner.stats.evictions += 1;
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> DecodeCacheStats {
+        self.inner.lock().unwrap().stats
+    }
+}
+
+/// The values of a utxo summed by the balance queries of the ledger store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedOutput {
+    pub lovelace: u64,
+    /// Native assets as (policy, name, quantity)
+    pub assets: Vec<(Hash<28>, Vec<u8>, u64)>,
+}
+
+impl DecodedOutput {
+    pub fn decode(cbor: &EraCbor) -> Result<Self, pallas::codec::minicbor::decode::Error> {
+        let output = MultiEraOutput::try_from(cbor)?;
+
+        let assets = output
+            .non_ada_assets()
+            .iter()
+            .flat_map(|batch| {
+                batch.assets().into_iter().map(move |asset| {
+                    (
+                        *batch.policy(),
+                        asset.name().to_vec(),
+                        asset.output_coin().unwrap_or_default(),
+                    )
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            lovelace: output.lovelace_amount(),
+            assets,
+        })
+    }
+}
+
+/// The parameters of a filtered utxo query, used as key of the query cache
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UtxoQuery {
+    Address(Vec<u8>),
+    Payment(Vec<u8>),
+    Stake(Vec<u8>),
+    Policy(Vec<u8>),
+    Asset(Vec<u8>),
+    DatumHash(Vec<u8>),
+    ScriptRef(Vec<u8>),
+}
+
+struct QueryInner {
+    cursor: Option<ChainPoint>,
+    entries: HashMap<UtxoQuery, UtxoSet>,
+    order: VecDeque<UtxoQuery>,
+}
+
+/// A bounded cache of filtered utxo query results
+///
+/// Results are keyed by the query and the cursor of the ledger at the time of
+/// the query, a lookup at a different cursor is a miss and drops every entry
+/// computed for the previous one. This keeps the cache correct even if a
+/// result computed before an apply is stored after the apply invalidated the
+/// cache. Entries are evicted in insertion order once the capacity is reached.
+pub struct QueryCache {
+    capacity: usize,
+    inner: Mutex<QueryInner>,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(QueryInner {
+                cursor: None,
+                entries: HashMap::with_capacity(capacity),
+                order: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    pub fn get(&self, query: &UtxoQuery, cursor: &Option<ChainPoint>) -> Option<UtxoSet> {
+        let inner = self.inner.lock().unwrap();
+
+        if inner.cursor != *cursor {
+            return None;
+        }
+
+        inner.entries.get(query).cloned()
+    }
+
+    pub fn insert(&self, query: UtxoQuery, cursor: Option<ChainPoint>, result: UtxoSet) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.cursor != cursor {
+            inner.entries.clear();
+            inner.order.clear();
+            inner.cursor = cursor;
+        }
+
+        if inner.entries.insert(query.clone(), result).is_none() {
+            inner.order.push_back(query);
+        }
+
+        while inner.entries.len() > self.capacity {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+
+            inner.entries.remove(&oldest);
+        }
+    }
+
+    /// Drops every cached result, to be called once the ledger moves
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.order.clear();
+        inner.cursor = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pallas::ledger::traverse::{MultiEraBlock, MultiEraOutput, MultiEraTx};
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::ledger::{compute_delta, LedgerSlice};
+
+    fn load_test_block(name: &str) -> Vec<u8> {
+        let path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("test_data")
+            .join(name);
+
+        let content = std::fs::read_to_string(path).unwrap();
+        hex::decode(content).unwrap()
+    }
+
+    fn fake_delta(block: &MultiEraBlock) -> LedgerDelta {
+        let resolved_inputs = block
+            .txs()
+            .iter()
+            .flat_map(MultiEraTx::consumes)
+            .map(|utxo| TxoRef(*utxo.hash(), utxo.index() as u32))
+            .map(|key| (key, EraCbor(block.era(), vec![])))
+            .collect();
+
+        compute_delta(block, LedgerSlice { resolved_inputs }).unwrap()
+    }
+
+    fn decode_lovelace(cbor: &EraCbor) -> Result<u64, pallas::codec::minicbor::decode::Error> {
+        MultiEraOutput::try_from(cbor).map(|x| x.lovelace_amount())
+    }
+
+    #[test]
+    fn test_recently_produced_are_decoded_once() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let delta = fake_delta(&block);
+
+        let cache = DecodeCache::new(delta.produced_utxo.len());
+        let decodes = Cell::new(0);
+
+        // resolve every produced utxo a few times, as subsequent blocks would
+        for _ in 0..3 {
+            for (txo, body) in delta.produced_utxo.iter() {
+                let value = cache
+                    .get_or_decode(txo, body, |x| {
+                        decodes.set(decodes.get() + 1);
+                        decode_lovelace(x)
+                    })
+                    .unwrap();
+
+                assert_eq!(*value, decode_lovelace(body).unwrap());
+            }
+        }
+
+        let produced = delta.produced_utxo.len() as u64;
+        assert_eq!(decodes.get(), produced);
+
+        let stats = cache.stats();
+        assert_eq!(stats.misses, produced);
+        assert_eq!(stats.hits, produced * 2);
+    }
+
+    #[test]
+    fn test_invalidate_on_apply() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let delta = fake_delta(&block);
+
+        let cache = DecodeCache::new(1_000);
+
+        for (txo, body) in delta.produced_utxo.iter() {
+            cache.get_or_decode(txo, body, decode_lovelace).unwrap();
+        }
+
+        assert_eq!(cache.len(), delta.produced_utxo.len());
+
+        cache.invalidate(std::slice::from_ref(&delta));
+
+        assert!(cache.is_empty());
+
+        for txo in delta.produced_utxo.keys() {
+            assert!(cache.get(txo).is_none());
+        }
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let cbor = load_test_block("alonzo27.block");
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+        let delta = fake_delta(&block);
+
+        assert!(delta.produced_utxo.len() > 2);
+
+        let cache = DecodeCache::new(2);
+
+        for (txo, body) in delta.produced_utxo.iter() {
+            cache.get_or_decode(txo, body, decode_lovelace).unwrap();
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.stats().evictions,
+            delta.produced_utxo.len() as u64 - 2
+        );
+    }
+
+    #[test]
+    fn test_query_cache_keyed_by_cursor() {
+        let cache = QueryCache::new(2);
+
+        let tip = Some(ChainPoint(1, pallas::crypto::hash::Hash::new([1; 32])));
+        let next = Some(ChainPoint(2, pallas::crypto::hash::Hash::new([2; 32])));
+
+        let query = UtxoQuery::Address(vec![1]);
+        let result = UtxoSet::from([TxoRef(pallas::crypto::hash::Hash::new([0; 32]), 0)]);
+
+        cache.insert(query.clone(), tip.clone(), result.clone());
+        assert_eq!(cache.get(&query, &tip), Some(result.clone()));
+
+        // a different cursor never sees results computed for the previous one
+        assert_eq!(cache.get(&query, &next), None);
+
+        cache.insert(UtxoQuery::Stake(vec![1]), next.clone(), UtxoSet::new());
+        assert_eq!(cache.get(&query, &tip), None);
+        assert_eq!(cache.len(), 1);
+
+        for x in 0..3u8 {
+            cache.insert(UtxoQuery::Policy(vec![x]), next.clone(), UtxoSet::new());
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&UtxoQuery::Policy(vec![0]), &next), None);
+
+        cache.invalidate();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use super::{LedgerError, LedgerStore};
+use crate::ledger::{BlockSlot, LedgerDelta};
+
+/// Min amount of finalized records in the log before it's compacted
+const COMPACT_MIN_RECORDS: usize = 1_000;
+
+/// An append-only log of the deltas written to the ledger store
+///
+/// Each record is a little-endian `u32` length followed by the bincode
+/// encoding of the delta. Records are synced to disk before `append` returns,
+/// so a delta is in the log before the store commits it. A crash in the
+/// middle of an append leaves a torn record at the tail, which `replay`
+/// ignores and `open` cuts off.
+///
+/// Records of finalized slots can't be undone anymore, so the log drops them
+/// as the store is finalized. To keep the cost of rewriting the file low, it
+/// only happens once finalized records make up half of the log.
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Slot of each record, in log order
+    slots: VecDeque<BlockSlot>,
+    /// Amount of leading records that belong to finalized slots
+    finalized: usize,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path` for appending, creating it if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LedgerError> {
+        let path = path.as_ref().to_path_buf();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let mut reader = BufReader::new(&file);
+        let mut slots = VecDeque::new();
+        let mut valid_len = 0;
+
+        while let Some(record) = read_raw_record(&mut reader)? {
+            let delta = LedgerDelta::from_bincode(&record).map_err(LedgerError::WriteAheadLog)?;
+            slots.push_back(record_slot(&delta));
+            valid_len += 4 + record.len() as u64;
+        }
+
+        // new records must not land after a torn one, replay would stop there
+        file.set_len(valid_len).map_err(io_error)?;
+
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            slots,
+            finalized: 0,
+        })
+    }
+
+    /// Appends a batch of deltas, durable once it returns
+    pub fn append(&mut self, deltas: &[LedgerDelta]) -> Result<(), LedgerError> {
+        for delta in deltas {
+            let record = bincode::serialize(delta).map_err(LedgerError::WriteAheadLog)?;
+            write_record(&mut self.file, &record)?;
+        }
+
+        self.file.flush().map_err(io_error)?;
+        self.file.get_ref().sync_data().map_err(io_error)?;
+
+        self.slots.extend(deltas.iter().map(record_slot));
+
+        Ok(())
+    }
+
+    /// Drops the records of slots before `until`, once there are enough
+    ///
+    /// Only leading records are dropped, a record is kept if an earlier one
+    /// belongs to a slot that isn't finalized yet.
+    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), LedgerError> {
+        while self
+            .slots
+            .get(self.finalized)
+            .is_some_and(|slot| *slot < until)
+        {
+            self.finalized += 1;
+        }
+
+        if self.finalized < COMPACT_MIN_RECORDS || self.finalized * 2 < self.slots.len() {
+            return Ok(());
+        }
+
+        self.compact()
+    }
+
+    /// Rewrites the log without the finalized records
+    ///
+    /// The records left are written to a new file that replaces the log once
+    /// it's synced, a crash in between leaves the previous log in place.
+    fn compact(&mut self) -> Result<(), LedgerError> {
+        let tmp = self.path.with_extension("compact");
+
+        let source = File::open(&self.path).map_err(io_error)?;
+        let mut reader = BufReader::new(source);
+
+        let mut writer = BufWriter::new(File::create(&tmp).map_err(io_error)?);
+
+        for _ in 0..self.finalized {
+            read_raw_record(&mut reader)?;
+        }
+
+        while let Some(record) = read_raw_record(&mut reader)? {
+            write_record(&mut writer, &record)?;
+        }
+
+        writer.flush().map_err(io_error)?;
+        writer.get_ref().sync_all().map_err(io_error)?;
+        drop(writer);
+
+        std::fs::rename(&tmp, &self.path).map_err(io_error)?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(io_error)?;
+
+        self.file = BufWriter::new(file);
+
+        info!(
+            dropped = self.finalized,
+            kept = self.slots.len() - self.finalized,
+            "compacted write-ahead log"
+        );
+
+        self.slots.drain(..self.finalized);
+        self.finalized = 0;
+
+        Ok(())
+    }
+}
+
+fn io_error(err: std::io::Error) -> LedgerError {
+    LedgerError::WriteAheadLog(err.into())
+}
+
+/// The slot a record belongs to, records without a position go first
+fn record_slot(delta: &LedgerDelta) -> BlockSlot {
+    delta
+        .new_position
+        .as_ref()
+        .or(delta.undone_position.as_ref())
+        .map(|x| x.0)
+        .unwrap_or_default()
+}
+
+fn write_record(writer: &mut impl Write, record: &[u8]) -> Result<(), LedgerError> {
+    let len = u32::try_from(record.len()).map_err(|_| {
+        LedgerError::WriteAheadLog(Box::new(bincode::ErrorKind::Custom(format!(
+            "delta record of {} bytes doesn't fit in the log",
+            record.len()
+        ))))
+    })?;
+
+    writer.write_all(&len.to_le_bytes()).map_err(io_error)?;
+    writer.write_all(record).map_err(io_error)?;
+
+    Ok(())
+}
+
+/// Reads the bytes of the next record, `None` at the end of the log or at a
+/// torn record
+fn read_raw_record(reader: &mut impl Read) -> Result<Option<Vec<u8>>, LedgerError> {
+    let mut len = [0u8; 4];
+
+    match reader.read_exact(&mut len) {
+        Ok(_) => (),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(io_error(err)),
+    }
+
+    let mut record = vec![0u8; u32::from_le_bytes(len) as usize];
+
+    match reader.read_exact(&mut record) {
+        Ok(_) => (),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+            warn!("ignoring torn record at the end of the write-ahead log");
+            return Ok(None);
+        }
+        Err(err) => return Err(io_error(err)),
+    }
+
+    Ok(Some(record))
+}
+
+/// Reads the next record, `None` at the end of the log or at a torn record
+fn read_record(reader: &mut impl Read) -> Result<Option<LedgerDelta>, LedgerError> {
+    let Some(record) = read_raw_record(reader)? else {
+        return Ok(None);
+    };
+
+    let delta = LedgerDelta::from_bincode(&record).map_err(LedgerError::WriteAheadLog)?;
+
+    Ok(Some(delta))
+}
+
+/// Applies every delta of the log at `path` to the store, in order
+///
+/// Meant to bring a store restored from a backup or a snapshot up to date.
+/// Records of finalized slots are dropped as the log grows, so the store
+/// needs to be at or past the first slot of the log. Deltas already in the
+/// store are applied again, which is harmless for the utxos they produce. The
+/// store shouldn't be writing to the same log. Returns the amount of deltas
+/// replayed.
+pub fn replay(path: impl AsRef<Path>, store: &mut LedgerStore) -> Result<usize, LedgerError> {
+    let file = File::open(path).map_err(io_error)?;
+    let mut reader = BufReader::new(file);
+
+    let mut count = 0;
+
+    while let Some(delta) = read_record(&mut reader)? {
+        store.apply(&[delta])?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+    use crate::ledger::{ChainPoint, PParamsBody};
+    use crate::state::memory;
+    use pallas::ledger::traverse::Era;
+
+    fn sample_deltas() -> Vec<LedgerDelta> {
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let mut first = fake_produce_delta(
+            1,
+            vec![
+                (fake_txo(1, 0), fake_utxo(&address, 1_000_000)),
+                (fake_txo(1, 1), fake_utxo(&address, 2_000_000)),
+            ],
+        );
+
+        first
+            .new_pparams
+            .push(PParamsBody(Era::Babbage, vec![0x80]));
+
+        let mut second = fake_produce_delta(2, vec![(fake_txo(2, 0), fake_utxo(&address, 3))]);
+
+        second
+            .consumed_utxo
+            .insert(fake_txo(1, 0), fake_utxo(&address, 1_000_000));
+
+        vec![first, second]
+    }
+
+    #[test]
+    fn replayed_log_matches_direct_apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deltas.log");
+
+        let mut direct: LedgerStore = memory::LedgerStore::new().into();
+        direct.apply(&sample_deltas()).unwrap();
+
+        let mut log = WriteAheadLog::open(&path).unwrap();
+        log.append(&sample_deltas()).unwrap();
+        drop(log);
+
+        // a crash in the middle of the next append
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff, 0x00, 0x00, 0x00, 0x01]).unwrap();
+        drop(file);
+
+        let mut replayed: LedgerStore = memory::LedgerStore::new().into();
+        assert_eq!(replay(&path, &mut replayed).unwrap(), 2);
+
+        assert_eq!(
+            replayed.cursor().unwrap(),
+            Some(ChainPoint(2, slot_to_hash(2)))
+        );
+
+        assert_eq!(replayed.cursor().unwrap(), direct.cursor().unwrap());
+
+        let refs = vec![fake_txo(1, 0), fake_txo(1, 1), fake_txo(2, 0)];
+
+        assert_eq!(
+            replayed.get_utxos(refs.clone()).unwrap(),
+            direct.get_utxos(refs).unwrap()
+        );
+
+        assert_eq!(
+            replayed.get_pparams(2).unwrap(),
+            direct.get_pparams(2).unwrap()
+        );
+
+        // the torn record is cut off when the log is opened again
+        let mut log = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(log.slots, [1, 2]);
+
+        log.append(&[fake_produce_delta(3, vec![])]).unwrap();
+        drop(log);
+
+        let mut replayed: LedgerStore = memory::LedgerStore::new().into();
+        assert_eq!(replay(&path, &mut replayed).unwrap(), 3);
+    }
+
+    #[test]
+    fn finalized_records_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deltas.log");
+
+        let deltas: Vec<_> = (1..=3_000)
+            .map(|slot| fake_produce_delta(slot, vec![]))
+            .collect();
+
+        let mut log = WriteAheadLog::open(&path).unwrap();
+        log.append(&deltas).unwrap();
+
+        let full = std::fs::metadata(&path).unwrap().len();
+
+        // too few finalized records to be worth a rewrite
+        log.finalize(1_000).unwrap();
+        assert_eq!(log.finalized, 999);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), full);
+
+        log.finalize(1_501).unwrap();
+        assert_eq!(log.finalized, 0);
+        assert_eq!(log.slots.len(), 1_500);
+        assert!(std::fs::metadata(&path).unwrap().len() < full);
+
+        // appends keep going to the compacted log
+        log.append(&[fake_produce_delta(3_001, vec![])]).unwrap();
+        drop(log);
+
+        let mut replayed: LedgerStore = memory::LedgerStore::new().into();
+        assert_eq!(replay(&path, &mut replayed).unwrap(), 1_501);
+
+        assert_eq!(
+            replayed.first_cursor().unwrap(),
+            Some(ChainPoint(1_501, slot_to_hash(1_501)))
+        );
+
+        assert_eq!(
+            replayed.cursor().unwrap(),
+            Some(ChainPoint(3_001, slot_to_hash(3_001)))
+        );
+
+        assert_eq!(WriteAheadLog::open(&path).unwrap().slots.len(), 1_501);
+    }
+}
@@ -0,0 +1,506 @@
+use pallas::ledger::traverse::MultiEraOutput;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use super::redb::tables::FilterIndexes;
+use crate::ledger::*;
+
+type Error = super::LedgerError;
+
+/// The filter index keys of a batch of utxos, as (index, key, utxo)
+type IndexKeys = Vec<(usize, Vec<u8>, TxoRef)>;
+
+fn index_keys<'a>(
+    utxos: impl Iterator<Item = (&'a TxoRef, &'a EraCbor)>,
+) -> Result<IndexKeys, Error> {
+    let mut out = vec![];
+
+    for (txo, body) in utxos {
+        let output = MultiEraOutput::try_from(body)?;
+
+        for (index, key) in FilterIndexes::index_keys(&output)? {
+            out.push((index, key, txo.clone()));
+        }
+    }
+
+    Ok(out)
+}
+
+#[derive(Default, Clone)]
+struct Inner {
+    cursors: BTreeMap<BlockSlot, (BlockHash, Option<BlockHeight>, Vec<TxoRef>)>,
+    utxos: HashMap<TxoRef, EraCbor>,
+    pparams: BTreeMap<BlockSlot, PParamsBody>,
+    /// Filter indexes, by their position in `FilterIndexes`
+    indexes: [BTreeMap<Vec<u8>, UtxoSet>; 5],
+}
+
+impl Inner {
+    fn index(&mut self, keys: IndexKeys) {
+        for (index, key, txo) in keys {
+            self.indexes[index].entry(key).or_default().insert(txo);
+        }
+    }
+
+    fn unindex(&mut self, keys: IndexKeys) {
+        for (index, key, txo) in keys {
+            if let Entry::Occupied(mut entry) = self.indexes[index].entry(key) {
+                entry.get_mut().remove(&txo);
+
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        }
+    }
+
+    fn get_by_key(&self, index: usize, key: &[u8]) -> UtxoSet {
+        self.indexes[index].get(key).cloned().unwrap_or_default()
+    }
+}
+
+/// A ledger store that keeps all of its state in memory
+///
+/// It follows the same semantics as the v2 redb schema: consumed utxos are
+/// kept until finalized, but they leave the filter indexes as soon as they're
+/// consumed. The optional indexes (datum, reference script and slot) aren't
+/// supported. Nothing is persisted, the state is dropped together with the
+/// last clone of the store.
+#[derive(Clone, Default)]
+pub struct LedgerStore(Arc<RwLock<Inner>>);
+
+impl LedgerStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.cursor().map(|x| x.is_none())
+    }
+
+    pub fn cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let last = inner
+            .cursors
+            .last_key_value()
+            .map(|(slot, (hash, ..))| ChainPoint(*slot, *hash));
+
+        Ok(last)
+    }
+
+    pub fn cursor_with_height(&self) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let last = inner
+            .cursors
+            .last_key_value()
+            .map(|(slot, (hash, height, _))| (ChainPoint(*slot, *hash), *height));
+
+        Ok(last)
+    }
+
+    pub fn repair_cursor(&mut self, point: &ChainPoint) -> Result<(), Error> {
+        let mut inner = self.0.write().unwrap();
+
+        inner.cursors = BTreeMap::from([(point.0, (point.1, None, vec![]))]);
+
+        Ok(())
+    }
+
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let first = inner
+            .cursors
+            .first_key_value()
+            .map(|(slot, (hash, ..))| ChainPoint(*slot, *hash));
+
+        Ok(first)
+    }
+
+    pub fn utxo_count(&self) -> Result<u64, Error> {
+        Ok(self.0.read().unwrap().utxos.len() as u64)
+    }
+
+    pub fn verify_utxos(&self) -> Result<Vec<super::UtxoMismatch>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let mut out: Vec<_> = inner
+            .utxos
+            .iter()
+            .filter_map(|(txo, body)| super::UtxoMismatch::check(txo, body))
+            .collect();
+
+        out.sort_by(|a, b| a.txo.cmp(&b.txo));
+
+        Ok(out)
+    }
+
+    pub fn get_pparams(&self, until: BlockSlot) -> Result<Vec<PParamsBody>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let out = inner
+            .pparams
+            .range(..until)
+            .map(|(_, body)| body.clone())
+            .collect();
+
+        Ok(out)
+    }
+
+    pub fn get_utxo_by_address(&self, address: &[u8]) -> Result<UtxoSet, Error> {
+        let inner = self.0.read().unwrap();
+        Ok(inner.get_by_key(FilterIndexes::ADDRESS, address))
+    }
+
+    pub fn get_utxo_by_payment(&self, payment: &[u8]) -> Result<UtxoSet, Error> {
+        let inner = self.0.read().unwrap();
+        Ok(inner.get_by_key(FilterIndexes::PAYMENT, payment))
+    }
+
+    pub fn get_utxo_by_stake(&self, stake: &[u8]) -> Result<UtxoSet, Error> {
+        let inner = self.0.read().unwrap();
+        Ok(inner.get_by_key(FilterIndexes::STAKE, stake))
+    }
+
+    pub fn get_utxo_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, Error> {
+        let inner = self.0.read().unwrap();
+        Ok(inner.get_by_key(FilterIndexes::POLICY, policy))
+    }
+
+    pub fn get_utxo_by_asset(&self, asset: &[u8]) -> Result<UtxoSet, Error> {
+        let inner = self.0.read().unwrap();
+        Ok(inner.get_by_key(FilterIndexes::ASSET, asset))
+    }
+
+    /// Lists the distinct stake parts in the index, see
+    /// `FilterIndexes::list_stake_keys`
+    pub fn list_stake_credentials(
+        &self,
+        cursor: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), Error> {
+        use std::ops::Bound;
+
+        let inner = self.0.read().unwrap();
+
+        let start = match cursor {
+            Some(x) => Bound::Excluded(x),
+            None => Bound::Unbounded,
+        };
+
+        let mut keys = inner.indexes[FilterIndexes::STAKE]
+            .range((start, Bound::Unbounded))
+            .map(|(key, _)| key.clone());
+
+        let out: Vec<_> = keys.by_ref().take(limit).collect();
+
+        let next = match keys.next() {
+            Some(_) => out.last().cloned(),
+            None => None,
+        };
+
+        Ok((out, next))
+    }
+
+    /// Copies the utxos that aren't consumed by any of the pending cursors
+    pub fn get_live_utxos(&self) -> Result<Vec<(TxoRef, EraCbor)>, Error> {
+        let inner = self.0.read().unwrap();
+
+        let consumed: UtxoSet = inner
+            .cursors
+            .values()
+            .flat_map(|(.., tombstones)| tombstones.iter().cloned())
+            .collect();
+
+        let out = inner
+            .utxos
+            .iter()
+            .filter(|(txo, _)| !consumed.contains(*txo))
+            .map(|(txo, body)| (txo.clone(), body.clone()))
+            .collect();
+
+        Ok(out)
+    }
+
+    /// Scans the whole utxo map, it isn't sorted by tx hash like the redb
+    /// table is. Fine for the small sets this backend is meant for.
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let inner = self.0.read().unwrap();
+
+        let out = inner
+            .utxos
+            .iter()
+            .filter(|(txo, _)| *txo.0 == *tx_hash)
+            .map(|(txo, body)| (txo.clone(), body.clone()))
+            .collect();
+
+        Ok(out)
+    }
+
+    pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
+        let inner = self.0.read().unwrap();
+
+        let out = refs
+            .into_iter()
+            .filter_map(|txo| inner.utxos.get(&txo).cloned().map(|body| (txo, body)))
+            .collect();
+
+        Ok(out)
+    }
+
+    pub fn get_utxos_at_cursor(
+        &self,
+        refs: Vec<TxoRef>,
+    ) -> Result<(UtxoMap, Option<ChainPoint>), Error> {
+        let inner = self.0.read().unwrap();
+
+        let cursor = inner
+            .cursors
+            .last_key_value()
+            .map(|(slot, (hash, ..))| ChainPoint(*slot, *hash));
+
+        let utxos = refs
+            .into_iter()
+            .filter_map(|txo| inner.utxos.get(&txo).cloned().map(|body| (txo, body)))
+            .collect();
+
+        Ok((utxos, cursor))
+    }
+
+    pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), Error> {
+        let mut inner = self.0.write().unwrap();
+
+        for delta in deltas {
+            // re-producing a utxo is only fine if it's a replay of the same body
+            let conflict = delta
+                .produced_utxo
+                .iter()
+                .find(|(txo, body)| inner.utxos.get(*txo).is_some_and(|x| x != *body));
+
+            if let Some((txo, _)) = conflict {
+                return Err(Error::BrokenInvariant(BrokenInvariant::ConflictingUtxo(
+                    txo.clone(),
+                )));
+            }
+
+            // keys are derived before touching the state, a body that can't be
+            // decoded leaves the delta unapplied
+            let tracked = index_keys(
+                delta
+                    .produced_utxo
+                    .iter()
+                    .chain(delta.recovered_stxi.iter()),
+            )?;
+
+            let forgotten = index_keys(delta.consumed_utxo.iter().chain(delta.undone_utxo.iter()))?;
+
+            if let Some(ChainPoint(slot, hash)) = delta.new_position.as_ref() {
+                let tombstones = delta.consumed_utxo.keys().cloned().collect();
+                inner
+                    .cursors
+                    .insert(*slot, (*hash, delta.new_height, tombstones));
+
+                for body in delta.new_pparams.iter() {
+                    inner.pparams.insert(*slot, body.clone());
+                }
+            }
+
+            if let Some(ChainPoint(slot, _)) = delta.undone_position.as_ref() {
+                inner.cursors.remove(slot);
+                inner.pparams.remove(slot);
+            }
+
+            for (txo, body) in delta.produced_utxo.iter() {
+                inner.utxos.insert(txo.clone(), body.clone());
+            }
+
+            for txo in delta.undone_utxo.keys() {
+                inner.utxos.remove(txo);
+            }
+
+            inner.index(tracked);
+            inner.unindex(forgotten);
+        }
+
+        Ok(())
+    }
+
+    /// Applies known-immutable history, consumed utxos are removed right away
+    /// and no cursors are kept but the last one
+    pub fn bulk_load(
+        &mut self,
+        deltas: impl IntoIterator<Item = LedgerDelta>,
+    ) -> Result<usize, Error> {
+        let mut inner = self.0.write().unwrap();
+        let mut written = 0;
+
+        for delta in deltas {
+            let tracked = index_keys(delta.produced_utxo.iter())?;
+            let forgotten = index_keys(delta.consumed_utxo.iter())?;
+
+            if let Some(ChainPoint(slot, hash)) = delta.new_position {
+                inner.cursors = BTreeMap::from([(slot, (hash, delta.new_height, vec![]))]);
+
+                for body in delta.new_pparams {
+                    inner.pparams.insert(slot, body);
+                }
+            }
+
+            for txo in delta.consumed_utxo.keys() {
+                inner.utxos.remove(txo);
+            }
+
+            inner.utxos.extend(delta.produced_utxo);
+
+            inner.index(tracked);
+            inner.unindex(forgotten);
+
+            written += 1;
+        }
+
+        Ok(written)
+    }
+
+    /// Compacts the slots before `until`, returning the amount of slots
+    /// compacted
+    pub fn finalize(&mut self, until: BlockSlot) -> Result<usize, Error> {
+        let mut inner = self.0.write().unwrap();
+
+        let retained = inner.cursors.split_off(&until);
+        let compacted = std::mem::replace(&mut inner.cursors, retained);
+
+        for (_, _, tombstones) in compacted.values() {
+            for txo in tombstones {
+                inner.utxos.remove(txo);
+            }
+        }
+
+        Ok(compacted.len())
+    }
+
+    pub fn copy(&self, target: &Self) -> Result<(), Error> {
+        let source = self.0.read().unwrap().clone();
+        *target.0.write().unwrap() = source;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+
+    #[test]
+    fn apply_and_finalize() {
+        let mut store = LedgerStore::new();
+        assert!(store.is_empty().unwrap());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produce = fake_produce_delta(1, vec![(fake_txo(1, 0), fake_utxo(&address, 1))]);
+
+        let consume = LedgerDelta {
+            new_position: Some(ChainPoint(2, slot_to_hash(2))),
+            consumed_utxo: [(fake_txo(1, 0), fake_utxo(&address, 1))].into(),
+            ..Default::default()
+        };
+
+        store.apply(&[produce, consume]).unwrap();
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(2, slot_to_hash(2)))
+        );
+
+        // consumed utxos are kept until the slot that consumed them is finalized
+        assert_eq!(store.get_utxos(vec![fake_txo(1, 0)]).unwrap().len(), 1);
+
+        store.finalize(3).unwrap();
+
+        assert!(store.get_utxos(vec![fake_txo(1, 0)]).unwrap().is_empty());
+        assert_eq!(store.first_cursor().unwrap(), None);
+    }
+
+    #[test]
+    fn undo_removes_produced() {
+        let mut store = LedgerStore::new();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = (fake_txo(1, 0), fake_utxo(&address, 1));
+
+        store
+            .apply(&[fake_produce_delta(1, vec![utxo.clone()])])
+            .unwrap();
+
+        let undo = LedgerDelta {
+            undone_position: Some(ChainPoint(1, slot_to_hash(1))),
+            undone_utxo: [utxo].into(),
+            ..Default::default()
+        };
+
+        store.apply(&[undo]).unwrap();
+
+        assert!(store.is_empty().unwrap());
+        assert!(store.get_utxos(vec![fake_txo(1, 0)]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn filter_indexes_follow_apply() {
+        let mut store = LedgerStore::new();
+
+        let policy = [9u8; 28];
+        let address = fake_base_address(&[1u8; 28], &[2u8; 28]);
+        let utxo = (
+            fake_txo(1, 0),
+            fake_utxo_with_assets(&address, 1, &[(policy, b"coin", 5)]),
+        );
+
+        store
+            .apply(&[fake_produce_delta(1, vec![utxo.clone()])])
+            .unwrap();
+
+        let expected = UtxoSet::from([fake_txo(1, 0)]);
+
+        let mut asset = policy.to_vec();
+        asset.extend(b"coin");
+
+        assert_eq!(store.get_utxo_by_address(&address).unwrap(), expected);
+        assert_eq!(store.get_utxo_by_payment(&[1u8; 28]).unwrap(), expected);
+        assert_eq!(store.get_utxo_by_stake(&[2u8; 28]).unwrap(), expected);
+        assert_eq!(store.get_utxo_by_policy(&policy).unwrap(), expected);
+        assert_eq!(store.get_utxo_by_asset(&asset).unwrap(), expected);
+
+        assert_eq!(
+            store.list_stake_credentials(None, 10).unwrap(),
+            (vec![vec![2u8; 28]], None)
+        );
+
+        let consume = LedgerDelta {
+            new_position: Some(ChainPoint(2, slot_to_hash(2))),
+            consumed_utxo: [utxo.clone()].into(),
+            ..Default::default()
+        };
+
+        store.apply(std::slice::from_ref(&consume)).unwrap();
+
+        // consumed utxos leave the indexes right away, before being finalized
+        assert!(store.get_utxo_by_address(&address).unwrap().is_empty());
+        assert!(store.get_utxo_by_asset(&asset).unwrap().is_empty());
+        assert!(store.list_stake_credentials(None, 10).unwrap().0.is_empty());
+
+        let undo = LedgerDelta {
+            undone_position: consume.new_position.clone(),
+            recovered_stxi: [utxo].into(),
+            ..Default::default()
+        };
+
+        store.apply(&[undo]).unwrap();
+
+        assert_eq!(store.get_utxo_by_address(&address).unwrap(), expected);
+        assert_eq!(store.get_utxo_by_asset(&asset).unwrap(), expected);
+    }
+}
@@ -1,16 +1,34 @@
+//! Ledger state storage
+//!
+//! [`LedgerStore`] is the facade the rest of the node talks to. The engine
+//! behind it is picked through the [`LedgerBackend`] enum rather than a
+//! `LedgerStore` trait object: several queries return `impl Iterator` values
+//! borrowing a backend snapshot, which can't be part of an object-safe trait,
+//! and the backends don't share one query surface (some answer
+//! [`LedgerError::QueryNotSupported`]). Caching, observers and the delta log
+//! live in the facade, so a backend only covers raw reads and writes. Adding
+//! one means adding a variant and a match arm per method.
+
 use itertools::Itertools as _;
 use pallas::{
+    applying::utils::MultiEraProtocolParameters,
     interop::utxorpc as interop,
     ledger::{
-        configs::{byron, shelley},
-        traverse::{MultiEraBlock, MultiEraTx},
+        primitives::alonzo::StakeCredential,
+        traverse::{MultiEraBlock, MultiEraOutput, MultiEraTx, MultiEraUpdate},
     },
 };
 use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use thiserror::Error;
 
+use crate::ledger::network::NetworkParams;
 use crate::ledger::*;
 
+pub mod cache;
+pub mod delta_log;
+pub mod memory;
+pub mod nonblocking;
 pub mod redb;
 
 #[derive(Debug, Error)]
@@ -24,11 +42,50 @@ pub enum LedgerError {
     #[error("address decoding error")]
     AddressDecoding(pallas::ledger::addresses::Error),
 
+    #[error("cbor decoding error")]
+    CborDecoding(#[source] pallas::codec::minicbor::decode::Error),
+
+    #[error("cursor decoding error")]
+    CursorDecoding(#[source] bincode::Error),
+
+    #[error("invalid era tag {0}")]
+    InvalidEra(u16),
+
     #[error("query not supported")]
     QueryNotSupported,
 
     #[error("invalid store version")]
     InvalidStoreVersion,
+
+    #[error("unrecognized store schema {0}")]
+    SchemaMismatch(String),
+
+    #[error("store is locked by another process")]
+    Contention,
+
+    #[error("ledger worker is not available")]
+    WorkerUnavailable,
+
+    #[error("protocol params error")]
+    PParams(#[source] pparams::PParamsError),
+
+    #[error("aggregated amount overflows")]
+    AmountOverflow,
+
+    #[error("tx hash must be 32 bytes long, got {0}")]
+    InvalidTxHash(usize),
+
+    #[error("delta decoding error")]
+    DeltaDecoding(#[source] bincode::Error),
+
+    #[error("write-ahead log error")]
+    WriteAheadLog(#[source] bincode::Error),
+
+    #[error("rollback of slot {slot} crosses the immutable boundary at {boundary:?}")]
+    RollbackTooDeep {
+        slot: BlockSlot,
+        boundary: Option<BlockSlot>,
+    },
 }
 
 impl From<::redb::TableError> for LedgerError {
@@ -55,191 +112,2388 @@ impl From<::redb::TransactionError> for LedgerError {
     }
 }
 
+impl From<pparams::PParamsError> for LedgerError {
+    fn from(value: pparams::PParamsError) -> Self {
+        Self::PParams(value)
+    }
+}
+
+impl From<pallas::codec::minicbor::decode::Error> for LedgerError {
+    fn from(value: pallas::codec::minicbor::decode::Error) -> Self {
+        Self::CborDecoding(value)
+    }
+}
+
 impl From<pallas::ledger::addresses::Error> for LedgerError {
     fn from(value: pallas::ledger::addresses::Error) -> Self {
         Self::AddressDecoding(value)
     }
 }
 
-/// A persistent store for ledger state
+/// Entry count and stored size of a table of the ledger db
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableStats {
+    pub name: String,
+    pub entries: u64,
+    pub stored_bytes: u64,
+}
+
+/// A summary of the contents of the ledger store, for diagnostics
+#[derive(Debug, Clone)]
+pub struct LedgerStats {
+    pub utxo_count: u64,
+    /// Per-table breakdown, empty for backends without tables
+    pub tables: Vec<TableStats>,
+    pub first_cursor: Option<ChainPoint>,
+    pub cursor: Option<ChainPoint>,
+}
+
+/// Adds an amount to an aggregated total, failing instead of wrapping
+///
+/// Totals are kept as `u128` so that summing `u64` amounts across the utxo set
+/// doesn't overflow in practice, the check is a safety net.
+fn add_amount(total: u128, amount: u64) -> Result<u128, LedgerError> {
+    total
+        .checked_add(amount as u128)
+        .ok_or(LedgerError::AmountOverflow)
+}
+
+/// How a filter index entry disagrees with the utxo set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InconsistencyKind {
+    /// The index points to a utxo that isn't live
+    Dangling,
+    /// A live utxo is missing from the index
+    Unindexed,
+}
+
+/// A filter index entry reported by `verify_indexes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexInconsistency {
+    /// Name of the index table, eg: `byaddress`
+    pub index: String,
+    pub key: Vec<u8>,
+    pub txo: TxoRef,
+    pub kind: InconsistencyKind,
+}
+
+/// A stored utxo that failed the consistency checks of `verify_utxos`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoMismatch {
+    pub txo: TxoRef,
+    pub reason: String,
+}
+
+impl UtxoMismatch {
+    /// Checks that the body of a stored utxo decodes as an output of its era
+    fn check(txo: &TxoRef, body: &EraCbor) -> Option<Self> {
+        let err = MultiEraOutput::try_from(body).err()?;
+
+        Some(Self {
+            txo: txo.clone(),
+            reason: err.to_string(),
+        })
+    }
+}
+
+/// Selects how an address query matches the addresses of the utxos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressMatch {
+    /// Only the exact same address bytes
+    Exact,
+    /// Any Shelley address with the same payment credential, regardless of
+    /// its delegation part (eg: base and enterprise addresses of a key)
+    PaymentCredential,
+    /// Any address delegating to the same stake credential, regardless of its
+    /// payment part
+    StakeCredential,
+}
+
+/// The storage engine backing a ledger store
 #[derive(Clone)]
 #[non_exhaustive]
-pub enum LedgerStore {
+pub enum LedgerBackend {
     Redb(redb::LedgerStore),
+    Memory(memory::LedgerStore),
+    /// Redb, with the immutable and volatile portions in separate dbs
+    Split(redb::split::LedgerStore),
+}
+
+/// A consumer of the deltas committed to the ledger store
+///
+/// Observers are notified only after the write transaction holding the deltas
+/// has been successfully committed, a failed apply never reaches them.
+/// Notification happens synchronously in the thread calling `apply`, so
+/// observers should hand over the data quickly (eg: through a channel).
+pub trait DeltaObserver: Send + Sync {
+    fn on_applied(&self, deltas: &[LedgerDelta]);
+}
+
+impl<F> DeltaObserver for F
+where
+    F: Fn(&[LedgerDelta]) + Send + Sync,
+{
+    fn on_applied(&self, deltas: &[LedgerDelta]) {
+        self(deltas)
+    }
+}
+
+/// A persistent store for ledger state
+///
+/// Clones share the same backend, the same set of registered observers, the
+/// same caches and the same write-ahead log.
+#[derive(Clone)]
+pub struct LedgerStore {
+    backend: LedgerBackend,
+    observers: Arc<RwLock<Vec<Arc<dyn DeltaObserver>>>>,
+    query_cache: Option<Arc<cache::QueryCache>>,
+    decode_cache: Option<Arc<cache::DecodeCache<cache::DecodedOutput>>>,
+    finalize_batch: usize,
+    delta_log: Option<Arc<Mutex<delta_log::WriteAheadLog>>>,
 }
 
 impl LedgerStore {
-    pub fn cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.cursor(),
+    pub fn new(backend: LedgerBackend) -> Self {
+        Self {
+            backend,
+            observers: Default::default(),
+            query_cache: None,
+            decode_cache: None,
+            finalize_batch: redb::DEFAULT_FINALIZE_BATCH,
+            delta_log: None,
         }
     }
 
-    pub fn is_empty(&self) -> Result<bool, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.is_empty(),
+    /// Memoizes the results of the filtered utxo queries
+    ///
+    /// Up to `capacity` results are kept for the current cursor, they're
+    /// dropped as soon as the ledger moves. A zero capacity disables the cache.
+    pub fn with_query_cache(mut self, capacity: usize) -> Self {
+        self.query_cache = match capacity {
+            0 => None,
+            x => Some(Arc::new(cache::QueryCache::new(x))),
+        };
+
+        self
+    }
+
+    /// Keeps the decoded amounts of the utxos resolved by the balance queries
+    ///
+    /// Up to `capacity` utxos are kept, entries touched by an applied or
+    /// rolled back delta are dropped. A zero capacity disables the cache.
+    pub fn with_decode_cache(mut self, capacity: usize) -> Self {
+        self.decode_cache = match capacity {
+            0 => None,
+            x => Some(Arc::new(cache::DecodeCache::new(x))),
+        };
+
+        self
+    }
+
+    /// Sets the max number of slots compacted per write transaction when
+    /// finalizing
+    pub fn with_finalize_batch(mut self, slots: usize) -> Self {
+        self.finalize_batch = slots;
+        self
+    }
+
+    /// Appends every applied delta to a log before it's committed
+    ///
+    /// The log can bring a restored store up to date with
+    /// [`delta_log::replay`]. Deltas loaded through `bulk_load` aren't logged.
+    /// Finalizing the store drops the finalized slots from the log.
+    pub fn with_write_ahead_log(mut self, log: delta_log::WriteAheadLog) -> Self {
+        self.delta_log = Some(Arc::new(Mutex::new(log)));
+        self
+    }
+
+    fn cached_query(
+        &self,
+        query: cache::UtxoQuery,
+        run: impl FnOnce() -> Result<UtxoSet, LedgerError>,
+    ) -> Result<UtxoSet, LedgerError> {
+        let Some(cache) = &self.query_cache else {
+            return run();
+        };
+
+        let cursor = self.cursor()?;
+
+        if let Some(found) = cache.get(&query, &cursor) {
+            return Ok(found);
         }
+
+        let result = run()?;
+        cache.insert(query, cursor, result.clone());
+
+        Ok(result)
     }
 
-    pub fn get_pparams(&self, until: BlockSlot) -> Result<Vec<PParamsBody>, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_pparams(until),
+    /// Resolves and decodes the utxos, going through the decode cache if any
+    ///
+    /// Only the utxos that aren't cached are fetched from the backend.
+    fn get_decoded_utxos(
+        &self,
+        refs: UtxoSet,
+    ) -> Result<Vec<Arc<cache::DecodedOutput>>, LedgerError> {
+        let Some(cache) = &self.decode_cache else {
+            return self
+                .get_utxos(refs.into_iter().collect())?
+                .values()
+                .map(|body| Ok(Arc::new(cache::DecodedOutput::decode(body)?)))
+                .collect();
+        };
+
+        let mut decoded = Vec::with_capacity(refs.len());
+        let mut missing = vec![];
+
+        for txo in refs {
+            match cache.get(&txo) {
+                Some(found) => decoded.push(found),
+                None => missing.push(txo),
+            }
         }
+
+        for (txo, body) in self.get_utxos(missing)? {
+            let value = Arc::new(cache::DecodedOutput::decode(&body)?);
+            cache.insert(txo, value.clone());
+            decoded.push(value);
+        }
+
+        Ok(decoded)
     }
 
-    pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxos(refs),
+    pub fn backend(&self) -> &LedgerBackend {
+        &self.backend
+    }
+
+    /// Registers an observer that will receive every committed batch of deltas
+    pub fn register_observer(&self, observer: Arc<dyn DeltaObserver>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    fn notify_observers(&self, deltas: &[LedgerDelta]) {
+        for observer in self.observers.read().unwrap().iter() {
+            observer.on_applied(deltas);
         }
     }
 
-    pub fn get_utxo_by_address(&self, address: &[u8]) -> Result<UtxoSet, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxo_by_address(address),
+    pub fn cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.cursor(),
+            LedgerBackend::Memory(x) => x.cursor(),
+            LedgerBackend::Split(x) => x.cursor(),
         }
     }
 
-    pub fn get_utxo_by_payment(&self, payment: &[u8]) -> Result<UtxoSet, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxo_by_payment(payment),
+    /// Same as `cursor`, along with the height of the block at the cursor
+    ///
+    /// The height is `None` when the store doesn't know it, eg: for cursors
+    /// written before heights were recorded, repaired cursors or stores on
+    /// the v1 schema.
+    pub fn cursor_with_height(
+        &self,
+    ) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.cursor_with_height(),
+            LedgerBackend::Memory(x) => x.cursor_with_height(),
+            LedgerBackend::Split(x) => x.cursor_with_height(),
         }
     }
 
-    pub fn get_utxo_by_stake(&self, stake: &[u8]) -> Result<UtxoSet, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxo_by_stake(stake),
+    /// Returns the earliest point still retained by the store
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.first_cursor(),
+            LedgerBackend::Memory(x) => x.first_cursor(),
+            LedgerBackend::Split(x) => x.first_cursor(),
         }
     }
 
-    pub fn get_utxo_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxo_by_policy(policy),
+    pub fn is_empty(&self) -> Result<bool, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.is_empty(),
+            LedgerBackend::Memory(x) => x.is_empty(),
+            LedgerBackend::Split(x) => x.is_empty(),
         }
     }
 
-    pub fn get_utxo_by_asset(&self, asset: &[u8]) -> Result<UtxoSet, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.get_utxo_by_asset(asset),
+    pub fn stats(&self) -> Result<LedgerStats, LedgerError> {
+        let (utxo_count, tables) = match &self.backend {
+            LedgerBackend::Redb(x) => {
+                let tables = x.table_stats()?;
+
+                let utxo_count = tables
+                    .iter()
+                    .find(|t| t.name == "utxos")
+                    .map(|t| t.entries)
+                    .unwrap_or_default();
+
+                (utxo_count, tables)
+            }
+            LedgerBackend::Memory(x) => (x.utxo_count()?, vec![]),
+            LedgerBackend::Split(x) => (x.utxo_count()?, vec![]),
+        };
+
+        Ok(LedgerStats {
+            utxo_count,
+            tables,
+            first_cursor: self.first_cursor()?,
+            cursor: self.cursor()?,
+        })
+    }
+
+    /// Scans the utxo set looking for entries that aren't self-consistent
+    ///
+    /// Outputs don't carry their own tx hash or index, so the key can't be
+    /// checked against the value. What can be checked is that each value has a
+    /// known era and decodes as an output of that era, which catches values
+    /// stored under the wrong era or truncated by a faulty write. Mismatches
+    /// are returned in ascending `TxoRef` order.
+    pub fn verify_utxos(&self) -> Result<Vec<UtxoMismatch>, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.verify_utxos(),
+            LedgerBackend::Memory(x) => x.verify_utxos(),
+            LedgerBackend::Split(x) => x.verify_utxos(),
         }
     }
 
-    pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.apply(deltas),
+    /// Cross-checks the filter indexes against the utxo set
+    ///
+    /// Reports index entries pointing to utxos that aren't live and live utxos
+    /// missing from an index. Either one means the indexes are stale and
+    /// should be rebuilt. The whole utxo set is scanned, expect this to take a
+    /// while on a large store. The optional indexes aren't checked.
+    pub fn verify_indexes(&self) -> Result<Vec<IndexInconsistency>, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.verify_indexes(),
+            LedgerBackend::Split(x) => x.verify_indexes(),
+            LedgerBackend::Memory(_) => Err(LedgerError::QueryNotSupported),
         }
     }
 
-    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => x.finalize(until),
+    /// Returns the update bodies recorded up to the given slot
+    ///
+    /// Consecutive identical bodies are collapsed into one. An update body
+    /// carries its target epoch and sets absolute values, so applying the same
+    /// body twice in a row yields the same params as applying it once; dropping
+    /// the repeats doesn't change the outcome of the fold.
+    pub fn get_pparams(&self, until: BlockSlot) -> Result<Vec<PParamsBody>, LedgerError> {
+        let mut bodies = match &self.backend {
+            LedgerBackend::Redb(x) => x.get_pparams(until)?,
+            LedgerBackend::Memory(x) => x.get_pparams(until)?,
+            LedgerBackend::Split(x) => x.get_pparams(until)?,
+        };
+
+        bodies.dedup();
+
+        Ok(bodies)
+    }
+
+    /// Same as `get_pparams`, but yields the bodies as they're read
+    ///
+    /// Redb backends read from a snapshot taken by this call, which stays
+    /// valid for as long as the iterator is alive, so rows written in the
+    /// meantime are never seen. The memory backend copies the bodies upfront.
+    pub fn stream_pparams(
+        &self,
+        until: BlockSlot,
+    ) -> Result<impl Iterator<Item = Result<PParamsBody, LedgerError>>, LedgerError> {
+        let rows: Box<dyn Iterator<Item = Result<PParamsBody, LedgerError>>> = match &self.backend {
+            LedgerBackend::Redb(x) => Box::new(x.stream_pparams(until)?),
+            LedgerBackend::Memory(x) => Box::new(x.get_pparams(until)?.into_iter().map(Ok)),
+            LedgerBackend::Split(x) => Box::new(x.stream_pparams(until)?),
+        };
+
+        // same as the dedup of `get_pparams`
+        let mut previous = None;
+
+        let stream = rows.filter(move |row| match row {
+            Ok(body) if previous.as_ref() == Some(body) => false,
+            Ok(body) => {
+                previous = Some(body.clone());
+                true
+            }
+            Err(_) => true,
+        });
+
+        Ok(stream)
+    }
+
+    /// Streams the live utxo set, in no particular order
+    ///
+    /// Utxos consumed by slots that aren't finalized yet are skipped. Redb
+    /// backends read from a snapshot taken by this call, same as
+    /// `stream_pparams`. The memory backend copies the utxos upfront.
+    pub fn iter_utxos(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(TxoRef, EraCbor), LedgerError>>, LedgerError> {
+        let utxos: Box<dyn Iterator<Item = Result<(TxoRef, EraCbor), LedgerError>>> =
+            match &self.backend {
+                LedgerBackend::Redb(x) => Box::new(x.iter_utxos()?),
+                LedgerBackend::Memory(x) => Box::new(x.get_live_utxos()?.into_iter().map(Ok)),
+                LedgerBackend::Split(x) => Box::new(x.iter_utxos()?),
+            };
+
+        Ok(utxos)
+    }
+
+    /// Sums the lovelace held by the live utxo set
+    ///
+    /// Only covers ada sitting in utxos: reward balances, deposits, the
+    /// treasury and the reserves live outside of the utxo set, so this total
+    /// reconciles to the ada supply only once those are added. Streams and
+    /// decodes every utxo, expect it to take a while on mainnet.
+    pub fn total_lovelace(&self) -> Result<u128, LedgerError> {
+        let mut total = 0u128;
+
+        for utxo in self.iter_utxos()? {
+            let (_, body) = utxo?;
+            let output = MultiEraOutput::try_from(&body)?;
+
+            total = add_amount(total, output.lovelace_amount())?;
         }
+
+        Ok(total)
     }
 
-    pub fn upgrade(self) -> Result<Self, LedgerError> {
-        match self {
-            LedgerStore::Redb(x) => Ok(LedgerStore::Redb(x.upgrade()?)),
+    /// Returns the update proposals targeting the given epoch with their proposer
+    ///
+    /// Proposals are decoded from the stored pparams bodies and split by genesis
+    /// key, so an update carrying proposals from several keys yields one entry
+    /// per key. See [`pparams::split_update_proposals`] for the caveats.
+    pub fn get_update_proposals_by_epoch(
+        &self,
+        epoch: u64,
+    ) -> Result<Vec<(pparams::GenesisKey, MultiEraUpdate<'static>)>, LedgerError> {
+        let bodies = self.get_pparams(BlockSlot::MAX)?;
+
+        let mut out = vec![];
+
+        for PParamsBody(era, cbor) in bodies.iter() {
+            let update = MultiEraUpdate::decode_for_era(*era, cbor)?;
+
+            if update.epoch() == epoch {
+                out.extend(pparams::split_update_proposals(&update));
+            }
         }
+
+        Ok(out)
     }
 
-    pub fn copy(&self, target: &Self) -> Result<(), LedgerError> {
-        match (self, target) {
-            (Self::Redb(x), Self::Redb(target)) => x.copy(target),
+    /// Returns the params as they were when the protocol version took effect
+    ///
+    /// Folds every stored update, see [`pparams::pparams_at_protocol_version`].
+    pub fn pparams_at_protocol_version(
+        &self,
+        protocol: usize,
+        genesis: &pparams::Genesis,
+    ) -> Result<MultiEraProtocolParameters, LedgerError> {
+        let bodies = self.get_pparams(BlockSlot::MAX)?;
+
+        let updates: Vec<_> = bodies
+            .iter()
+            .map(|PParamsBody(era, cbor)| MultiEraUpdate::decode_for_era(*era, cbor))
+            .try_collect()?;
+
+        let out = pparams::pparams_at_protocol_version(genesis, &updates, protocol)?;
+
+        Ok(out)
+    }
+
+    pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.get_utxos(refs),
+            LedgerBackend::Memory(x) => x.get_utxos(refs),
+            LedgerBackend::Split(x) => x.get_utxos(refs),
         }
     }
-}
 
-impl From<redb::LedgerStore> for LedgerStore {
-    fn from(value: redb::LedgerStore) -> Self {
-        Self::Redb(value)
+    /// Returns every utxo produced by a tx, without knowing its indexes
+    ///
+    /// The lookup is a range scan over the utxo keys, which sort by tx hash
+    /// first. Same as `get_utxos`, outputs consumed by slots that aren't
+    /// finalized yet are still returned.
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8]) -> Result<UtxoMap, LedgerError> {
+        let tx_hash: &[u8; 32] = tx_hash
+            .try_into()
+            .map_err(|_| LedgerError::InvalidTxHash(tx_hash.len()))?;
+
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.get_utxos_by_tx(tx_hash),
+            LedgerBackend::Memory(x) => x.get_utxos_by_tx(tx_hash),
+            LedgerBackend::Split(x) => x.get_utxos_by_tx(tx_hash),
+        }
     }
-}
 
-impl interop::LedgerContext for LedgerStore {
-    fn get_utxos<'a>(&self, refs: &[interop::TxoRef]) -> Option<interop::UtxoMap> {
-        let refs: Vec<_> = refs.iter().map(|x| TxoRef::from(*x)).collect();
+    /// Fetches utxos along with the cursor of the ledger they were read at
+    ///
+    /// Both come from the same snapshot of the store, so the cursor tells the
+    /// caller exactly which state the utxos reflect. Clients caching results
+    /// can compare it against the tip to find out if their view is stale.
+    pub fn get_utxos_at_cursor(
+        &self,
+        refs: Vec<TxoRef>,
+    ) -> Result<(UtxoMap, Option<ChainPoint>), LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.get_utxos_at_cursor(refs),
+            LedgerBackend::Memory(x) => x.get_utxos_at_cursor(refs),
+            LedgerBackend::Split(x) => x.get_utxos_at_cursor(refs),
+        }
+    }
 
-        let some = self
-            .get_utxos(refs)
-            .ok()?
-            .into_iter()
-            .map(|(k, v)| (k.into(), v.into()))
+    /// Resolves the spent, collateral and reference inputs of a tx at once
+    ///
+    /// All the refs are fetched together in a single read, so the three
+    /// groups are guaranteed to come from the same snapshot of the ledger. A
+    /// ref can appear in more than one group (eg: an input that's also used
+    /// as collateral).
+    pub fn resolve_tx_inputs(
+        &self,
+        spend: &[TxoRef],
+        collateral: &[TxoRef],
+        reference: &[TxoRef],
+    ) -> Result<ResolvedInputs, LedgerError> {
+        let all = spend
+            .iter()
+            .chain(collateral)
+            .chain(reference)
+            .unique()
+            .cloned()
             .collect();
 
-        Some(some)
+        let found = self.get_utxos(all)?;
+
+        let pick = |refs: &[TxoRef]| -> UtxoMap {
+            refs.iter()
+                .filter_map(|x| found.get(x).map(|body| (x.clone(), body.clone())))
+                .collect()
+        };
+
+        Ok(ResolvedInputs {
+            spend: pick(spend),
+            collateral: pick(collateral),
+            reference: pick(reference),
+        })
     }
-}
 
-pub fn load_slice_for_block(
-    block: &MultiEraBlock,
-    store: &LedgerStore,
-    unapplied_deltas: &[LedgerDelta],
-) -> Result<LedgerSlice, LedgerError> {
-    let txs: HashMap<_, _> = block.txs().into_iter().map(|tx| (tx.hash(), tx)).collect();
+    /// Resolves utxos splitting the refs into chunks of at most `chunk_size`
+    ///
+    /// Each chunk is resolved in its own read transaction, this avoids holding
+    /// a single transaction open (and pinning its pages) while resolving very
+    /// large lists of refs. The tradeoff is consistency: a write committed in
+    /// between chunks might be visible to some chunks but not others. Callers
+    /// that need a consistent snapshot should use `get_utxos`. Lists that fit
+    /// in a single chunk take the same single-transaction path as `get_utxos`.
+    pub fn get_utxos_chunked(
+        &self,
+        refs: Vec<TxoRef>,
+        chunk_size: usize,
+    ) -> Result<UtxoMap, LedgerError> {
+        if chunk_size == 0 || refs.len() <= chunk_size {
+            return self.get_utxos(refs);
+        }
 
-    // TODO: turn this into "referenced utxos" intead of just consumed.
-    let consumed: HashSet<_> = txs
-        .values()
-        .flat_map(MultiEraTx::consumes)
-        .map(|utxo| TxoRef(*utxo.hash(), utxo.index() as u32))
-        .collect();
+        let mut out = UtxoMap::new();
 
-    let consumed_same_block: HashMap<_, _> = txs
-        .iter()
-        .flat_map(|(tx_hash, tx)| {
-            tx.produces()
-                .into_iter()
-                .map(|(idx, utxo)| (TxoRef(*tx_hash, idx as u32), utxo.into()))
+        for chunk in refs.chunks(chunk_size) {
+            out.extend(self.get_utxos(chunk.to_vec())?);
+        }
+
+        Ok(out)
+    }
+
+    pub fn get_utxo(&self, txo: TxoRef) -> Result<Option<EraCbor>, LedgerError> {
+        let mut found = self.get_utxos(vec![txo.clone()])?;
+        Ok(found.remove(&txo))
+    }
+
+    /// Returns the utxos locked by exactly the given address bytes
+    ///
+    /// Other addresses sharing a credential with this one are not included, see
+    /// [`Self::get_utxos_by_address`] to match by credential.
+    pub fn get_utxo_by_address(&self, address: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(
+            cache::UtxoQuery::Address(address.to_vec()),
+            || match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_address(address),
+                LedgerBackend::Memory(x) => x.get_utxo_by_address(address),
+                LedgerBackend::Split(x) => x.get_utxos_by_address(address),
+            },
+        )
+    }
+
+    pub fn get_utxo_by_payment(&self, payment: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(
+            cache::UtxoQuery::Payment(payment.to_vec()),
+            || match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_payment(payment),
+                LedgerBackend::Memory(x) => x.get_utxo_by_payment(payment),
+                LedgerBackend::Split(x) => x.get_utxos_by_payment(payment),
+            },
+        )
+    }
+
+    pub fn get_utxo_by_stake(&self, stake: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(cache::UtxoQuery::Stake(stake.to_vec()), || {
+            match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_stake(stake),
+                LedgerBackend::Memory(x) => x.get_utxo_by_stake(stake),
+                LedgerBackend::Split(x) => x.get_utxos_by_stake(stake),
+            }
         })
-        .filter(|(x, _)| consumed.contains(x))
-        .collect();
+    }
 
-    let consumed_unapplied_deltas: HashMap<_, _> = unapplied_deltas
-        .iter()
-        .flat_map(|d| d.produced_utxo.iter().chain(d.recovered_stxi.iter()))
-        .filter(|(x, _)| consumed.contains(x))
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect();
+    /// Returns the utxos matching an address according to the match mode
+    ///
+    /// `address` is always a full address, the mode picks which part of it is
+    /// looked up. Credential modes require a Shelley address (or a stake
+    /// address for `StakeCredential`), Byron addresses have no credentials to
+    /// match on.
+    pub fn get_utxos_by_address(
+        &self,
+        address: &[u8],
+        mode: AddressMatch,
+    ) -> Result<UtxoSet, LedgerError> {
+        use pallas::ledger::addresses::{Address, ShelleyDelegationPart};
 
-    let to_fetch = consumed
-        .into_iter()
-        .filter(|x| !consumed_same_block.contains_key(x))
-        .filter(|x| !consumed_unapplied_deltas.contains_key(x))
-        .collect_vec();
+        if mode == AddressMatch::Exact {
+            return self.get_utxo_by_address(address);
+        }
 
-    let mut resolved_inputs = store.get_utxos(to_fetch)?;
-    resolved_inputs.extend(consumed_same_block);
-    resolved_inputs.extend(consumed_unapplied_deltas);
+        // keys must be derived the same way the filter indexes derive them
+        match (Address::from_bytes(address)?, mode) {
+            (Address::Shelley(x), AddressMatch::PaymentCredential) => {
+                self.get_utxo_by_payment(&x.payment().to_vec())
+            }
+            (Address::Shelley(x), AddressMatch::StakeCredential) => match x.delegation() {
+                ShelleyDelegationPart::Null => Ok(UtxoSet::new()),
+                x => self.get_utxo_by_stake(&x.to_vec()),
+            },
+            (Address::Stake(x), AddressMatch::StakeCredential) => {
+                self.get_utxo_by_stake(&x.to_vec())
+            }
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
 
-    // TODO: include reference scripts and collateral
+    pub fn get_utxo_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(cache::UtxoQuery::Policy(policy.to_vec()), || {
+            match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_policy(policy),
+                LedgerBackend::Memory(x) => x.get_utxo_by_policy(policy),
+                LedgerBackend::Split(x) => x.get_utxos_by_policy(policy),
+            }
+        })
+    }
 
-    Ok(LedgerSlice { resolved_inputs })
-}
+    pub fn get_utxo_by_asset(&self, asset: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(cache::UtxoQuery::Asset(asset.to_vec()), || {
+            match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_asset(asset),
+                LedgerBackend::Memory(x) => x.get_utxo_by_asset(asset),
+                LedgerBackend::Split(x) => x.get_utxos_by_asset(asset),
+            }
+        })
+    }
 
-pub fn apply_block_batch<'a>(
-    blocks: impl IntoIterator<Item = &'a MultiEraBlock<'a>>,
-    store: &mut LedgerStore,
-    byron: &byron::GenesisFile,
-    shelley: &shelley::GenesisFile,
-) -> Result<(), LedgerError> {
-    let mut deltas: Vec<LedgerDelta> = vec![];
+    /// Lists the distinct stake credentials controlling utxos, paginated
+    ///
+    /// Credentials are returned in ascending byte order. Pass the returned
+    /// cursor back to fetch the next page, a `None` cursor means there's
+    /// nothing left to read.
+    pub fn list_stake_credentials(
+        &self,
+        cursor: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.list_stake_credentials(cursor, limit),
+            LedgerBackend::Memory(x) => x.list_stake_credentials(cursor, limit),
+            LedgerBackend::Split(_) => Err(LedgerError::QueryNotSupported),
+        }
+    }
 
-    for block in blocks {
-        let context = load_slice_for_block(block, store, &deltas)?;
-        let delta = compute_delta(block, context).map_err(LedgerError::BrokenInvariant)?;
+    /// Aggregates the lovelace of the utxo set by stake credential
+    ///
+    /// This is the live snapshot at the tip of the ledger, not the one taken at
+    /// the last epoch boundary, and it doesn't account for rewards or
+    /// deposits. Utxos delegating through a pointer are left out since
+    /// resolving the pointer requires the certificate history. Computed in a
+    /// single pass over the utxo set, so totals come from the same read and
+    /// any backend can serve it. Expect it to take a while on mainnet.
+    pub fn stake_snapshot(&self) -> Result<HashMap<StakeCredential, u64>, LedgerError> {
+        use pallas::ledger::addresses::{Address, ShelleyDelegationPart};
 
-        deltas.push(delta);
+        let mut snapshot: HashMap<StakeCredential, u64> = HashMap::new();
+
+        for utxo in self.iter_utxos()? {
+            let (_, body) = utxo?;
+            let output = MultiEraOutput::try_from(&body)?;
+
+            let credential = match output.address()? {
+                Address::Shelley(x) => match x.delegation() {
+                    ShelleyDelegationPart::Key(x) => StakeCredential::AddrKeyhash(*x),
+                    ShelleyDelegationPart::Script(x) => StakeCredential::Scripthash(*x),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let total = snapshot.entry(credential).or_default();
+
+            *total = total
+                .checked_add(output.lovelace_amount())
+                .ok_or(LedgerError::AmountOverflow)?;
+        }
+
+        Ok(snapshot)
     }
 
-    store.apply(&deltas)?;
+    /// Returns the utxos holding a datum with the given hash
+    ///
+    /// Both datum hashes and inline datums are matched. Requires the optional
+    /// datum index to be enabled in the store.
+    pub fn get_utxo_by_datum_hash(&self, hash: &[u8]) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(cache::UtxoQuery::DatumHash(hash.to_vec()), || {
+            match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_datum_hash(hash),
+                LedgerBackend::Memory(_) | LedgerBackend::Split(_) => {
+                    Err(LedgerError::QueryNotSupported)
+                }
+            }
+        })
+    }
 
-    let tip = deltas
-        .last()
-        .and_then(|x| x.new_position.as_ref())
-        .map(|x| x.0)
-        .unwrap();
+    /// Returns the utxos carrying a reference script with the given hash
+    ///
+    /// Requires the optional reference script index to be enabled in the
+    /// store.
+    pub fn get_utxos_by_reference_script(
+        &self,
+        script_hash: &[u8],
+    ) -> Result<UtxoSet, LedgerError> {
+        self.cached_query(
+            cache::UtxoQuery::ScriptRef(script_hash.to_vec()),
+            || match &self.backend {
+                LedgerBackend::Redb(x) => x.get_utxo_by_script_ref(script_hash),
+                LedgerBackend::Memory(_) | LedgerBackend::Split(_) => {
+                    Err(LedgerError::QueryNotSupported)
+                }
+            },
+        )
+    }
 
-    let to_finalize = lastest_immutable_slot(tip, byron, shelley);
-    store.finalize(to_finalize)?;
+    /// Returns the utxos produced in slots `from..=to` that remain unspent
+    ///
+    /// Meant for incremental projections that process the utxo set by
+    /// ranges. Requires the optional slot index to be enabled in the store,
+    /// utxos produced before it was enabled aren't returned.
+    pub fn utxos_produced_in_range(
+        &self,
+        from: BlockSlot,
+        to: BlockSlot,
+    ) -> Result<UtxoSet, LedgerError> {
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.get_utxos_produced_in_range(from, to),
+            LedgerBackend::Memory(_) | LedgerBackend::Split(_) => {
+                Err(LedgerError::QueryNotSupported)
+            }
+        }
+    }
 
-    Ok(())
+    /// Sums the lovelace and native assets held by the utxos of an address
+    ///
+    /// Utxos are resolved and decoded once. Requires a backend with the address
+    /// index.
+    pub fn balance_by_address(&self, address: &[u8]) -> Result<Balance, LedgerError> {
+        let refs = self.get_utxo_by_address(address)?;
+
+        let mut balance = Balance::default();
+
+        for output in self.get_decoded_utxos(refs)? {
+            balance.lovelace = add_amount(balance.lovelace, output.lovelace)?;
+
+            for (policy, name, quantity) in output.assets.iter() {
+                let total = balance.assets.entry((*policy, name.clone())).or_default();
+                *total = add_amount(*total, *quantity)?;
+            }
+        }
+
+        Ok(balance)
+    }
+
+    /// Sums the quantity of an asset held across the utxo set
+    ///
+    /// This reflects the unspent supply at the tip of the ledger, it's not the
+    /// total ever minted: burned or otherwise destroyed quantities aren't
+    /// accounted for. Requires a backend with the asset index.
+    pub fn asset_total_in_utxos(&self, policy: &[u8], name: &[u8]) -> Result<u128, LedgerError> {
+        let mut subject = policy.to_vec();
+        subject.extend(name);
+
+        let refs = self.get_utxo_by_asset(&subject)?;
+
+        let mut total = 0u128;
+
+        for output in self.get_decoded_utxos(refs)? {
+            for (asset_policy, asset_name, quantity) in output.assets.iter() {
+                if asset_policy.as_slice() == policy && asset_name.as_slice() == name {
+                    total = add_amount(total, *quantity)?;
+                }
+            }
+        }
+
+        Ok(total)
+    }
+
+    pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), LedgerError> {
+        self.apply_with_limits(deltas, &DeltaLimits::default())
+    }
+
+    /// Seeds the store with the genesis utxos, as the delta of the origin
+    ///
+    /// See [`compute_genesis_delta`] for the utxos that are included. It's
+    /// meant to be applied once, on an empty store.
+    pub fn apply_genesis(&mut self, genesis: &pparams::Genesis) -> Result<(), LedgerError> {
+        let delta = compute_genesis_delta(genesis.byron, genesis.shelley)?;
+
+        self.apply(&[delta])
+    }
+
+    /// Applies deltas after checking that each one is within the size limits
+    ///
+    /// The checks happen before any write transaction is opened, the whole
+    /// batch is rejected if any of the deltas is over the limits or adds and
+    /// removes the same utxo. With `check_inputs`, the batch is also rejected
+    /// if a delta consumes a utxo that isn't in the store (nor produced earlier
+    /// in the batch) or that is stored with a different body.
+    pub fn apply_with_limits(
+        &mut self,
+        deltas: &[LedgerDelta],
+        limits: &DeltaLimits,
+    ) -> Result<(), LedgerError> {
+        for delta in deltas {
+            delta
+                .check_limits(limits)
+                .map_err(LedgerError::BrokenInvariant)?;
+
+            delta
+                .check_overlap()
+                .map_err(LedgerError::BrokenInvariant)?;
+        }
+
+        if limits.check_inputs {
+            self.check_inputs(deltas)?;
+        }
+
+        if let Some(log) = &self.delta_log {
+            log.lock().unwrap().append(deltas)?;
+        }
+
+        match &mut self.backend {
+            LedgerBackend::Redb(x) => x.apply(deltas)?,
+            LedgerBackend::Memory(x) => x.apply(deltas)?,
+            LedgerBackend::Split(x) => x.apply(deltas)?,
+        }
+
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate();
+        }
+
+        if let Some(cache) = &self.decode_cache {
+            cache.invalidate(deltas);
+        }
+
+        self.notify_observers(deltas);
+
+        Ok(())
+    }
+
+    /// Checks that the utxos consumed by the deltas resolve, in batch order
+    ///
+    /// Consumed utxos stay in the store until their slot is finalized, so a
+    /// utxo spent twice within the volatile window isn't caught here.
+    fn check_inputs(&self, deltas: &[LedgerDelta]) -> Result<(), LedgerError> {
+        let refs = deltas
+            .iter()
+            .flat_map(|x| x.consumed_utxo.keys().cloned())
+            .collect();
+
+        let mut available = self.get_utxos(refs)?;
+
+        for delta in deltas {
+            for (txo, body) in delta.consumed_utxo.iter() {
+                match available.remove(txo) {
+                    Some(stored) if &stored == body => (),
+                    Some(_) => {
+                        return Err(LedgerError::BrokenInvariant(
+                            BrokenInvariant::MismatchedInput(
+                                delta.new_position.clone(),
+                                txo.clone(),
+                            ),
+                        ))
+                    }
+                    None => {
+                        return Err(LedgerError::BrokenInvariant(
+                            BrokenInvariant::UnresolvedInput(
+                                delta.new_position.clone(),
+                                txo.clone(),
+                            ),
+                        ))
+                    }
+                }
+            }
+
+            available.extend(delta.produced_utxo.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Applies undo deltas, refusing to undo blocks of immutable history
+    ///
+    /// Only the slots that haven't been finalized keep what's needed to undo
+    /// them, the utxos consumed by older blocks might already be compacted.
+    /// Since finalization trails the tip by the security window, a rollback
+    /// past the first cursor means that the common prefix guarantee was
+    /// broken upstream. Such a batch is rejected as a whole with
+    /// `RollbackTooDeep`, leaving the store untouched.
+    pub fn rollback(&mut self, deltas: &[LedgerDelta]) -> Result<(), LedgerError> {
+        let boundary = self.first_cursor()?.map(|ChainPoint(slot, _)| slot);
+
+        for delta in deltas {
+            let Some(ChainPoint(slot, _)) = &delta.undone_position else {
+                continue;
+            };
+
+            let too_deep = match boundary {
+                Some(x) => *slot < x,
+                None => true,
+            };
+
+            if too_deep {
+                return Err(LedgerError::RollbackTooDeep {
+                    slot: *slot,
+                    boundary,
+                });
+            }
+        }
+
+        self.apply(deltas)
+    }
+
+    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), LedgerError> {
+        self.finalize_with_progress(until, |_, _| ())
+    }
+
+    /// Compacts the slots before `until`, reporting progress as it goes
+    ///
+    /// `progress` receives the slots compacted so far and the total to
+    /// compact, it's called once per committed batch (see
+    /// `with_finalize_batch`). Backends that compact in a single step call it
+    /// once at the end.
+    pub fn finalize_with_progress(
+        &mut self,
+        until: BlockSlot,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), LedgerError> {
+        match &mut self.backend {
+            LedgerBackend::Redb(x) => {
+                x.finalize_with_progress(until, self.finalize_batch, &mut progress)?
+            }
+            LedgerBackend::Memory(x) => {
+                let compacted = x.finalize(until)?;
+                progress(compacted, compacted);
+            }
+            LedgerBackend::Split(x) => {
+                let compacted = x.finalize(until)?;
+                progress(compacted, compacted);
+            }
+        }
+
+        if let Some(log) = &self.delta_log {
+            log.lock().unwrap().finalize(until)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the store, meant to be called once on graceful shutdown
+    ///
+    /// Caches held by the store are read-through, they never hold data that
+    /// isn't in the backend yet, so they're just dropped. A write-back cache
+    /// must write its pending data here, before the backend is flushed. The
+    /// backend then makes every committed write durable. The store remains
+    /// usable after a flush.
+    pub fn flush(&self) -> Result<(), LedgerError> {
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate();
+        }
+
+        if let Some(cache) = &self.decode_cache {
+            cache.clear();
+        }
+
+        match &self.backend {
+            LedgerBackend::Redb(x) => x.flush(),
+            LedgerBackend::Split(x) => x.flush(),
+            LedgerBackend::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Loads known-immutable history, trading safety for throughput
+    ///
+    /// Meant for the initial load of history that can't be rolled back, eg:
+    /// from a snapshot or from the immutable db of a node. Compared to `apply`:
+    ///
+    /// - deltas must come in chain order and undo data is ignored
+    /// - the size, overlap and conflict guards are skipped
+    /// - consumed utxos are removed right away, there's nothing to finalize
+    ///   and the loaded slots can't be undone
+    /// - batches of `commit_every` deltas are committed without durability,
+    ///   with periodic durable checkpoints and a durable commit at the end
+    /// - filter indexes are rebuilt from the utxo set once the load is done
+    /// - observers aren't notified
+    ///
+    /// Loading volatile history this way leaves the store unable to follow a
+    /// rollback. If interrupted, the store is left at the last checkpoint with
+    /// stale indexes, which are fixed by resuming the load from the cursor.
+    /// Returns the amount of deltas loaded.
+    pub fn bulk_load(
+        &mut self,
+        deltas: impl IntoIterator<Item = LedgerDelta>,
+        commit_every: usize,
+    ) -> Result<usize, LedgerError> {
+        let written = match &mut self.backend {
+            LedgerBackend::Redb(x) => x.bulk_load(deltas, commit_every)?,
+            LedgerBackend::Memory(x) => x.bulk_load(deltas)?,
+            LedgerBackend::Split(_) => return Err(LedgerError::InvalidStoreVersion),
+        };
+
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate();
+        }
+
+        if let Some(cache) = &self.decode_cache {
+            cache.clear();
+        }
+
+        Ok(written)
+    }
+
+    /// Rebuilds a lost or corrupted cursor from an authoritative point
+    ///
+    /// The utxos don't record the slot nor the hash of the block that produced
+    /// them, so the cursor can't be derived from the stored data. The point
+    /// must come from a trusted source, usually the tip of the WAL the ledger
+    /// was built from. After the repair, `cursor()` and `first_cursor()` both
+    /// return that point.
+    ///
+    /// This is a recovery tool with some limitations:
+    ///
+    /// - the point is trusted as-is, if it doesn't match the utxo set the
+    ///   ledger will diverge from the chain.
+    /// - the history of the volatile window is lost, so blocks before the point
+    ///   can't be undone.
+    /// - tombstones of slots that weren't finalized are lost, the utxos they
+    ///   consumed are never removed.
+    ///
+    /// Only a full resync avoids these caveats.
+    pub fn repair_cursor(&mut self, point: &ChainPoint) -> Result<(), LedgerError> {
+        match &mut self.backend {
+            LedgerBackend::Redb(x) => x.repair_cursor(point)?,
+            LedgerBackend::Memory(x) => x.repair_cursor(point)?,
+            LedgerBackend::Split(_) => return Err(LedgerError::InvalidStoreVersion),
+        }
+
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate();
+        }
+
+        if let Some(cache) = &self.decode_cache {
+            cache.clear();
+        }
+
+        Ok(())
+    }
+
+    pub fn upgrade(self) -> Result<Self, LedgerError> {
+        let backend = match self.backend {
+            LedgerBackend::Redb(x) => LedgerBackend::Redb(x.upgrade()?),
+            LedgerBackend::Memory(_) | LedgerBackend::Split(_) => {
+                return Err(LedgerError::InvalidStoreVersion)
+            }
+        };
+
+        Ok(Self {
+            backend,
+            observers: self.observers,
+            query_cache: self.query_cache,
+            decode_cache: self.decode_cache,
+            finalize_batch: self.finalize_batch,
+            delta_log: self.delta_log,
+        })
+    }
+
+    pub fn copy(&self, target: &Self) -> Result<(), LedgerError> {
+        match (&self.backend, &target.backend) {
+            (LedgerBackend::Redb(x), LedgerBackend::Redb(target)) => x.copy(target),
+            (LedgerBackend::Memory(x), LedgerBackend::Memory(target)) => x.copy(target),
+            _ => Err(LedgerError::InvalidStoreVersion),
+        }
+    }
+}
+
+impl From<LedgerBackend> for LedgerStore {
+    fn from(value: LedgerBackend) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<redb::LedgerStore> for LedgerStore {
+    fn from(value: redb::LedgerStore) -> Self {
+        Self::new(LedgerBackend::Redb(value))
+    }
+}
+
+impl From<redb::split::LedgerStore> for LedgerStore {
+    fn from(value: redb::split::LedgerStore) -> Self {
+        Self::new(LedgerBackend::Split(value))
+    }
+}
+
+impl From<memory::LedgerStore> for LedgerStore {
+    fn from(value: memory::LedgerStore) -> Self {
+        Self::new(LedgerBackend::Memory(value))
+    }
+}
+
+impl interop::LedgerContext for LedgerStore {
+    fn get_utxos<'a>(&self, refs: &[interop::TxoRef]) -> Option<interop::UtxoMap> {
+        let refs: Vec<_> = refs.iter().map(|x| TxoRef::from(*x)).collect();
+
+        let some = self
+            .get_utxos(refs)
+            .ok()?
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+
+        Some(some)
+    }
+}
+
+pub fn load_slice_for_block(
+    block: &MultiEraBlock,
+    store: &LedgerStore,
+    unapplied_deltas: &[LedgerDelta],
+) -> Result<LedgerSlice, LedgerError> {
+    let txs: HashMap<_, _> = block.txs().into_iter().map(|tx| (tx.hash(), tx)).collect();
+
+    // TODO: turn this into "referenced utxos" intead of just consumed.
+    let consumed: HashSet<_> = txs
+        .values()
+        .flat_map(MultiEraTx::consumes)
+        .map(|utxo| TxoRef(*utxo.hash(), utxo.index() as u32))
+        .collect();
+
+    let consumed_same_block: HashMap<_, _> = txs
+        .iter()
+        .flat_map(|(tx_hash, tx)| {
+            tx.produces()
+                .into_iter()
+                .map(|(idx, utxo)| (TxoRef(*tx_hash, idx as u32), utxo.into()))
+        })
+        .filter(|(x, _)| consumed.contains(x))
+        .collect();
+
+    let consumed_unapplied_deltas: HashMap<_, _> = unapplied_deltas
+        .iter()
+        .flat_map(|d| d.produced_utxo.iter().chain(d.recovered_stxi.iter()))
+        .filter(|(x, _)| consumed.contains(x))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    let to_fetch = consumed
+        .into_iter()
+        .filter(|x| !consumed_same_block.contains_key(x))
+        .filter(|x| !consumed_unapplied_deltas.contains_key(x))
+        .collect_vec();
+
+    let mut resolved_inputs: HashMap<_, _> = store.get_utxos(to_fetch)?.into_iter().collect();
+    resolved_inputs.extend(consumed_same_block);
+    resolved_inputs.extend(consumed_unapplied_deltas);
+
+    // TODO: include reference scripts and collateral
+
+    Ok(LedgerSlice { resolved_inputs })
+}
+
+/// Computes what applying a single block to the store would do, without
+/// committing anything
+///
+/// Meant for debugging a block that causes a discrepancy: `pre_state` holds
+/// the ledger right before the block (eg: a copy of a store taken at the right
+/// slot). Returns the delta of the block and, if the block carries update
+/// proposals, the params in effect once those proposals are enacted.
+pub fn replay_block(
+    pre_state: &LedgerStore,
+    block: &MultiEraBlock,
+    genesis: &pparams::Genesis,
+) -> Result<(LedgerDelta, Option<MultiEraProtocolParameters>), LedgerError> {
+    let context = load_slice_for_block(block, pre_state, &[])?;
+    let delta = compute_delta(block, context).map_err(LedgerError::BrokenInvariant)?;
+
+    if delta.new_pparams.is_empty() {
+        return Ok((delta, None));
+    }
+
+    let mut bodies = pre_state.get_pparams(block.slot())?;
+    bodies.extend(delta.new_pparams.iter().cloned());
+
+    let updates: Vec<_> = bodies
+        .iter()
+        .map(|PParamsBody(era, cbor)| MultiEraUpdate::decode_for_era(*era, cbor))
+        .try_collect()?;
+
+    // the proposals of the block are the last ones, they're enacted at the end
+    // of the epoch they target
+    let target = updates[updates.len() - delta.new_pparams.len()..]
+        .iter()
+        .map(|x| x.epoch())
+        .max()
+        .unwrap_or_default();
+
+    let pparams = pparams::fold_pparams(genesis, &updates, target + 1)?;
+
+    Ok((delta, Some(pparams)))
+}
+
+pub fn apply_block_batch<'a>(
+    blocks: impl IntoIterator<Item = &'a MultiEraBlock<'a>>,
+    store: &mut LedgerStore,
+    network: &NetworkParams,
+    limits: &DeltaLimits,
+) -> Result<(), LedgerError> {
+    let mut deltas: Vec<LedgerDelta> = vec![];
+
+    for block in blocks {
+        let context = load_slice_for_block(block, store, &deltas)?;
+        let delta = compute_delta(block, context).map_err(LedgerError::BrokenInvariant)?;
+
+        deltas.push(delta);
+    }
+
+    store.apply_with_limits(&deltas, limits)?;
+
+    let tip = deltas
+        .last()
+        .and_then(|x| x.new_position.as_ref())
+        .map(|x| x.0)
+        .unwrap();
+
+    let to_finalize = network.latest_immutable_slot(tip);
+    store.finalize(to_finalize)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+
+    #[test]
+    fn replayed_block_is_not_committed() {
+        let path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("test_data")
+            .join("alonzo27.block");
+
+        let cbor = hex::decode(std::fs::read_to_string(path).unwrap()).unwrap();
+        let block = MultiEraBlock::decode(&cbor).unwrap();
+
+        let produced: HashSet<_> = block
+            .txs()
+            .iter()
+            .flat_map(|tx| {
+                tx.produces()
+                    .into_iter()
+                    .map(move |(idx, _)| TxoRef(tx.hash(), idx as u32))
+            })
+            .collect();
+
+        // inputs coming from earlier blocks, chained ones are resolved within
+        // the block itself
+        let inputs: Vec<_> = block
+            .txs()
+            .iter()
+            .flat_map(MultiEraTx::consumes)
+            .map(|x| TxoRef(*x.hash(), x.index() as u32))
+            .filter(|x| !produced.contains(x))
+            .collect();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+
+        let pre_state = inputs
+            .iter()
+            .map(|x| (x.clone(), fake_utxo(&address, 1_000_000)))
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, pre_state)]).unwrap();
+
+        let genesis = pparams::testing::mainnet_genesis();
+
+        let (delta, pparams) = replay_block(&store, &block, &genesis).unwrap();
+
+        assert_eq!(
+            delta.new_position,
+            Some(ChainPoint(block.slot(), block.hash()))
+        );
+
+        let consumed: HashSet<_> = delta.consumed_utxo.keys().cloned().collect();
+        assert_eq!(consumed, inputs.iter().cloned().collect());
+
+        for body in delta.consumed_utxo.values() {
+            assert_eq!(body, &fake_utxo(&address, 1_000_000));
+        }
+
+        for txo in delta.produced_utxo.keys() {
+            assert!(produced.contains(txo));
+        }
+
+        // the block carries no proposals
+        assert!(delta.new_pparams.is_empty());
+        assert!(pparams.is_none());
+
+        // the store is left as it was
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(1, slot_to_hash(1)))
+        );
+        assert_eq!(store.get_utxos(inputs.clone()).unwrap().len(), inputs.len());
+    }
+
+    #[test]
+    fn replayed_block_enacts_its_proposals() {
+        let dir = "src/ledger/pparams/test_data/mainnet/update_proposal_blocks";
+
+        let raw: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|x| std::fs::read(x.unwrap().path()).unwrap())
+            .collect();
+
+        let mut blocks: Vec<_> = raw
+            .iter()
+            .map(|x| crate::ledger::decode_block(x).unwrap())
+            .collect();
+
+        blocks.sort_by_key(|x| x.slot());
+
+        // proposes a max block body size of 90112 for epoch 334
+        let index = blocks.iter().position(|x| x.slot() == 59088562).unwrap();
+        let block = &blocks[index];
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let inputs: Vec<_> = block
+            .txs()
+            .iter()
+            .flat_map(MultiEraTx::consumes)
+            .map(|x| TxoRef(*x.hash(), x.index() as u32))
+            .collect();
+
+        let pre_state = inputs
+            .iter()
+            .map(|x| (x.clone(), fake_utxo(&address, 1_000_000)))
+            .collect();
+
+        // the proposals of earlier blocks, as the ledger would hold them
+        let history: Vec<_> = blocks[..index]
+            .iter()
+            .map(|x| {
+                let mut new_pparams: Vec<_> = x
+                    .txs()
+                    .iter()
+                    .filter_map(MultiEraTx::update)
+                    .map(|update| PParamsBody(x.era(), update.encode()))
+                    .collect();
+
+                new_pparams.extend(
+                    x.update()
+                        .map(|update| PParamsBody(x.era(), update.encode())),
+                );
+
+                LedgerDelta {
+                    new_position: Some(ChainPoint(x.slot(), x.hash())),
+                    new_pparams,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+        store.apply(&[fake_produce_delta(1, pre_state)]).unwrap();
+        store.apply(&history).unwrap();
+
+        let committed = store.get_pparams(BlockSlot::MAX).unwrap();
+
+        let genesis = pparams::testing::mainnet_genesis();
+
+        let (delta, pparams) = replay_block(&store, block, &genesis).unwrap();
+
+        assert_eq!(delta.new_pparams.len(), 1);
+
+        let Some(MultiEraProtocolParameters::Alonzo(pparams)) = pparams else {
+            panic!("expected alonzo params");
+        };
+
+        assert_eq!(pparams.max_block_body_size, 90112);
+        assert_eq!(pparams.protocol_version, (6, 0));
+
+        // the proposal isn't committed
+        assert_eq!(store.get_pparams(BlockSlot::MAX).unwrap(), committed);
+    }
+
+    #[test]
+    fn input_check_rejects_unresolved_inputs() {
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = vec![(fake_txo(1, 0), fake_utxo(&address, 1_000_000))];
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let limits = DeltaLimits {
+            check_inputs: true,
+            ..Default::default()
+        };
+
+        let spend = |slot: u64, txo: TxoRef, lovelace: u64| {
+            let mut delta = fake_produce_delta(
+                slot,
+                vec![(fake_txo(slot as u8, 0), fake_utxo(&address, 1))],
+            );
+            delta
+                .consumed_utxo
+                .insert(txo, fake_utxo(&address, lovelace));
+            delta
+        };
+
+        // nothing ever produced this one
+        match store.apply_with_limits(&[spend(2, fake_txo(9, 0), 1_000_000)], &limits) {
+            Err(LedgerError::BrokenInvariant(BrokenInvariant::UnresolvedInput(point, txo))) => {
+                assert_eq!(point, Some(ChainPoint(2, slot_to_hash(2))));
+                assert_eq!(txo, fake_txo(9, 0));
+            }
+            _ => panic!("expected the unresolved input to be rejected"),
+        }
+
+        // the value doesn't match what's stored
+        assert!(matches!(
+            store.apply_with_limits(&[spend(2, fake_txo(1, 0), 5)], &limits),
+            Err(LedgerError::BrokenInvariant(
+                BrokenInvariant::MismatchedInput(..)
+            ))
+        ));
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(1, slot_to_hash(1)))
+        );
+
+        // inputs produced earlier in the same batch resolve
+        let batch = [
+            spend(2, fake_txo(1, 0), 1_000_000),
+            spend(3, fake_txo(2, 0), 1),
+        ];
+
+        store.apply_with_limits(&batch, &limits).unwrap();
+
+        // without the check, the store trusts the delta
+        let unchecked = spend(4, fake_txo(9, 0), 1_000_000);
+        store.apply(&[unchecked]).unwrap();
+    }
+
+    #[test]
+    fn chunked_utxos_match_single_tx() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced: Vec<_> = (0..10u8)
+            .map(|x| (fake_txo(x, 0), fake_utxo(&address, x as u64 * 1_000_000)))
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let mut refs: Vec<_> = (0..10u8).map(|x| fake_txo(x, 0)).collect();
+        refs.push(fake_txo(99, 0));
+
+        let single = store.get_utxos(refs.clone()).unwrap();
+        assert_eq!(single.len(), 10);
+
+        for chunk_size in [0, 1, 3, 10, 100] {
+            let chunked = store.get_utxos_chunked(refs.clone(), chunk_size).unwrap();
+            assert_eq!(chunked, single);
+        }
+    }
+
+    #[test]
+    fn overlapping_delta_is_rejected() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = (fake_txo(1, 0), fake_utxo(&address, 1));
+
+        let delta = LedgerDelta {
+            consumed_utxo: [utxo.clone()].into(),
+            ..fake_produce_delta(1, vec![utxo])
+        };
+
+        match store.apply(&[delta]) {
+            Err(LedgerError::BrokenInvariant(BrokenInvariant::ProducedAndConsumed(txo))) => {
+                assert_eq!(txo, fake_txo(1, 0));
+            }
+            x => panic!("expected overlap to be rejected, got {x:?}"),
+        }
+
+        // nothing should have been written
+        assert!(store.is_empty().unwrap());
+    }
+
+    #[test]
+    fn rollback_past_immutable_is_rejected() {
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        let undo = |slot| LedgerDelta {
+            undone_position: Some(ChainPoint(slot, slot_to_hash(slot))),
+            ..Default::default()
+        };
+
+        for mut store in stores {
+            let deltas: Vec<_> = (1..=5)
+                .map(|slot| fake_produce_delta(slot, vec![]))
+                .collect();
+
+            store.apply(&deltas).unwrap();
+            store.finalize(3).unwrap();
+
+            let err = store.rollback(&[undo(5), undo(2)]).unwrap_err();
+
+            assert!(matches!(
+                err,
+                LedgerError::RollbackTooDeep {
+                    slot: 2,
+                    boundary: Some(3)
+                }
+            ));
+
+            // the batch is rejected as a whole
+            assert_eq!(
+                store.cursor().unwrap(),
+                Some(ChainPoint(5, slot_to_hash(5)))
+            );
+
+            store.rollback(&[undo(5), undo(4)]).unwrap();
+            assert_eq!(
+                store.cursor().unwrap(),
+                Some(ChainPoint(3, slot_to_hash(3)))
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_carries_block_height() {
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            redb::LedgerStore::in_memory_v2_light().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        for mut store in stores {
+            let deltas: Vec<_> = (1..=3)
+                .map(|slot| LedgerDelta {
+                    new_height: Some(slot * 10),
+                    ..fake_produce_delta(slot, vec![])
+                })
+                .collect();
+
+            store.apply(&deltas).unwrap();
+
+            assert_eq!(
+                store.cursor_with_height().unwrap(),
+                Some((ChainPoint(3, slot_to_hash(3)), Some(30)))
+            );
+
+            store
+                .rollback(&[LedgerDelta {
+                    undone_position: Some(ChainPoint(3, slot_to_hash(3))),
+                    ..Default::default()
+                }])
+                .unwrap();
+
+            assert_eq!(
+                store.cursor_with_height().unwrap(),
+                Some((ChainPoint(2, slot_to_hash(2)), Some(20)))
+            );
+
+            // a repaired cursor doesn't know the height of its block
+            store
+                .repair_cursor(&ChainPoint(2, slot_to_hash(2)))
+                .unwrap();
+
+            assert_eq!(
+                store.cursor_with_height().unwrap(),
+                Some((ChainPoint(2, slot_to_hash(2)), None))
+            );
+        }
+
+        // the immutable tip of a split store keeps the height once finalized
+        let mut store: LedgerStore = redb::split::LedgerStore::in_memory().unwrap().into();
+
+        let delta = LedgerDelta {
+            new_height: Some(10),
+            ..fake_produce_delta(1, vec![])
+        };
+
+        store.apply(&[delta]).unwrap();
+        store.finalize(2).unwrap();
+
+        assert_eq!(
+            store.cursor_with_height().unwrap(),
+            Some((ChainPoint(1, slot_to_hash(1)), Some(10)))
+        );
+    }
+
+    #[test]
+    fn replayed_utxo_is_idempotent() {
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = (fake_txo(1, 0), fake_utxo(&address, 1));
+
+        for mut store in stores {
+            store
+                .apply(&[fake_produce_delta(1, vec![utxo.clone()])])
+                .unwrap();
+
+            store
+                .apply(&[fake_produce_delta(2, vec![utxo.clone()])])
+                .unwrap();
+
+            let found = store.get_utxos(vec![utxo.0.clone()]).unwrap();
+            assert_eq!(found.get(&utxo.0), Some(&utxo.1));
+        }
+    }
+
+    #[test]
+    fn conflicting_utxo_is_rejected() {
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let original = (fake_txo(1, 0), fake_utxo(&address, 1));
+        let conflicting = (fake_txo(1, 0), fake_utxo(&address, 2));
+
+        for mut store in stores {
+            store
+                .apply(&[fake_produce_delta(1, vec![original.clone()])])
+                .unwrap();
+
+            match store.apply(&[fake_produce_delta(2, vec![conflicting.clone()])]) {
+                Err(LedgerError::BrokenInvariant(BrokenInvariant::ConflictingUtxo(txo))) => {
+                    assert_eq!(txo, fake_txo(1, 0));
+                }
+                x => panic!("expected conflict to be rejected, got {x:?}"),
+            }
+
+            // the stored body is left untouched
+            let found = store.get_utxos(vec![original.0.clone()]).unwrap();
+            assert_eq!(found.get(&original.0), Some(&original.1));
+        }
+    }
+
+    #[test]
+    fn bulk_load_matches_apply() {
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let other = fake_enterprise_address(&[2u8; 28]);
+
+        let deltas = || {
+            let mut deltas: Vec<_> = (1..=10u8)
+                .map(|x| {
+                    fake_produce_delta(
+                        x as u64,
+                        vec![(fake_txo(x, 0), fake_utxo(&address, x as u64))],
+                    )
+                })
+                .collect();
+
+            // a later block spends some of the earlier outputs
+            deltas.push(LedgerDelta {
+                new_position: Some(ChainPoint(11, slot_to_hash(11))),
+                produced_utxo: [(fake_txo(11, 0), fake_utxo(&other, 11))].into(),
+                consumed_utxo: (1..=3u8)
+                    .map(|x| (fake_txo(x, 0), fake_utxo(&address, x as u64)))
+                    .collect(),
+                ..Default::default()
+            });
+
+            deltas
+        };
+
+        let mut applied: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+        applied.apply(&deltas()).unwrap();
+        applied.finalize(12).unwrap();
+
+        let mut loaded: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+        assert_eq!(loaded.bulk_load(deltas(), 3).unwrap(), 11);
+
+        assert_eq!(
+            loaded.cursor().unwrap(),
+            Some(ChainPoint(11, slot_to_hash(11)))
+        );
+
+        let refs: Vec<_> = (1..=11u8).map(|x| fake_txo(x, 0)).collect();
+        assert_eq!(
+            loaded.get_utxos(refs.clone()).unwrap(),
+            applied.get_utxos(refs).unwrap()
+        );
+
+        // indexes are rebuilt at the end of the load
+        for addr in [&address, &other] {
+            assert_eq!(
+                loaded.get_utxo_by_address(addr).unwrap(),
+                applied.get_utxo_by_address(addr).unwrap()
+            );
+        }
+
+        assert_eq!(loaded.get_utxo_by_address(&address).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn query_cache_follows_applies() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+        store = store.with_query_cache(10);
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        store
+            .apply(&[fake_produce_delta(
+                1,
+                vec![(fake_txo(1, 0), fake_utxo(&address, 1))],
+            )])
+            .unwrap();
+
+        let first = store.get_utxo_by_address(&address).unwrap();
+        assert_eq!(first.len(), 1);
+
+        // served from the cache, same result
+        assert_eq!(store.get_utxo_by_address(&address).unwrap(), first);
+
+        store
+            .apply(&[fake_produce_delta(
+                2,
+                vec![(fake_txo(2, 0), fake_utxo(&address, 2))],
+            )])
+            .unwrap();
+
+        assert_eq!(store.get_utxo_by_address(&address).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn decode_cache_follows_applies() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+        store = store.with_decode_cache(10);
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        store
+            .apply(&[fake_produce_delta(
+                1,
+                vec![(fake_txo(1, 0), fake_utxo(&address, 1))],
+            )])
+            .unwrap();
+
+        assert_eq!(store.balance_by_address(&address).unwrap().lovelace, 1);
+
+        // decoded once, then served from the cache
+        assert_eq!(store.balance_by_address(&address).unwrap().lovelace, 1);
+
+        let stats = store.decode_cache.as_ref().unwrap().stats();
+        assert_eq!((stats.misses, stats.hits), (1, 1));
+
+        let produce = fake_produce_delta(2, vec![(fake_txo(2, 0), fake_utxo(&address, 2))]);
+        store.apply(std::slice::from_ref(&produce)).unwrap();
+
+        assert_eq!(store.balance_by_address(&address).unwrap().lovelace, 3);
+
+        store
+            .rollback(&[LedgerDelta {
+                undone_position: produce.new_position.clone(),
+                undone_utxo: produce.produced_utxo.clone(),
+                ..Default::default()
+            }])
+            .unwrap();
+
+        assert!(store
+            .decode_cache
+            .as_ref()
+            .unwrap()
+            .get(&fake_txo(2, 0))
+            .is_none());
+        assert_eq!(store.balance_by_address(&address).unwrap().lovelace, 1);
+    }
+
+    #[test]
+    fn tx_inputs_are_grouped() {
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = (0..4u8)
+            .map(|x| (fake_txo(x, 0), fake_utxo(&address, x as u64)))
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let resolved = store
+            .resolve_tx_inputs(
+                &[fake_txo(0, 0), fake_txo(1, 0)],
+                &[fake_txo(1, 0)],
+                &[fake_txo(2, 0), fake_txo(99, 0)],
+            )
+            .unwrap();
+
+        assert_eq!(
+            resolved.spend.keys().collect::<Vec<_>>(),
+            vec![&fake_txo(0, 0), &fake_txo(1, 0)]
+        );
+
+        assert_eq!(
+            resolved.collateral.get(&fake_txo(1, 0)),
+            Some(&fake_utxo(&address, 1))
+        );
+
+        // unknown refs are left out of their group
+        assert_eq!(
+            resolved.reference.keys().collect::<Vec<_>>(),
+            vec![&fake_txo(2, 0)]
+        );
+    }
+
+    #[test]
+    fn observers_receive_committed_deltas() {
+        use std::sync::Mutex;
+
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let seen = Arc::new(Mutex::new(vec![]));
+
+        let seen_ = seen.clone();
+        store.register_observer(Arc::new(move |deltas: &[LedgerDelta]| {
+            let mut seen = seen_.lock().unwrap();
+            seen.extend(deltas.iter().filter_map(|x| x.new_position.clone()));
+        }));
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let delta = fake_produce_delta(1, vec![(fake_txo(1, 0), fake_utxo(&address, 1))]);
+        store.apply(&[delta]).unwrap();
+
+        assert_eq!(*seen.lock().unwrap(), vec![ChainPoint(1, slot_to_hash(1))]);
+
+        // a rejected batch shouldn't reach the observers
+        let limits = DeltaLimits {
+            max_produced: 0,
+            max_consumed: 0,
+            ..Default::default()
+        };
+
+        let delta = fake_produce_delta(2, vec![(fake_txo(2, 0), fake_utxo(&address, 1))]);
+        assert!(store.apply_with_limits(&[delta], &limits).is_err());
+
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn update_proposals_by_epoch() {
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+
+        let deltas = [
+            LedgerDelta {
+                new_position: Some(ChainPoint(1, slot_to_hash(1))),
+                new_pparams: vec![shelley_update_block(5, &[(1, 3), (2, 4)])],
+                ..Default::default()
+            },
+            LedgerDelta {
+                new_position: Some(ChainPoint(2, slot_to_hash(2))),
+                new_pparams: vec![shelley_update_block(6, &[(3, 5)])],
+                ..Default::default()
+            },
+        ];
+
+        store.apply(&deltas).unwrap();
+
+        let proposals = store.get_update_proposals_by_epoch(5).unwrap();
+        assert_eq!(proposals.len(), 2);
+
+        for ((key, update), (expected_key, expected_major)) in
+            proposals.iter().zip([(1u8, 3u64), (2, 4)])
+        {
+            assert_eq!(*key, pparams::GenesisKey::new([expected_key; 28]));
+            assert_eq!(update.epoch(), 5);
+            assert_eq!(
+                update.first_proposed_protocol_version(),
+                Some((expected_major, 0))
+            );
+        }
+
+        assert_eq!(store.get_update_proposals_by_epoch(6).unwrap().len(), 1);
+        assert!(store.get_update_proposals_by_epoch(7).unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeated_pparams_are_deduplicated() {
+        let mut store: LedgerStore = memory::LedgerStore::new().into();
+
+        let raw = vec![
+            shelley_update_block(4, &[(1, 3)]),
+            shelley_update_block(4, &[(1, 3)]),
+            shelley_update_block(6, &[(1, 4)]),
+            shelley_update_block(6, &[(1, 4)]),
+        ];
+
+        let deltas: Vec<_> = raw
+            .iter()
+            .enumerate()
+            .map(|(i, body)| LedgerDelta {
+                new_position: Some(ChainPoint(i as u64 + 1, slot_to_hash(i as u64 + 1))),
+                new_pparams: vec![body.clone()],
+                ..Default::default()
+            })
+            .collect();
+
+        store.apply(&deltas).unwrap();
+
+        let deduped = store.get_pparams(BlockSlot::MAX).unwrap();
+        assert_eq!(
+            deduped,
+            vec![
+                shelley_update_block(4, &[(1, 3)]),
+                shelley_update_block(6, &[(1, 4)])
+            ]
+        );
+
+        let genesis = pparams::testing::mainnet_genesis();
+
+        let fold = |bodies: &[PParamsBody]| {
+            let updates: Vec<_> = bodies
+                .iter()
+                .map(|PParamsBody(era, cbor)| MultiEraUpdate::decode_for_era(*era, cbor).unwrap())
+                .collect();
+
+            let pparams = pparams::fold_pparams(&genesis, &updates, 8).unwrap();
+            format!("{pparams:?}")
+        };
+
+        assert_eq!(fold(&raw), fold(&deduped));
+    }
+
+    #[test]
+    fn streamed_pparams_match_collected() {
+        let delta = |slot: u64, body: PParamsBody| LedgerDelta {
+            new_pparams: vec![body],
+            ..fake_produce_delta(slot, vec![])
+        };
+
+        let genesis = pparams::testing::mainnet_genesis();
+
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            redb::split::LedgerStore::in_memory().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        for mut store in stores {
+            let deltas = vec![
+                delta(1, shelley_update_block(4, &[(1, 3)])),
+                delta(2, shelley_update_block(4, &[(1, 3)])),
+                delta(3, shelley_update_block(6, &[(1, 4)])),
+                delta(4, shelley_update_block(7, &[(1, 5)])),
+            ];
+
+            store.apply(&deltas).unwrap();
+
+            // leaves part of the rows in the immutable db of the split store
+            store.finalize(3).unwrap();
+
+            for until in [0, 2, 3, 5, BlockSlot::MAX] {
+                let streamed: Vec<_> = store
+                    .stream_pparams(until)
+                    .unwrap()
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+
+                assert_eq!(streamed, store.get_pparams(until).unwrap());
+            }
+
+            let collected = store.get_pparams(BlockSlot::MAX).unwrap();
+            let stream = store.stream_pparams(BlockSlot::MAX).unwrap();
+
+            // the stream reads from the snapshot it was opened on
+            store
+                .apply(&[delta(5, shelley_update_block(8, &[(1, 6)]))])
+                .unwrap();
+
+            let updates: Vec<_> = collected
+                .iter()
+                .map(|PParamsBody(era, cbor)| MultiEraUpdate::decode_for_era(*era, cbor).unwrap())
+                .collect();
+
+            let expected = pparams::fold_pparams(&genesis, &updates, 9).unwrap();
+            let streamed = pparams::fold_pparams_streamed(&genesis, stream, 9).unwrap();
+
+            assert_eq!(format!("{streamed:?}"), format!("{expected:?}"));
+            assert_eq!(expected.protocol_version(), 5);
+        }
+    }
+
+    #[test]
+    fn utxos_fetched_by_tx_hash() {
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            redb::LedgerStore::in_memory_v2_light().unwrap().into(),
+            redb::split::LedgerStore::in_memory().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        for mut store in stores {
+            // txs with neighbouring hashes, to check the scan stops at the prefix
+            let delta = fake_produce_delta(
+                1,
+                vec![
+                    (fake_txo(1, 0), fake_utxo(&address, 1)),
+                    (fake_txo(2, 0), fake_utxo(&address, 2)),
+                    (fake_txo(2, 1), fake_utxo(&address, 3)),
+                    (fake_txo(2, 7), fake_utxo(&address, 4)),
+                    (fake_txo(3, 0), fake_utxo(&address, 5)),
+                ],
+            );
+
+            store.apply(&[delta]).unwrap();
+
+            let utxos = store.get_utxos_by_tx(&[2u8; 32]).unwrap();
+
+            assert_eq!(
+                utxos.keys().cloned().collect::<Vec<_>>(),
+                vec![fake_txo(2, 0), fake_txo(2, 1), fake_txo(2, 7)]
+            );
+
+            assert_eq!(utxos[&fake_txo(2, 7)], fake_utxo(&address, 4));
+
+            assert!(store.get_utxos_by_tx(&[9u8; 32]).unwrap().is_empty());
+
+            assert!(matches!(
+                store.get_utxos_by_tx(&[2u8; 28]),
+                Err(LedgerError::InvalidTxHash(28))
+            ));
+        }
+    }
+
+    #[test]
+    fn total_lovelace_of_live_utxos() {
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            redb::LedgerStore::in_memory_v2_light().unwrap().into(),
+            redb::split::LedgerStore::in_memory().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        for mut store in stores {
+            assert_eq!(store.total_lovelace().unwrap(), 0);
+
+            let first = fake_produce_delta(
+                1,
+                vec![
+                    (fake_txo(1, 0), fake_utxo(&address, 1_000_000)),
+                    (fake_txo(1, 1), fake_utxo(&address, 2_000_000)),
+                ],
+            );
+
+            let mut second = fake_produce_delta(
+                2,
+                vec![(fake_txo(2, 0), fake_utxo(&address, 45_000_000_000_000_000))],
+            );
+
+            second
+                .consumed_utxo
+                .insert(fake_txo(1, 0), fake_utxo(&address, 1_000_000));
+
+            let third = fake_produce_delta(3, vec![(fake_txo(3, 0), fake_utxo(&address, 3))]);
+
+            store.apply(&[first, second]).unwrap();
+
+            // the split store moves the first slot to the immutable db
+            store.finalize(2).unwrap();
+            store.apply(&[third]).unwrap();
+
+            // the consumed utxo is skipped before and after it's compacted
+            assert_eq!(store.total_lovelace().unwrap(), 45_000_000_002_000_003);
+
+            store.finalize(BlockSlot::MAX).unwrap();
+            assert_eq!(store.total_lovelace().unwrap(), 45_000_000_002_000_003);
+
+            let utxos: Vec<_> = store.iter_utxos().unwrap().try_collect().unwrap();
+            assert_eq!(utxos.len(), 3);
+        }
+    }
+
+    #[test]
+    fn asset_total_across_utxos() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let policy = [5u8; 28];
+        let other_policy = [6u8; 28];
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = vec![
+            (
+                fake_txo(1, 0),
+                fake_utxo_with_assets(&address, 1, &[(policy, b"coin", u64::MAX)]),
+            ),
+            (
+                fake_txo(2, 0),
+                fake_utxo_with_assets(&address, 1, &[(policy, b"coin", u64::MAX)]),
+            ),
+            (
+                fake_txo(3, 0),
+                fake_utxo_with_assets(
+                    &address,
+                    1,
+                    &[(policy, b"coin", 10), (other_policy, b"coin", 1_000)],
+                ),
+            ),
+            (
+                fake_txo(4, 0),
+                fake_utxo_with_assets(&address, 1, &[(policy, b"other", 7)]),
+            ),
+        ];
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let total = store.asset_total_in_utxos(&policy, b"coin").unwrap();
+        assert_eq!(total, u64::MAX as u128 * 2 + 10);
+
+        let total = store.asset_total_in_utxos(&policy, b"other").unwrap();
+        assert_eq!(total, 7);
+
+        let total = store.asset_total_in_utxos(&policy, b"missing").unwrap();
+        assert_eq!(total, 0);
+    }
+
+    #[test]
+    fn balance_of_base_address() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let policy = [5u8; 28];
+        let address = fake_base_address(&[1u8; 28], &[2u8; 28]);
+        let other = fake_base_address(&[3u8; 28], &[2u8; 28]);
+
+        let produced = vec![
+            (fake_txo(1, 0), fake_utxo(&address, 2_000_000)),
+            (
+                fake_txo(2, 0),
+                fake_utxo_with_assets(
+                    &address,
+                    1_500_000,
+                    &[(policy, b"coin", 100), (policy, b"other", 1)],
+                ),
+            ),
+            (
+                fake_txo(3, 0),
+                fake_utxo_with_assets(&address, 1_000_000, &[(policy, b"coin", 20)]),
+            ),
+            (fake_txo(4, 0), fake_utxo(&other, 9_000_000)),
+        ];
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let balance = store.balance_by_address(&address).unwrap();
+
+        assert_eq!(balance.lovelace, 4_500_000);
+
+        let expected = HashMap::from([
+            (
+                (pallas::crypto::hash::Hash::new(policy), b"coin".to_vec()),
+                120,
+            ),
+            (
+                (pallas::crypto::hash::Hash::new(policy), b"other".to_vec()),
+                1,
+            ),
+        ]);
+
+        assert_eq!(balance.assets, expected);
+    }
+
+    #[test]
+    fn amount_sum_fails_instead_of_wrapping() {
+        assert_eq!(add_amount(u128::MAX - 1, 1).unwrap(), u128::MAX);
+
+        assert!(matches!(
+            add_amount(u128::MAX - 1, 2),
+            Err(LedgerError::AmountOverflow)
+        ));
+
+        let total = add_amount(u64::MAX as u128, u64::MAX).unwrap();
+        assert_eq!(total, u64::MAX as u128 * 2);
+    }
+
+    #[test]
+    fn genesis_utxos_are_resolvable() {
+        use pallas::ledger::configs::{alonzo, byron, shelley};
+
+        let root = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("examples")
+            .join("sync-preview");
+
+        let byron = byron::from_file(&root.join("byron.json")).unwrap();
+        let mut shelley = shelley::from_file(&root.join("shelley.json")).unwrap();
+        let alonzo = alonzo::from_file(&root.join("alonzo.json")).unwrap();
+
+        let funded = fake_enterprise_address(&[9u8; 28]);
+        shelley.initial_funds = Some([(hex::encode(&funded), 5_000_000)].into());
+
+        let genesis = pparams::Genesis {
+            byron: &byron,
+            shelley: &shelley,
+            alonzo: &alonzo,
+        };
+
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+        store.apply_genesis(&genesis).unwrap();
+
+        // a utxo of the byron distribution
+        let byron_txo = TxoRef(
+            "4843cf2e582b2f9ce37600e5ab4cc678991f988f8780fed05407f9537f7712bd"
+                .parse()
+                .unwrap(),
+            0,
+        );
+
+        // a utxo of the shelley initial funds, indexed by the pseudo tx id
+        let shelley_txo = TxoRef(pallas::crypto::hash::Hasher::<256>::hash(&funded), 0);
+
+        let utxos = store
+            .get_utxos(vec![byron_txo.clone(), shelley_txo.clone()])
+            .unwrap();
+
+        let output = MultiEraOutput::try_from(utxos.get(&byron_txo).unwrap()).unwrap();
+        assert_eq!(output.lovelace_amount(), 30_000_000_000_000_000);
+
+        let output = MultiEraOutput::try_from(utxos.get(&shelley_txo).unwrap()).unwrap();
+        assert_eq!(output.address().unwrap().to_vec(), funded);
+        assert_eq!(output.lovelace_amount(), 5_000_000);
+
+        // initial funds are also reachable through the address index
+        let refs = store.get_utxo_by_address(&funded).unwrap();
+        assert!(refs.contains(&shelley_txo));
+    }
+
+    #[test]
+    fn stake_snapshot_by_credential() {
+        let stores: Vec<LedgerStore> = vec![
+            redb::LedgerStore::in_memory_v2().unwrap().into(),
+            memory::LedgerStore::new().into(),
+        ];
+
+        let first = fake_base_address(&[1u8; 28], &[2u8; 28]);
+        let second = fake_base_address(&[3u8; 28], &[2u8; 28]);
+        let other_stake = fake_base_address(&[1u8; 28], &[4u8; 28]);
+        let enterprise = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = vec![
+            (fake_txo(1, 0), fake_utxo(&first, 1_000_000)),
+            (fake_txo(2, 0), fake_utxo(&second, 2_000_000)),
+            (fake_txo(3, 0), fake_utxo(&other_stake, 5_000_000)),
+            (fake_txo(4, 0), fake_utxo(&enterprise, 7_000_000)),
+            (fake_txo(5, 0), fake_utxo(&first, 9_000_000)),
+        ];
+
+        let expected = HashMap::from([
+            (StakeCredential::AddrKeyhash([2u8; 28].into()), 3_000_000),
+            (StakeCredential::AddrKeyhash([4u8; 28].into()), 5_000_000),
+        ]);
+
+        for mut store in stores {
+            store
+                .apply(&[fake_produce_delta(1, produced.clone())])
+                .unwrap();
+
+            let mut spend = fake_produce_delta(2, vec![]);
+
+            spend
+                .consumed_utxo
+                .insert(fake_txo(5, 0), fake_utxo(&first, 9_000_000));
+
+            store.apply(&[spend]).unwrap();
+
+            assert_eq!(store.stake_snapshot().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn address_match_modes() {
+        let mut store: LedgerStore = redb::LedgerStore::in_memory_v2().unwrap().into();
+
+        let base = fake_base_address(&[1u8; 28], &[2u8; 28]);
+        let same_payment = fake_base_address(&[1u8; 28], &[3u8; 28]);
+        let enterprise = fake_enterprise_address(&[1u8; 28]);
+        let same_stake = fake_base_address(&[4u8; 28], &[2u8; 28]);
+
+        let produced = vec![
+            (fake_txo(1, 0), fake_utxo(&base, 1_000_000)),
+            (fake_txo(2, 0), fake_utxo(&same_payment, 1_000_000)),
+            (fake_txo(3, 0), fake_utxo(&enterprise, 1_000_000)),
+            (fake_txo(4, 0), fake_utxo(&same_stake, 1_000_000)),
+        ];
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let matching = |address: &[u8], mode| {
+            store
+                .get_utxos_by_address(address, mode)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(matching(&base, AddressMatch::Exact), vec![fake_txo(1, 0)]);
+
+        assert_eq!(
+            matching(&base, AddressMatch::PaymentCredential),
+            vec![fake_txo(1, 0), fake_txo(2, 0), fake_txo(3, 0)]
+        );
+
+        assert_eq!(
+            matching(&base, AddressMatch::StakeCredential),
+            vec![fake_txo(1, 0), fake_txo(4, 0)]
+        );
+
+        // enterprise addresses have no stake credential to match on
+        assert!(matching(&enterprise, AddressMatch::StakeCredential).is_empty());
+    }
 }
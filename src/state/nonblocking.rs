@@ -0,0 +1,210 @@
+use std::sync::mpsc;
+use tokio::sync::oneshot;
+
+use super::{LedgerError, LedgerStore};
+use crate::ledger::*;
+
+enum WriteOp {
+    Apply(Vec<LedgerDelta>),
+    Finalize(BlockSlot),
+    Flush,
+}
+
+type WriteRequest = (WriteOp, oneshot::Sender<Result<(), LedgerError>>);
+
+fn write_worker(mut store: LedgerStore, requests: mpsc::Receiver<WriteRequest>) {
+    for (op, reply) in requests {
+        let result = match op {
+            WriteOp::Apply(deltas) => store.apply(&deltas),
+            WriteOp::Finalize(until) => store.finalize(until),
+            WriteOp::Flush => store.flush(),
+        };
+
+        // the caller might have given up waiting, nothing to do about it
+        let _ = reply.send(result);
+    }
+}
+
+/// An async façade over the ledger store
+///
+/// Ledger store operations do blocking I/O, calling them directly from an
+/// async handler stalls the executor thread for the duration of the call.
+/// This wrapper runs reads on the tokio blocking pool via
+/// `spawn_blocking`, so it must be used from within a tokio runtime.
+///
+/// Writes are serialized through a single dedicated worker thread that owns
+/// its own handle to the store, so concurrent callers never contend on the
+/// write transaction and deltas are applied in the order they were submitted.
+/// The worker stops once every clone of the façade has been dropped.
+#[derive(Clone)]
+pub struct AsyncLedgerStore {
+    store: LedgerStore,
+    writer: mpsc::Sender<WriteRequest>,
+}
+
+impl AsyncLedgerStore {
+    pub fn new(store: LedgerStore) -> Self {
+        let (writer, requests) = mpsc::channel();
+
+        let worker = store.clone();
+        std::thread::Builder::new()
+            .name("ledger-writer".into())
+            .spawn(move || write_worker(worker, requests))
+            .expect("can't spawn ledger writer thread");
+
+        Self { store, writer }
+    }
+
+    /// Access to the underlying synchronous store
+    pub fn inner(&self) -> &LedgerStore {
+        &self.store
+    }
+
+    async fn blocking<T, F>(&self, op: F) -> Result<T, LedgerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&LedgerStore) -> Result<T, LedgerError> + Send + 'static,
+    {
+        let store = self.store.clone();
+
+        match tokio::task::spawn_blocking(move || op(&store)).await {
+            Ok(x) => x,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            Err(_) => Err(LedgerError::WorkerUnavailable),
+        }
+    }
+
+    async fn write(&self, op: WriteOp) -> Result<(), LedgerError> {
+        let (reply, response) = oneshot::channel();
+
+        self.writer
+            .send((op, reply))
+            .map_err(|_| LedgerError::WorkerUnavailable)?;
+
+        response.await.map_err(|_| LedgerError::WorkerUnavailable)?
+    }
+
+    pub async fn cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
+        self.blocking(|x| x.cursor()).await
+    }
+
+    pub async fn get_pparams(&self, until: BlockSlot) -> Result<Vec<PParamsBody>, LedgerError> {
+        self.blocking(move |x| x.get_pparams(until)).await
+    }
+
+    pub async fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, LedgerError> {
+        self.blocking(move |x| x.get_utxos(refs)).await
+    }
+
+    pub async fn get_utxo(&self, txo: TxoRef) -> Result<Option<EraCbor>, LedgerError> {
+        self.blocking(move |x| x.get_utxo(txo)).await
+    }
+
+    pub async fn get_utxo_by_address(&self, address: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_address(&address))
+            .await
+    }
+
+    pub async fn get_utxo_by_payment(&self, payment: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_payment(&payment))
+            .await
+    }
+
+    pub async fn get_utxo_by_stake(&self, stake: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_stake(&stake)).await
+    }
+
+    pub async fn get_utxo_by_policy(&self, policy: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_policy(&policy)).await
+    }
+
+    pub async fn get_utxo_by_asset(&self, asset: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_asset(&asset)).await
+    }
+
+    pub async fn get_utxo_by_datum_hash(&self, hash: Vec<u8>) -> Result<UtxoSet, LedgerError> {
+        self.blocking(move |x| x.get_utxo_by_datum_hash(&hash))
+            .await
+    }
+
+    /// Queues the deltas on the writer worker and waits for them to be applied
+    pub async fn apply(&self, deltas: Vec<LedgerDelta>) -> Result<(), LedgerError> {
+        self.write(WriteOp::Apply(deltas)).await
+    }
+
+    /// Queues a finalize on the writer worker and waits for it to complete
+    pub async fn finalize(&self, until: BlockSlot) -> Result<(), LedgerError> {
+        self.write(WriteOp::Finalize(until)).await
+    }
+
+    /// Queues a flush on the writer worker, after any pending write
+    pub async fn flush(&self) -> Result<(), LedgerError> {
+        self.write(WriteOp::Flush).await
+    }
+}
+
+impl From<LedgerStore> for AsyncLedgerStore {
+    fn from(value: LedgerStore) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+    use crate::state::memory;
+
+    #[tokio::test]
+    async fn writes_are_visible_to_reads() {
+        let store = AsyncLedgerStore::new(memory::LedgerStore::new().into());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let deltas = (1..=10)
+            .map(|slot| {
+                fake_produce_delta(
+                    slot,
+                    vec![(fake_txo(slot as u8, 0), fake_utxo(&address, slot))],
+                )
+            })
+            .collect();
+
+        store.apply(deltas).await.unwrap();
+
+        assert_eq!(
+            store.cursor().await.unwrap(),
+            Some(ChainPoint(10, slot_to_hash(10)))
+        );
+
+        let found = store.get_utxo(fake_txo(5, 0)).await.unwrap();
+        assert_eq!(found, Some(fake_utxo(&address, 5)));
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_are_serialized() {
+        let store = AsyncLedgerStore::new(memory::LedgerStore::new().into());
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let writes: Vec<_> = (1..=10)
+            .map(|slot| {
+                let store = store.clone();
+                let utxo = (fake_txo(slot as u8, 0), fake_utxo(&address, slot));
+
+                tokio::spawn(async move {
+                    store
+                        .apply(vec![fake_produce_delta(slot, vec![utxo])])
+                        .await
+                })
+            })
+            .collect();
+
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        let refs = (1..=10).map(|x| fake_txo(x, 0)).collect();
+        assert_eq!(store.get_utxos(refs).await.unwrap().len(), 10);
+    }
+}
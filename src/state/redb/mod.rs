@@ -1,22 +1,89 @@
-use ::redb::{Database, MultimapTableHandle as _, TableHandle as _};
+use ::redb::{Database, MultimapTableHandle as _, ReadableTableMetadata as _, TableHandle as _};
 use itertools::Itertools;
 use log::info;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use tracing::{debug, warn};
+use tracing::{debug, error, warn};
 
 use super::*;
 
-mod tables;
+pub mod split;
+pub(super) mod tables;
 pub mod v1;
 pub mod v2;
 pub mod v2light;
 
 const DEFAULT_CACHE_SIZE_MB: usize = 500;
 
-fn compute_schema_hash(db: &Database) -> Result<Option<String>, LedgerError> {
-    let mut hasher = pallas::crypto::hash::Hasher::<160>::new();
+/// Max number of slots compacted in a single write transaction by `finalize`
+pub const DEFAULT_FINALIZE_BATCH: usize = 1000;
+
+/// Number of bulk load batches between durable commits
+const BULK_CHECKPOINT_EVERY: usize = 16;
+
+/// Writes immutable deltas in batches of `commit_every`, see
+/// `LedgerStore::bulk_load` on the facade
+///
+/// Batches are committed without durability, every `BULK_CHECKPOINT_EVERY`
+/// batches and the last one are committed durably so an interrupted load
+/// resumes from a recent checkpoint. Indexes aren't touched, except for the
+/// slot index which can't be rebuilt afterwards. Returns the amount of deltas
+/// written.
+fn bulk_write(
+    db: &Database,
+    deltas: impl IntoIterator<Item = LedgerDelta>,
+    commit_every: usize,
+) -> Result<usize, LedgerError> {
+    let mut written = 0;
+
+    let batches = deltas.into_iter().chunks(commit_every.max(1));
+
+    for (i, batch) in batches.into_iter().enumerate() {
+        let mut wx = db
+            .begin_write()
+            .map_err(|e| LedgerError::StorageError(e.into()))?;
+
+        if (i + 1) % BULK_CHECKPOINT_EVERY == 0 {
+            wx.set_durability(::redb::Durability::Immediate);
+        } else {
+            wx.set_durability(::redb::Durability::None);
+        }
+
+        let slot_index = tables::SlotIndex::is_enabled(&wx)?;
+
+        for delta in batch {
+            tables::CursorTable::apply_immutable(&wx, &delta)?;
+            tables::UtxosTable::apply_immutable(&wx, &delta)?;
+            tables::PParamsTable::apply(&wx, &delta)?;
+
+            if slot_index {
+                tables::SlotIndex::apply_immutable(&wx, &delta)?;
+            }
+
+            written += 1;
+        }
+
+        wx.commit()?;
+    }
 
+    // persists whatever was written since the last checkpoint
+    let mut wx = db
+        .begin_write()
+        .map_err(|e| LedgerError::StorageError(e.into()))?;
+
+    wx.set_durability(::redb::Durability::Immediate);
+    wx.commit()?;
+
+    Ok(written)
+}
+
+/// Tables that are created on demand to enable optional features. Their
+/// presence doesn't change the schema version.
+const OPTIONAL_TABLES: &[&str] = &["bydatum", "byscriptref", "byslot", "slotbyutxo"];
+
+fn list_table_names(db: &Database) -> Result<Vec<String>, LedgerError> {
     let rx = db
         .begin_read()
         .map_err(|e| LedgerError::StorageError(e.into()))?;
@@ -31,7 +98,18 @@ fn compute_schema_hash(db: &Database) -> Result<Option<String>, LedgerError> {
         .map_err(|e| LedgerError::StorageError(e.into()))?
         .map(|t| t.name().to_owned());
 
-    let mut names = names_1.chain(names_2).collect_vec();
+    let names = names_1
+        .chain(names_2)
+        .filter(|n| !OPTIONAL_TABLES.contains(&n.as_str()))
+        .collect_vec();
+
+    Ok(names)
+}
+
+fn compute_schema_hash(db: &Database) -> Result<Option<String>, LedgerError> {
+    let mut hasher = pallas::crypto::hash::Hasher::<160>::new();
+
+    let mut names = list_table_names(db)?;
 
     debug!(tables = ?names, "tables names used to compute hash");
 
@@ -51,12 +129,47 @@ fn compute_schema_hash(db: &Database) -> Result<Option<String>, LedgerError> {
     Ok(Some(hash.to_string()))
 }
 
+/// Checks if the db holds some, but not all, of the v2 tables
+///
+/// Initialization creates all tables in a single transaction so this shouldn't
+/// happen, but a db in this state can be repaired by creating the missing
+/// tables without touching the existing data.
+fn is_partial_v2(db: &Database) -> Result<bool, LedgerError> {
+    let found = list_table_names(db)?;
+
+    if found.is_empty() {
+        return Ok(false);
+    }
+
+    let reference = LedgerStore::in_memory_v2()?;
+    let expected = list_table_names(reference.db())?;
+
+    let is_subset = found.iter().all(|x| expected.contains(x));
+
+    Ok(is_subset && found.len() < expected.len())
+}
+
 fn open_db(path: impl AsRef<Path>, cache_size: Option<usize>) -> Result<Database, LedgerError> {
+    let repaired = Arc::new(AtomicBool::new(false));
+    let repaired_flag = repaired.clone();
+
     let db = Database::builder()
-        .set_repair_callback(|x| warn!(progress = x.progress() * 100f64, "ledger db is repairing"))
+        .set_repair_callback(move |x| {
+            repaired_flag.store(true, Ordering::Relaxed);
+            warn!(progress = x.progress() * 100f64, "ledger db is repairing")
+        })
         .set_cache_size(1024 * 1024 * cache_size.unwrap_or(DEFAULT_CACHE_SIZE_MB))
         .create(path)
-        .map_err(|x| LedgerError::StorageError(x.into()))?;
+        .map_err(|x| match x {
+            ::redb::DatabaseError::DatabaseAlreadyOpen => LedgerError::Contention,
+            x => LedgerError::StorageError(x.into()),
+        })?;
+
+    if repaired.load(Ordering::Relaxed) {
+        warn!("ledger db recovered from an unclean shutdown");
+    } else {
+        debug!("ledger db opened cleanly");
+    }
 
     Ok(db)
 }
@@ -83,7 +196,7 @@ impl LedgerStore {
         let db = open_db(path, cache_size)?;
         let hash = compute_schema_hash(&db)?;
 
-        let schema = match hash.as_deref() {
+        let schema: Self = match hash.as_deref() {
             // use stable schema if no hash
             None => {
                 info!("no state db schema, initializing as v2");
@@ -101,9 +214,20 @@ impl LedgerStore {
                 info!("detected state db schema v2-light");
                 v2light::LedgerStore::new(db).into()
             }
-            Some(x) => panic!("can't recognize db hash {}", x),
+            Some(_) if is_partial_v2(&db)? => {
+                warn!("state db schema v2 is missing tables, repairing");
+                v2::LedgerStore::initialize(db)?.into()
+            }
+            Some(x) => return Err(LedgerError::SchemaMismatch(x.to_owned())),
         };
 
+        // a cursor that can't be read means the db content is inconsistent, better to
+        // fail at startup than halfway through a sync
+        if let Err(err) = schema.cursor() {
+            error!(%err, "ledger cursor is unreadable, the db might be corrupt");
+            return Err(err);
+        }
+
         Ok(schema)
     }
 
@@ -164,6 +288,86 @@ impl LedgerStore {
         }
     }
 
+    /// Makes every write committed so far durable
+    ///
+    /// Writes are committed with eventual durability, committing an empty
+    /// transaction with immediate durability persists them too.
+    pub fn flush(&self) -> Result<(), LedgerError> {
+        let mut wx = self
+            .db()
+            .begin_write()
+            .map_err(|e| LedgerError::StorageError(e.into()))?;
+
+        wx.set_durability(::redb::Durability::Immediate);
+
+        wx.commit()
+            .map_err(|e| LedgerError::StorageError(e.into()))?;
+
+        Ok(())
+    }
+
+    /// Reports the entry count and stored size of every table in the db
+    ///
+    /// Tables are opened untyped, so this works for any schema version,
+    /// including the optional tables. Tables are sorted by name.
+    pub fn table_stats(&self) -> Result<Vec<TableStats>, LedgerError> {
+        let rx = self
+            .db()
+            .begin_read()
+            .map_err(|e| LedgerError::StorageError(e.into()))?;
+
+        let mut out = vec![];
+
+        for handle in rx
+            .list_tables()
+            .map_err(|e| LedgerError::StorageError(e.into()))?
+        {
+            let name = handle.name().to_owned();
+            let table = rx.open_untyped_table(handle)?;
+
+            out.push(TableStats {
+                name,
+                entries: table.len()?,
+                stored_bytes: table.stats()?.stored_bytes(),
+            });
+        }
+
+        for handle in rx
+            .list_multimap_tables()
+            .map_err(|e| LedgerError::StorageError(e.into()))?
+        {
+            let name = handle.name().to_owned();
+            let table = rx.open_untyped_multimap_table(handle)?;
+
+            out.push(TableStats {
+                name,
+                entries: table.len()?,
+                stored_bytes: table.stats()?.stored_bytes(),
+            });
+        }
+
+        out.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(out)
+    }
+
+    /// Checks every stored utxo, see `state::LedgerStore::verify_utxos`
+    ///
+    /// All schema versions share the same utxo table, so this works for any
+    /// of them.
+    pub fn verify_utxos(&self) -> Result<Vec<UtxoMismatch>, LedgerError> {
+        let rx = self.db().begin_read()?;
+        tables::UtxosTable::verify(&rx)
+    }
+
+    /// Checks the filter indexes, see `state::LedgerStore::verify_indexes`
+    pub fn verify_indexes(&self) -> Result<Vec<IndexInconsistency>, LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => x.verify_indexes(),
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
+
     pub fn cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
         match self {
             LedgerStore::SchemaV1(x) => Ok(x.cursor()?),
@@ -172,6 +376,24 @@ impl LedgerStore {
         }
     }
 
+    pub fn cursor_with_height(
+        &self,
+    ) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(x) => Ok(x.cursor_with_height()?),
+            LedgerStore::SchemaV2(x) => Ok(x.cursor_with_height()?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.cursor_with_height()?),
+        }
+    }
+
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(x) => Ok(x.first_cursor()?),
+            LedgerStore::SchemaV2(x) => Ok(x.first_cursor()?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.first_cursor()?),
+        }
+    }
+
     pub fn is_empty(&self) -> Result<bool, LedgerError> {
         match self {
             LedgerStore::SchemaV1(x) => Ok(x.is_empty()?),
@@ -188,6 +410,30 @@ impl LedgerStore {
         }
     }
 
+    pub fn stream_pparams(&self, until: BlockSlot) -> Result<tables::PParamsIterator, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(x) => Ok(x.stream_pparams(until)?),
+            LedgerStore::SchemaV2(x) => Ok(x.stream_pparams(until)?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.stream_pparams(until)?),
+        }
+    }
+
+    pub fn iter_utxos(&self) -> Result<tables::LiveUtxosIterator, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(x) => Ok(x.iter_utxos()?),
+            LedgerStore::SchemaV2(x) => Ok(x.iter_utxos()?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.iter_utxos()?),
+        }
+    }
+
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(x) => Ok(x.get_utxos_by_tx(tx_hash)?),
+            LedgerStore::SchemaV2(x) => Ok(x.get_utxos_by_tx(tx_hash)?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.get_utxos_by_tx(tx_hash)?),
+        }
+    }
+
     pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, LedgerError> {
         match self {
             LedgerStore::SchemaV1(x) => Ok(x.get_utxos(refs)?),
@@ -196,6 +442,25 @@ impl LedgerStore {
         }
     }
 
+    /// Reads the utxos and the cursor within a single read transaction
+    pub fn get_utxos_at_cursor(
+        &self,
+        refs: Vec<TxoRef>,
+    ) -> Result<(UtxoMap, Option<ChainPoint>), LedgerError> {
+        let rx = self.db().begin_read()?;
+
+        let cursor = match self {
+            LedgerStore::SchemaV1(_) => tables::BlocksTable::last(&rx)?,
+            LedgerStore::SchemaV2(_) | LedgerStore::SchemaV2Light(_) => {
+                tables::CursorTable::last(&rx)?.map(|(k, v)| ChainPoint(k, v.hash))
+            }
+        };
+
+        let utxos = tables::UtxosTable::get_sparse(&rx, refs)?;
+
+        Ok((utxos, cursor))
+    }
+
     pub fn get_utxo_by_address(&self, address: &[u8]) -> Result<UtxoSet, LedgerError> {
         match self {
             LedgerStore::SchemaV2(x) => Ok(x.get_utxos_by_address(address)?),
@@ -217,6 +482,73 @@ impl LedgerStore {
         }
     }
 
+    pub fn list_stake_credentials(
+        &self,
+        cursor: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.list_stake_credentials(cursor, limit)?),
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
+
+    /// Creates the datum index if it doesn't exist yet
+    ///
+    /// The index only tracks utxos produced after it has been enabled, utxos
+    /// already in the store are not backfilled.
+    pub fn enable_datum_index(&self) -> Result<(), LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.enable_datum_index()?),
+            _ => Err(LedgerError::InvalidStoreVersion),
+        }
+    }
+
+    pub fn get_utxo_by_datum_hash(&self, hash: &[u8]) -> Result<UtxoSet, LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.get_utxos_by_datum_hash(hash)?),
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
+
+    /// Creates the slot index if it doesn't exist yet
+    ///
+    /// Same as the datum index, utxos already in the store are not backfilled.
+    pub fn enable_slot_index(&self) -> Result<(), LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.enable_slot_index()?),
+            _ => Err(LedgerError::InvalidStoreVersion),
+        }
+    }
+
+    pub fn get_utxos_produced_in_range(
+        &self,
+        from: BlockSlot,
+        to: BlockSlot,
+    ) -> Result<UtxoSet, LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.get_utxos_produced_in_range(from, to)?),
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
+
+    /// Creates the reference script index if it doesn't exist yet
+    ///
+    /// Same as the datum index, utxos already in the store are not backfilled.
+    pub fn enable_script_ref_index(&self) -> Result<(), LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.enable_script_ref_index()?),
+            _ => Err(LedgerError::InvalidStoreVersion),
+        }
+    }
+
+    pub fn get_utxo_by_script_ref(&self, hash: &[u8]) -> Result<UtxoSet, LedgerError> {
+        match self {
+            LedgerStore::SchemaV2(x) => Ok(x.get_utxos_by_script_ref(hash)?),
+            _ => Err(LedgerError::QueryNotSupported),
+        }
+    }
+
     pub fn get_utxo_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, LedgerError> {
         match self {
             LedgerStore::SchemaV2(x) => Ok(x.get_utxos_by_policy(policy)?),
@@ -240,10 +572,70 @@ impl LedgerStore {
     }
 
     pub fn finalize(&mut self, until: BlockSlot) -> Result<(), LedgerError> {
+        self.finalize_in_batches(until, DEFAULT_FINALIZE_BATCH)
+    }
+
+    /// Compacts the slots before `until`, committing every `batch` slots
+    ///
+    /// Keeps write transactions bounded when there's a large backlog to
+    /// finalize. If interrupted, the store is left finalized up to the last
+    /// committed batch and finalizing again picks up from there. The v1 schema
+    /// doesn't support batching and compacts in a single transaction.
+    pub fn finalize_in_batches(
+        &mut self,
+        until: BlockSlot,
+        batch: usize,
+    ) -> Result<(), LedgerError> {
+        self.finalize_with_progress(until, batch, |_, _| ())
+    }
+
+    /// Same as `finalize_in_batches`, calling `progress` with the slots
+    /// compacted so far and the total to compact after each committed batch
+    pub fn finalize_with_progress(
+        &mut self,
+        until: BlockSlot,
+        batch: usize,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), LedgerError> {
         match self {
-            LedgerStore::SchemaV1(x) => Ok(x.finalize(until)?),
-            LedgerStore::SchemaV2(x) => Ok(x.finalize(until)?),
-            LedgerStore::SchemaV2Light(x) => Ok(x.finalize(until)?),
+            LedgerStore::SchemaV1(x) => {
+                let compacted = x.finalize(until)?;
+                progress(compacted, compacted);
+                Ok(())
+            }
+            LedgerStore::SchemaV2(x) => Ok(x.finalize(until, batch, progress)?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.finalize(until, batch, progress)?),
+        }
+    }
+
+    /// Writes known-immutable history, see the facade for details
+    ///
+    /// The v1 schema isn't supported.
+    pub fn bulk_load(
+        &mut self,
+        deltas: impl IntoIterator<Item = LedgerDelta>,
+        commit_every: usize,
+    ) -> Result<usize, LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(_) => Err(LedgerError::InvalidStoreVersion),
+            LedgerStore::SchemaV2(x) => {
+                let written = bulk_write(x.db(), deltas, commit_every)?;
+                x.rebuild_indexes()?;
+                Ok(written)
+            }
+            LedgerStore::SchemaV2Light(x) => bulk_write(x.db(), deltas, commit_every),
+        }
+    }
+
+    /// Resets the cursor to an authoritative point, see the facade for details
+    ///
+    /// The v1 schema derives its cursor from a different table and isn't
+    /// supported.
+    pub fn repair_cursor(&mut self, point: &ChainPoint) -> Result<(), LedgerError> {
+        match self {
+            LedgerStore::SchemaV1(_) => Err(LedgerError::InvalidStoreVersion),
+            LedgerStore::SchemaV2(x) => Ok(x.repair_cursor(point)?),
+            LedgerStore::SchemaV2Light(x) => Ok(x.repair_cursor(point)?),
         }
     }
 
@@ -291,6 +683,120 @@ impl From<v2light::LedgerStore> for LedgerStore {
 mod tests {
     use super::*;
 
+    #[test]
+    fn table_stats_count_entries() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = (0..5u8)
+            .map(|x| (fake_txo(x, 0), fake_utxo(&address, x as u64)))
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let stats = store.table_stats().unwrap();
+
+        let utxos = stats.iter().find(|x| x.name == "utxos").unwrap();
+        assert_eq!(utxos.entries, 5);
+        assert!(utxos.stored_bytes > 0);
+
+        let by_address = stats.iter().find(|x| x.name == "byaddress").unwrap();
+        assert_eq!(by_address.entries, 5);
+    }
+
+    #[test]
+    fn verify_reports_stale_index_entries() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = vec![
+            (fake_txo(1, 0), fake_utxo(&address, 1)),
+            (fake_txo(2, 0), fake_utxo(&address, 2)),
+        ];
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        // consumed but not finalized, still in the utxo table
+        let mut spend = fake_produce_delta(2, vec![]);
+
+        spend
+            .consumed_utxo
+            .insert(fake_txo(2, 0), fake_utxo(&address, 2));
+
+        store.apply(&[spend]).unwrap();
+
+        assert!(store.verify_indexes().unwrap().is_empty());
+
+        let wx = store.db().begin_write().unwrap();
+
+        {
+            let mut table = wx
+                .open_multimap_table(tables::FilterIndexes::BY_ADDRESS)
+                .unwrap();
+
+            table.insert(address.as_slice(), (&[9u8; 32], 0)).unwrap();
+
+            let mut table = wx
+                .open_multimap_table(tables::FilterIndexes::BY_PAYMENT)
+                .unwrap();
+
+            table.remove([1u8; 28].as_slice(), (&[1u8; 32], 0)).unwrap();
+        }
+
+        wx.commit().unwrap();
+
+        let found = store.verify_indexes().unwrap();
+
+        assert_eq!(
+            found,
+            vec![
+                IndexInconsistency {
+                    index: "byaddress".into(),
+                    key: address.clone(),
+                    txo: fake_txo(9, 0),
+                    kind: InconsistencyKind::Dangling,
+                },
+                IndexInconsistency {
+                    index: "bypayment".into(),
+                    key: [1u8; 28].to_vec(),
+                    txo: fake_txo(1, 0),
+                    kind: InconsistencyKind::Unindexed,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_reports_undecodable_utxos() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let produced = vec![
+            (fake_txo(1, 0), fake_utxo(&address, 1)),
+            // a truncated output, as a faulty write would leave it
+            (
+                fake_txo(2, 0),
+                EraCbor(pallas::ledger::traverse::Era::Babbage, vec![0xa2, 0x00]),
+            ),
+        ];
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let mismatches = store.verify_utxos().unwrap();
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].txo, fake_txo(2, 0));
+    }
+
     #[test]
     fn schema_hash_computation() {
         let store = LedgerStore::in_memory_v1().unwrap();
@@ -323,9 +829,632 @@ mod tests {
             recovered_stxi: Default::default(),
             undone_utxo: Default::default(),
             new_pparams: Default::default(),
+            new_height: Default::default(),
         };
 
         store.apply(&[delta]).unwrap();
         assert!(!store.is_empty().unwrap());
     }
+
+    #[test]
+    fn payment_index_ignores_stake_part() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let payment = [1u8; 28];
+        let stake = [2u8; 28];
+
+        let base = fake_txo(1, 0);
+        let enterprise = fake_txo(2, 0);
+        let unrelated = fake_txo(3, 0);
+
+        let delta = fake_produce_delta(
+            1,
+            vec![
+                (
+                    base.clone(),
+                    fake_utxo(&fake_base_address(&payment, &stake), 1_000_000),
+                ),
+                (
+                    enterprise.clone(),
+                    fake_utxo(&fake_enterprise_address(&payment), 2_000_000),
+                ),
+                (
+                    unrelated.clone(),
+                    fake_utxo(&fake_enterprise_address(&[9u8; 28]), 3_000_000),
+                ),
+            ],
+        );
+
+        store.apply(&[delta]).unwrap();
+
+        let by_payment = store.get_utxo_by_payment(&payment).unwrap();
+        assert_eq!(by_payment, UtxoSet::from([base.clone(), enterprise]));
+
+        let by_stake = store.get_utxo_by_stake(&stake).unwrap();
+        assert_eq!(by_stake, UtxoSet::from([base]));
+
+        // enterprise addresses shouldn't be indexed under an empty stake part
+        assert!(store.get_utxo_by_stake(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_results_are_sorted() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        // produce the utxos in a scrambled order
+        let seeds = [7u8, 3, 9, 1, 5, 8, 2, 6, 4];
+
+        let produced = seeds
+            .iter()
+            .map(|x| (fake_txo(*x, *x as u32 % 3), fake_utxo(&address, *x as u64)))
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let expected = seeds
+            .iter()
+            .map(|x| fake_txo(*x, *x as u32 % 3))
+            .sorted()
+            .collect_vec();
+
+        for _ in 0..3 {
+            let set = store.get_utxo_by_payment(&[1u8; 28]).unwrap();
+            assert_eq!(set.into_iter().collect_vec(), expected);
+
+            let refs = expected.iter().rev().cloned().collect();
+            let map = store.get_utxos(refs).unwrap();
+            assert_eq!(map.into_keys().collect_vec(), expected);
+        }
+    }
+
+    #[test]
+    fn list_stake_credentials_paginated() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let payment = [1u8; 28];
+
+        let produced = (1..=5u8)
+            .map(|x| {
+                let address = fake_base_address(&payment, &[x; 28]);
+                (fake_txo(x, 0), fake_utxo(&address, 1_000_000))
+            })
+            // a second utxo for the same stake key shouldn't produce a duplicate
+            .chain([(
+                fake_txo(6, 0),
+                fake_utxo(&fake_base_address(&payment, &[1u8; 28]), 1_000_000),
+            )])
+            // enterprise addresses don't contribute stake credentials
+            .chain([(
+                fake_txo(7, 0),
+                fake_utxo(&fake_enterprise_address(&payment), 1),
+            )])
+            .collect();
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let mut cursor = None;
+        let mut pages = vec![];
+
+        loop {
+            let (page, next) = store.list_stake_credentials(cursor, 2).unwrap();
+            pages.push(page);
+
+            match next {
+                Some(x) => cursor = Some(x),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages.iter().map(Vec::len).collect_vec(), vec![2, 2, 1]);
+
+        let all = pages.concat();
+        let expected = (1..=5u8).map(|x| vec![x; 28]).collect_vec();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn datum_index_lookup() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        // the integer 42 as plutus data
+        let datum = [0x18, 0x2a];
+        let datum_hash = pallas::crypto::hash::Hasher::<256>::hash(&datum);
+
+        let with_datum = fake_txo(1, 0);
+        let without_datum = fake_txo(2, 0);
+
+        let produced = vec![
+            (
+                with_datum.clone(),
+                fake_utxo_with_inline_datum(&address, 1, &datum),
+            ),
+            (without_datum, fake_utxo(&address, 1)),
+        ];
+
+        // queries aren't supported until the index is enabled
+        assert!(matches!(
+            store.get_utxo_by_datum_hash(datum_hash.as_slice()),
+            Err(LedgerError::QueryNotSupported)
+        ));
+
+        store.enable_datum_index().unwrap();
+
+        // the optional index doesn't change the schema version
+        let hash = compute_schema_hash(store.db()).unwrap();
+        assert_eq!(hash.unwrap(), V2_HASH);
+
+        store.apply(&[fake_produce_delta(1, produced)]).unwrap();
+
+        let found = store.get_utxo_by_datum_hash(datum_hash.as_slice()).unwrap();
+
+        assert_eq!(found, UtxoSet::from([with_datum.clone()]));
+
+        let utxos = store.get_utxos(found.into_iter().collect()).unwrap();
+        let inline = crate::ledger::inline_datum(&utxos[&with_datum]).unwrap();
+        assert_eq!(inline, Some(datum.to_vec()));
+    }
+
+    #[test]
+    fn script_ref_index_lookup() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let script = [0x4d, 0x01, 0x00, 0x00, 0x33, 0x22, 0x22, 0x20, 0x05, 0x12];
+        let script_hash = pallas::crypto::hash::Hasher::<224>::hash_tagged(&script, 2);
+
+        let with_script = fake_txo(1, 0);
+        let without_script = fake_txo(2, 0);
+
+        let produced = vec![
+            (
+                with_script.clone(),
+                fake_utxo_with_script_ref(&address, 1, &script),
+            ),
+            (without_script, fake_utxo(&address, 1)),
+        ];
+
+        assert!(matches!(
+            store.get_utxo_by_script_ref(script_hash.as_slice()),
+            Err(LedgerError::QueryNotSupported)
+        ));
+
+        store.enable_script_ref_index().unwrap();
+
+        let hash = compute_schema_hash(store.db()).unwrap();
+        assert_eq!(hash.unwrap(), V2_HASH);
+
+        store
+            .apply(&[fake_produce_delta(1, produced.clone())])
+            .unwrap();
+
+        let found = store
+            .get_utxo_by_script_ref(script_hash.as_slice())
+            .unwrap();
+
+        assert_eq!(found, UtxoSet::from([with_script.clone()]));
+
+        // spending the output removes it from the index
+        let spent = LedgerDelta {
+            new_position: Some(ChainPoint(2, slot_to_hash(2))),
+            consumed_utxo: produced.into_iter().collect(),
+            ..Default::default()
+        };
+
+        store.apply(&[spent]).unwrap();
+
+        let found = store
+            .get_utxo_by_script_ref(script_hash.as_slice())
+            .unwrap();
+
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn slot_index_range_lookup() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        assert!(matches!(
+            store.get_utxos_produced_in_range(0, 10),
+            Err(LedgerError::QueryNotSupported)
+        ));
+
+        store.enable_slot_index().unwrap();
+
+        // the optional index doesn't change the schema version
+        let hash = compute_schema_hash(store.db()).unwrap();
+        assert_eq!(hash.unwrap(), V2_HASH);
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = |slot: u64| (fake_txo(slot as u8, 0), fake_utxo(&address, slot));
+
+        let deltas: Vec<_> = (1..=5)
+            .map(|slot| fake_produce_delta(slot, vec![utxo(slot)]))
+            .collect();
+
+        store.apply(&deltas).unwrap();
+
+        let mut spend = fake_produce_delta(6, vec![utxo(6)]);
+        spend.consumed_utxo = [utxo(3)].into();
+        store.apply(&[spend]).unwrap();
+
+        let found = store.get_utxos_produced_in_range(2, 4).unwrap();
+        assert_eq!(found, UtxoSet::from([fake_txo(2, 0), fake_txo(4, 0)]));
+
+        // undoing the spend puts the utxo back at its original slot
+        let undo = LedgerDelta {
+            undone_position: Some(ChainPoint(6, slot_to_hash(6))),
+            undone_utxo: [utxo(6)].into(),
+            recovered_stxi: [utxo(3)].into(),
+            ..Default::default()
+        };
+
+        store.apply(&[undo]).unwrap();
+
+        let found = store.get_utxos_produced_in_range(2, 6).unwrap();
+
+        assert_eq!(
+            found,
+            UtxoSet::from([
+                fake_txo(2, 0),
+                fake_txo(3, 0),
+                fake_txo(4, 0),
+                fake_txo(5, 0)
+            ])
+        );
+    }
+    #[test]
+    fn finalize_in_batches() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        // each slot spends the utxo produced by the previous one
+        let deltas: Vec<_> = (1..=50u8)
+            .map(|slot| {
+                let mut delta = fake_produce_delta(
+                    slot as u64,
+                    vec![(fake_txo(slot, 0), fake_utxo(&address, 1))],
+                );
+
+                if slot > 1 {
+                    delta
+                        .consumed_utxo
+                        .insert(fake_txo(slot - 1, 0), fake_utxo(&address, 1));
+                }
+
+                delta
+            })
+            .collect();
+
+        store.apply(&deltas).unwrap();
+
+        // a prefix of the range leaves a consistent store
+        store.finalize_in_batches(10, 3).unwrap();
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(10, slot_to_hash(10)))
+        );
+
+        // progress is reported after each committed batch
+        let mut reports = vec![];
+
+        store
+            .finalize_with_progress(40, 7, |done, total| reports.push((done, total)))
+            .unwrap();
+
+        assert_eq!(
+            reports,
+            vec![(7, 30), (14, 30), (21, 30), (28, 30), (30, 30)]
+        );
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(40, slot_to_hash(40)))
+        );
+
+        // tombstones of compacted slots are gone, later ones are kept
+        let utxos = store
+            .get_utxos(vec![fake_txo(38, 0), fake_txo(39, 0), fake_txo(50, 0)])
+            .unwrap();
+
+        assert_eq!(
+            utxos.keys().cloned().collect_vec(),
+            vec![fake_txo(39, 0), fake_txo(50, 0)]
+        );
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(50, slot_to_hash(50)))
+        );
+    }
+
+    #[test]
+    fn first_cursor_after_finalize() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        assert_eq!(store.first_cursor().unwrap(), None);
+
+        let deltas: Vec<_> = (1..=5)
+            .map(|slot| fake_produce_delta(slot, vec![]))
+            .collect();
+        store.apply(&deltas).unwrap();
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(1, slot_to_hash(1)))
+        );
+
+        store.finalize(3).unwrap();
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(3, slot_to_hash(3)))
+        );
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(5, slot_to_hash(5)))
+        );
+    }
+
+    #[test]
+    fn open_unknown_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger");
+
+        {
+            let db = open_db(&path, None).unwrap();
+            let wx = db.begin_write().unwrap();
+            let def: ::redb::TableDefinition<u64, u64> = ::redb::TableDefinition::new("foreign");
+            wx.open_table(def).unwrap();
+            wx.commit().unwrap();
+        }
+
+        let result = LedgerStore::open(&path, None);
+        assert!(matches!(result, Err(LedgerError::SchemaMismatch(_))));
+    }
+
+    #[test]
+    fn open_repairs_partial_v2() {
+        use crate::ledger::testing::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger");
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = (fake_txo(1, 0), fake_utxo(&address, 1));
+
+        // simulate a db where only some of the tables made it to disk
+        {
+            let db = open_db(&path, None).unwrap();
+            let wx = db.begin_write().unwrap();
+            tables::CursorTable::initialize(&wx).unwrap();
+            tables::UtxosTable::initialize(&wx).unwrap();
+            tables::UtxosTable::apply(&wx, &fake_produce_delta(1, vec![utxo.clone()])).unwrap();
+            wx.commit().unwrap();
+        }
+
+        let store = LedgerStore::open(&path, None).unwrap();
+        assert!(matches!(store, LedgerStore::SchemaV2(_)));
+
+        let hash = compute_schema_hash(store.db()).unwrap();
+        assert_eq!(hash.unwrap(), V2_HASH);
+
+        // existing data survives the repair
+        let found = store.get_utxos(vec![utxo.0.clone()]).unwrap();
+        assert_eq!(found.get(&utxo.0), Some(&utxo.1));
+    }
+
+    #[test]
+    fn open_locked_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger");
+
+        let _store = LedgerStore::open(&path, None).unwrap();
+
+        let result = LedgerStore::open(&path, None);
+        assert!(matches!(result, Err(LedgerError::Contention)));
+    }
+
+    #[test]
+    fn repair_lost_cursor() {
+        use crate::ledger::testing::*;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        let deltas: Vec<_> = (1..=5u8)
+            .map(|slot| {
+                fake_produce_delta(
+                    slot as u64,
+                    vec![(fake_txo(slot, 0), fake_utxo(&address, 1))],
+                )
+            })
+            .collect();
+
+        store.apply(&deltas).unwrap();
+
+        // simulate the loss of the cursor table
+        let LedgerStore::SchemaV2(inner) = &store else {
+            unreachable!();
+        };
+
+        let wx = inner.db().begin_write().unwrap();
+        wx.open_table(tables::CursorTable::DEF)
+            .unwrap()
+            .retain(|_, _| false)
+            .unwrap();
+        wx.commit().unwrap();
+
+        assert_eq!(store.cursor().unwrap(), None);
+        assert_eq!(store.first_cursor().unwrap(), None);
+
+        let point = ChainPoint(5, slot_to_hash(5));
+        store.repair_cursor(&point).unwrap();
+
+        assert_eq!(store.cursor().unwrap(), Some(point.clone()));
+        assert_eq!(store.first_cursor().unwrap(), Some(point));
+
+        // the utxo set is untouched
+        let utxos = store
+            .get_utxos(vec![fake_txo(1, 0), fake_txo(5, 0)])
+            .unwrap();
+        assert_eq!(utxos.len(), 2);
+    }
+
+    #[test]
+    fn legacy_cursor_has_unknown_height() {
+        use crate::ledger::testing::*;
+
+        let store = LedgerStore::in_memory_v2().unwrap();
+
+        let LedgerStore::SchemaV2(inner) = &store else {
+            unreachable!();
+        };
+
+        // cursors written before the height was recorded
+        let legacy = bincode::serialize(&(slot_to_hash(7), vec![fake_txo(1, 0)])).unwrap();
+
+        let wx = inner.db().begin_write().unwrap();
+        wx.open_table(tables::CursorTable::DEF)
+            .unwrap()
+            .insert(7, legacy.as_slice())
+            .unwrap();
+        wx.commit().unwrap();
+
+        assert_eq!(
+            store.cursor_with_height().unwrap(),
+            Some((ChainPoint(7, slot_to_hash(7)), None))
+        );
+    }
+
+    #[test]
+    fn flushed_store_survives_reopen() {
+        use crate::ledger::testing::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger");
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+
+        {
+            let mut store = LedgerStore::open(&path, None).unwrap();
+
+            let deltas: Vec<_> = (1..=3u8)
+                .map(|slot| {
+                    fake_produce_delta(
+                        slot as u64,
+                        vec![(fake_txo(slot, 0), fake_utxo(&address, 1))],
+                    )
+                })
+                .collect();
+
+            store.apply(&deltas).unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = LedgerStore::open(&path, None).unwrap();
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(3, slot_to_hash(3)))
+        );
+
+        let utxos = store
+            .get_utxos(vec![fake_txo(1, 0), fake_txo(2, 0), fake_txo(3, 0)])
+            .unwrap();
+
+        assert_eq!(utxos.len(), 3);
+    }
+
+    #[test]
+    fn reads_proceed_during_write() {
+        use crate::ledger::testing::*;
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let mut store = LedgerStore::in_memory_v2().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let committed = (fake_txo(1, 0), fake_utxo(&address, 1));
+        let pending = (fake_txo(2, 0), fake_utxo(&address, 2));
+
+        store
+            .apply(&[fake_produce_delta(1, vec![committed.clone()])])
+            .unwrap();
+
+        // an apply that is still in flight, holding the write transaction
+        let delta = fake_produce_delta(2, vec![pending.clone()]);
+        let wx = store.db().begin_write().unwrap();
+        tables::CursorTable::apply(&wx, &delta).unwrap();
+        tables::UtxosTable::apply(&wx, &delta).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                let sender = sender.clone();
+                let refs = vec![committed.0.clone(), pending.0.clone()];
+
+                std::thread::spawn(move || {
+                    let cursor = store.cursor().unwrap();
+                    let utxos = store.get_utxos(refs).unwrap();
+                    sender.send((cursor, utxos)).unwrap();
+                })
+            })
+            .collect();
+
+        // readers see the last committed snapshot without waiting for the write
+        for _ in readers.iter() {
+            let (cursor, utxos) = receiver
+                .recv_timeout(Duration::from_secs(10))
+                .expect("reader blocked behind the write transaction");
+
+            assert_eq!(cursor, Some(ChainPoint(1, slot_to_hash(1))));
+            assert_eq!(utxos.len(), 1);
+            assert!(utxos.contains_key(&committed.0));
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        wx.commit().unwrap();
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(2, slot_to_hash(2)))
+        );
+        assert_eq!(
+            store
+                .get_utxos(vec![committed.0.clone(), pending.0.clone()])
+                .unwrap()
+                .len(),
+            2
+        );
+    }
 }
@@ -0,0 +1,485 @@
+use ::redb::{Database, Durability};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::state::*;
+type Error = crate::state::LedgerError;
+
+use super::tables;
+
+/// A ledger store spanning two databases, one for the immutable portion of
+/// the ledger and one for the volatile window
+///
+/// The immutable db has the tables of the v2 schema and holds the ledger as of
+/// the last finalized slot. The volatile db holds the cursors, utxos, pparams and
+/// filter indexes of the deltas applied since then, along with the deltas
+/// themselves. Both dbs can live on different storage, eg: the immutable one
+/// on cheap disks and the volatile one on fast disks.
+///
+/// Reads merge both dbs. Utxos consumed within the volatile window are still
+/// present in the immutable indexes, so they're filtered out using the
+/// tombstones of the volatile cursors. `finalize` migrates the deltas of the
+/// finalized slots to the immutable db and then drops them from the volatile
+/// one. Each side is committed separately, migrating a delta twice is
+/// harmless, so a crash in between is fixed by the next finalize.
+#[derive(Clone)]
+pub struct LedgerStore {
+    immutable: Arc<Database>,
+    volatile: Arc<Database>,
+}
+
+impl LedgerStore {
+    pub fn open(
+        immutable: impl AsRef<Path>,
+        volatile: impl AsRef<Path>,
+        cache_size: Option<usize>,
+    ) -> Result<Self, Error> {
+        let immutable = super::open_db(immutable, cache_size)?;
+        let volatile = super::open_db(volatile, cache_size)?;
+
+        Self::initialize(immutable, volatile)
+    }
+
+    pub fn in_memory() -> Result<Self, Error> {
+        let db = || {
+            ::redb::Database::builder()
+                .create_with_backend(::redb::backends::InMemoryBackend::new())
+                .unwrap()
+        };
+
+        Self::initialize(db(), db())
+    }
+
+    fn initialize(immutable: Database, volatile: Database) -> Result<Self, Error> {
+        for db in [&immutable, &volatile] {
+            let mut wx = db.begin_write()?;
+            wx.set_durability(Durability::Immediate);
+
+            tables::CursorTable::initialize(&wx)?;
+            tables::UtxosTable::initialize(&wx)?;
+            tables::PParamsTable::initialize(&wx)?;
+            tables::FilterIndexes::initialize(&wx)?;
+
+            wx.commit()?;
+        }
+
+        let mut wx = volatile.begin_write()?;
+        wx.set_durability(Durability::Immediate);
+        tables::VolatileDeltasTable::initialize(&wx)?;
+        wx.commit()?;
+
+        Ok(Self {
+            immutable: immutable.into(),
+            volatile: volatile.into(),
+        })
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.cursor().map(|x| x.is_none())
+    }
+
+    pub fn cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let rx = self.volatile.begin_read()?;
+
+        if let Some((slot, value)) = tables::CursorTable::last(&rx)? {
+            return Ok(Some(ChainPoint(slot, value.hash)));
+        }
+
+        let rx = self.immutable.begin_read()?;
+        let last = tables::CursorTable::last(&rx)?.map(|(k, v)| ChainPoint(k, v.hash));
+
+        Ok(last)
+    }
+
+    pub fn cursor_with_height(&self) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, Error> {
+        let rx = self.volatile.begin_read()?;
+
+        if let Some((slot, value)) = tables::CursorTable::last(&rx)? {
+            return Ok(Some((ChainPoint(slot, value.hash), value.height)));
+        }
+
+        let rx = self.immutable.begin_read()?;
+
+        let last = tables::CursorTable::last(&rx)?.map(|(k, v)| (ChainPoint(k, v.hash), v.height));
+
+        Ok(last)
+    }
+
+    /// The oldest point of the volatile window, or the immutable tip if the
+    /// window is empty
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let rx = self.volatile.begin_read()?;
+
+        if let Some((slot, value)) = tables::CursorTable::first(&rx)? {
+            return Ok(Some(ChainPoint(slot, value.hash)));
+        }
+
+        let rx = self.immutable.begin_read()?;
+        let last = tables::CursorTable::last(&rx)?.map(|(k, v)| ChainPoint(k, v.hash));
+
+        Ok(last)
+    }
+
+    pub fn utxo_count(&self) -> Result<u64, Error> {
+        use ::redb::ReadableTableMetadata as _;
+
+        let rx = self.immutable.begin_read()?;
+        let immutable = rx.open_table(tables::UtxosTable::DEF)?.len()?;
+
+        let rx = self.volatile.begin_read()?;
+        let volatile = rx.open_table(tables::UtxosTable::DEF)?.len()?;
+
+        Ok(immutable + volatile)
+    }
+
+    pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), Error> {
+        let mut wx = self.volatile.begin_write()?;
+        wx.set_durability(Durability::Eventual);
+
+        for delta in deltas {
+            tables::CursorTable::apply(&wx, delta)?;
+            tables::UtxosTable::apply(&wx, delta)?;
+            tables::PParamsTable::apply(&wx, delta)?;
+            tables::FilterIndexes::apply(&wx, delta)?;
+            tables::VolatileDeltasTable::apply(&wx, delta)?;
+        }
+
+        wx.commit()?;
+
+        Ok(())
+    }
+
+    /// Migrates the deltas of the slots before `until` to the immutable db
+    pub fn finalize(&mut self, until: BlockSlot) -> Result<usize, Error> {
+        let rx = self.volatile.begin_read()?;
+        let deltas = tables::VolatileDeltasTable::get_range(&rx, until)?;
+        drop(rx);
+
+        if deltas.is_empty() {
+            return Ok(0);
+        }
+
+        let mut wx = self.immutable.begin_write()?;
+        wx.set_durability(Durability::Immediate);
+
+        for (_, delta) in deltas.iter() {
+            tables::UtxosTable::apply_immutable(&wx, delta)?;
+            tables::PParamsTable::apply(&wx, delta)?;
+            tables::FilterIndexes::apply(&wx, delta)?;
+        }
+
+        // there's nothing to undo in the immutable db, only its tip is kept
+        if let Some((_, last)) = deltas.last() {
+            if let Some(point) = last.new_position.as_ref() {
+                tables::CursorTable::reset(&wx, point, last.new_height)?;
+            }
+        }
+
+        wx.commit()?;
+
+        let mut wx = self.volatile.begin_write()?;
+        wx.set_durability(Durability::Immediate);
+
+        for (_, delta) in deltas.iter() {
+            // the migrated delta is dropped from the volatile db the same way
+            // an undo would drop it, minus the recovery of the consumed utxos
+            let migrated = LedgerDelta {
+                undone_position: delta.new_position.clone(),
+                undone_utxo: delta.produced_utxo.clone(),
+                ..Default::default()
+            };
+
+            tables::CursorTable::apply(&wx, &migrated)?;
+            tables::UtxosTable::apply(&wx, &migrated)?;
+            tables::PParamsTable::apply(&wx, &migrated)?;
+            tables::FilterIndexes::apply(&wx, &migrated)?;
+            tables::VolatileDeltasTable::apply(&wx, &migrated)?;
+        }
+
+        wx.commit()?;
+
+        Ok(deltas.len())
+    }
+
+    /// Makes every write committed so far durable, on both dbs
+    pub fn flush(&self) -> Result<(), Error> {
+        for db in [&self.immutable, &self.volatile] {
+            let mut wx = db.begin_write()?;
+            wx.set_durability(Durability::Immediate);
+            wx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
+        if refs.is_empty() {
+            return Ok(Default::default());
+        }
+
+        let rx = self.volatile.begin_read()?;
+        let mut out = tables::UtxosTable::get_sparse(&rx, refs.clone())?;
+
+        let missing: Vec<_> = refs.into_iter().filter(|x| !out.contains_key(x)).collect();
+
+        if !missing.is_empty() {
+            let rx = self.immutable.begin_read()?;
+            out.extend(tables::UtxosTable::get_sparse(&rx, missing)?);
+        }
+
+        Ok(out)
+    }
+
+    /// Merges the outputs of the tx found in each db
+    ///
+    /// All the outputs of a tx are produced by the same block, so in practice
+    /// they're all in one of the dbs.
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let rx = self.immutable.begin_read()?;
+        let mut out = tables::UtxosTable::get_by_tx(&rx, tx_hash)?;
+
+        let rx = self.volatile.begin_read()?;
+        out.extend(tables::UtxosTable::get_by_tx(&rx, tx_hash)?);
+
+        Ok(out)
+    }
+
+    /// Reads the utxos and the cursor from a read transaction on each db
+    ///
+    /// The volatile transaction is opened first. Finalization commits to the
+    /// immutable db before clearing the volatile one, so whatever happens in
+    /// between both transactions, the data of the cursor is present in one of
+    /// the dbs.
+    pub fn get_utxos_at_cursor(
+        &self,
+        refs: Vec<TxoRef>,
+    ) -> Result<(UtxoMap, Option<ChainPoint>), Error> {
+        let volatile = self.volatile.begin_read()?;
+        let immutable = self.immutable.begin_read()?;
+
+        let cursor = match tables::CursorTable::last(&volatile)? {
+            Some(x) => Some(x),
+            None => tables::CursorTable::last(&immutable)?,
+        };
+
+        let cursor = cursor.map(|(k, v)| ChainPoint(k, v.hash));
+
+        let mut out = tables::UtxosTable::get_sparse(&volatile, refs.clone())?;
+
+        let missing: Vec<_> = refs.into_iter().filter(|x| !out.contains_key(x)).collect();
+        out.extend(tables::UtxosTable::get_sparse(&immutable, missing)?);
+
+        Ok((out, cursor))
+    }
+
+    pub fn get_pparams(&self, until: BlockSlot) -> Result<Vec<PParamsBody>, Error> {
+        let rx = self.immutable.begin_read()?;
+        let mut out = tables::PParamsTable::get_range(&rx, until)?;
+
+        let rx = self.volatile.begin_read()?;
+        out.extend(tables::PParamsTable::get_range(&rx, until)?);
+
+        Ok(out)
+    }
+
+    /// Streams the immutable rows and then the volatile ones, both ranges are
+    /// opened upfront
+    pub fn stream_pparams(
+        &self,
+        until: BlockSlot,
+    ) -> Result<std::iter::Chain<tables::PParamsIterator, tables::PParamsIterator>, Error> {
+        let rx = self.immutable.begin_read()?;
+        let immutable = tables::PParamsTable::stream_range(&rx, until)?;
+
+        let rx = self.volatile.begin_read()?;
+        let volatile = tables::PParamsTable::stream_range(&rx, until)?;
+
+        Ok(immutable.chain(volatile))
+    }
+
+    /// Streams the live utxos of the immutable db and then the volatile ones
+    ///
+    /// The volatile tombstones cover the utxos of both dbs, a utxo consumed
+    /// within the volatile window is skipped whichever db holds it.
+    pub fn iter_utxos(&self) -> Result<tables::LiveUtxosIterator, Error> {
+        let rx = self.volatile.begin_read()?;
+        let consumed = tables::CursorTable::pending_tombstones(&rx)?;
+        let volatile = tables::UtxosTable::iter(&rx)?;
+
+        let rx = self.immutable.begin_read()?;
+        let immutable = tables::UtxosTable::iter(&rx)?;
+
+        Ok(tables::LiveUtxosIterator::new(
+            vec![immutable, volatile],
+            consumed,
+        ))
+    }
+
+    /// Runs a filter query on both dbs, dropping the utxos consumed within the
+    /// volatile window
+    fn filtered(
+        &self,
+        query: impl Fn(&::redb::ReadTransaction) -> Result<UtxoSet, Error>,
+    ) -> Result<UtxoSet, Error> {
+        let rx = self.volatile.begin_read()?;
+        let volatile = query(&rx)?;
+
+        let consumed: UtxoSet = tables::CursorTable::get_range(&rx, BlockSlot::MAX, usize::MAX)?
+            .into_iter()
+            .flat_map(|(_, value)| value.tombstones)
+            .collect();
+
+        let rx = self.immutable.begin_read()?;
+        let immutable = query(&rx)?;
+
+        let out = immutable
+            .into_iter()
+            .filter(|x| !consumed.contains(x))
+            .chain(volatile)
+            .collect();
+
+        Ok(out)
+    }
+
+    pub fn get_utxos_by_address(&self, address: &[u8]) -> Result<UtxoSet, Error> {
+        self.filtered(|rx| tables::FilterIndexes::get_by_address(rx, address))
+    }
+
+    pub fn get_utxos_by_payment(&self, payment: &[u8]) -> Result<UtxoSet, Error> {
+        self.filtered(|rx| tables::FilterIndexes::get_by_payment(rx, payment))
+    }
+
+    pub fn get_utxos_by_stake(&self, stake: &[u8]) -> Result<UtxoSet, Error> {
+        self.filtered(|rx| tables::FilterIndexes::get_by_stake(rx, stake))
+    }
+
+    pub fn get_utxos_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, Error> {
+        self.filtered(|rx| tables::FilterIndexes::get_by_policy(rx, policy))
+    }
+
+    pub fn get_utxos_by_asset(&self, asset: &[u8]) -> Result<UtxoSet, Error> {
+        self.filtered(|rx| tables::FilterIndexes::get_by_asset(rx, asset))
+    }
+
+    pub fn verify_utxos(&self) -> Result<Vec<UtxoMismatch>, Error> {
+        let rx = self.immutable.begin_read()?;
+        let mut out = tables::UtxosTable::verify(&rx)?;
+
+        let rx = self.volatile.begin_read()?;
+        out.extend(tables::UtxosTable::verify(&rx)?);
+
+        out.sort_by(|a, b| a.txo.cmp(&b.txo));
+
+        Ok(out)
+    }
+
+    /// Checks the filter indexes of each db against its own utxo set
+    pub fn verify_indexes(&self) -> Result<Vec<IndexInconsistency>, Error> {
+        let mut out = vec![];
+
+        for db in [&self.immutable, &self.volatile] {
+            let rx = db.begin_read()?;
+            let pending = tables::CursorTable::pending_tombstones(&rx)?;
+
+            out.extend(tables::FilterIndexes::verify(&rx, &pending)?);
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::testing::*;
+
+    #[test]
+    fn queries_span_both_dbs() {
+        let mut store = LedgerStore::in_memory().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = |seed: u8| (fake_txo(seed, 0), fake_utxo(&address, seed as u64));
+
+        let deltas: Vec<_> = (1..=4)
+            .map(|slot| fake_produce_delta(slot, vec![utxo(slot as u8)]))
+            .collect();
+
+        store.apply(&deltas).unwrap();
+
+        // spends an immutable utxo and a volatile one
+        let mut spend = fake_produce_delta(5, vec![utxo(5)]);
+        spend.consumed_utxo = [utxo(1), utxo(4)].into();
+        store.apply(&[spend]).unwrap();
+
+        assert_eq!(store.finalize(3).unwrap(), 2);
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(3, slot_to_hash(3)))
+        );
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(5, slot_to_hash(5)))
+        );
+
+        let expected = UtxoSet::from([fake_txo(2, 0), fake_txo(3, 0), fake_txo(5, 0)]);
+        assert_eq!(store.get_utxos_by_address(&address).unwrap(), expected);
+
+        // consumed utxos can still be resolved until the spend is finalized
+        let refs: Vec<_> = (1..=5).map(|x| fake_txo(x, 0)).collect();
+        assert_eq!(store.get_utxos(refs.clone()).unwrap().len(), 5);
+
+        store.finalize(6).unwrap();
+
+        assert_eq!(
+            store.first_cursor().unwrap(),
+            Some(ChainPoint(5, slot_to_hash(5)))
+        );
+
+        assert_eq!(store.get_utxos_by_address(&address).unwrap(), expected);
+        assert_eq!(store.get_utxos(refs).unwrap().len(), 3);
+
+        // nothing is left in the volatile db
+        let rx = store.volatile.begin_read().unwrap();
+        assert!(tables::CursorTable::last(&rx).unwrap().is_none());
+    }
+
+    #[test]
+    fn undo_restores_immutable_utxo() {
+        let mut store = LedgerStore::in_memory().unwrap();
+
+        let address = fake_enterprise_address(&[1u8; 28]);
+        let utxo = (fake_txo(1, 0), fake_utxo(&address, 1));
+
+        store
+            .apply(&[fake_produce_delta(1, vec![utxo.clone()])])
+            .unwrap();
+
+        store.finalize(2).unwrap();
+
+        let mut spend = fake_produce_delta(2, vec![]);
+        spend.consumed_utxo = [utxo.clone()].into();
+        store.apply(&[spend]).unwrap();
+
+        assert!(store.get_utxos_by_address(&address).unwrap().is_empty());
+
+        let undo = LedgerDelta {
+            undone_position: Some(ChainPoint(2, slot_to_hash(2))),
+            recovered_stxi: [utxo.clone()].into(),
+            ..Default::default()
+        };
+
+        store.apply(&[undo]).unwrap();
+
+        assert_eq!(
+            store.get_utxos_by_address(&address).unwrap(),
+            UtxoSet::from([utxo.0])
+        );
+
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some(ChainPoint(1, slot_to_hash(1)))
+        );
+    }
+}
@@ -1,14 +1,41 @@
-use ::redb::{MultimapTableDefinition, TableDefinition, WriteTransaction};
+use ::redb::{
+    MultimapTableDefinition, MultimapTableHandle as _, TableDefinition, WriteTransaction,
+};
 use ::redb::{Range, ReadTransaction, ReadableTable as _, TableError};
 use itertools::Itertools as _;
 use pallas::{crypto::hash::Hash, ledger::traverse::MultiEraOutput};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashSet};
 
 use crate::state::*;
 
 type Error = crate::state::LedgerError;
 
+fn decode_era(era: u16) -> Result<pallas::ledger::traverse::Era, Error> {
+    pallas::ledger::traverse::Era::try_from(era).map_err(|_| Error::InvalidEra(era))
+}
+
+/// Layout of `CursorValue` before it carried the block height
+#[derive(Deserialize)]
+struct LegacyCursorValue {
+    hash: Hash<32>,
+    tombstones: Vec<TxoRef>,
+}
+
+fn decode_cursor(bytes: &[u8]) -> Result<CursorValue, Error> {
+    bincode::deserialize(bytes)
+        .or_else(|err| {
+            bincode::deserialize::<LegacyCursorValue>(bytes)
+                .map(|x| CursorValue {
+                    hash: x.hash,
+                    tombstones: x.tombstones,
+                    height: None,
+                })
+                .map_err(|_| err)
+        })
+        .map_err(Error::CursorDecoding)
+}
+
 pub struct BlocksTable;
 
 impl BlocksTable {
@@ -34,6 +61,19 @@ impl BlocksTable {
         Ok(last)
     }
 
+    pub fn first(rx: &ReadTransaction) -> Result<Option<ChainPoint>, Error> {
+        let table = match rx.open_table(Self::DEF) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(x) => return Err(x.into()),
+        };
+
+        let first = table.first()?;
+        let first = first.map(|(k, v)| ChainPoint(k.value(), Hash::new(*v.value())));
+
+        Ok(first)
+    }
+
     pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
         let mut table = wx.open_table(Self::DEF)?;
 
@@ -56,27 +96,58 @@ type UtxosValue = (u16, &'static [u8]);
 pub struct UtxosIterator(Range<'static, UtxosKey, UtxosValue>);
 
 impl Iterator for UtxosIterator {
-    type Item = Result<(TxoRef, EraCbor), ::redb::StorageError>;
+    type Item = Result<(TxoRef, EraCbor), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let x = self.0.next()?;
 
-        let x = x.map(|(k, v)| {
+        let x = x.map_err(Error::from).and_then(|(k, v)| {
             let (hash, idx) = k.value();
             let k = TxoRef((*hash).into(), idx);
 
             let (era, cbor) = v.value();
-            let era = pallas::ledger::traverse::Era::try_from(era).unwrap();
+            let era = decode_era(era)?;
             let cbor = cbor.to_owned();
             let v = EraCbor(era, cbor);
 
-            (k, v)
+            Ok((k, v))
         });
 
         Some(x)
     }
 }
 
+/// Streams the utxo tables of one or more dbs, skipping consumed utxos
+///
+/// Consumed utxos stay in the table until their slot is compacted, the
+/// tombstones of the pending cursors tell them apart from the live ones.
+pub struct LiveUtxosIterator {
+    tables: std::iter::Flatten<std::vec::IntoIter<UtxosIterator>>,
+    consumed: HashSet<TxoRef>,
+}
+
+impl LiveUtxosIterator {
+    pub fn new(tables: Vec<UtxosIterator>, consumed: HashSet<TxoRef>) -> Self {
+        Self {
+            tables: tables.into_iter().flatten(),
+            consumed,
+        }
+    }
+}
+
+impl Iterator for LiveUtxosIterator {
+    type Item = Result<(TxoRef, EraCbor), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.tables.next()? {
+                Ok((txo, _)) if self.consumed.contains(&txo) => continue,
+                x => return Some(x),
+            }
+        }
+    }
+}
+
 pub struct UtxosTable;
 
 impl UtxosTable {
@@ -94,17 +165,14 @@ impl UtxosTable {
         Ok(UtxosIterator(range))
     }
 
-    pub fn get_sparse(
-        rx: &ReadTransaction,
-        refs: Vec<TxoRef>,
-    ) -> Result<HashMap<TxoRef, EraCbor>, Error> {
+    pub fn get_sparse(rx: &ReadTransaction, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
         let table = rx.open_table(Self::DEF)?;
-        let mut out = HashMap::new();
+        let mut out = UtxoMap::new();
 
         for key in refs {
             if let Some(body) = table.get(&(&key.0 as &[u8; 32], key.1))? {
                 let (era, cbor) = body.value();
-                let era = pallas::ledger::traverse::Era::try_from(era).unwrap();
+                let era = decode_era(era)?;
                 let cbor = cbor.to_owned();
                 let value = EraCbor(era, cbor);
 
@@ -115,16 +183,85 @@ impl UtxosTable {
         Ok(out)
     }
 
+    /// Returns every utxo produced by a tx
+    ///
+    /// Keys are `(hash, index)` tuples and redb orders tuples element by
+    /// element, comparing fixed-size arrays byte by byte. The outputs of a tx
+    /// are then contiguous and sorted by index, so a single range scan finds
+    /// all of them.
+    pub fn get_by_tx(rx: &ReadTransaction, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let table = rx.open_table(Self::DEF)?;
+        let range = table.range((tx_hash, 0)..=(tx_hash, u32::MAX))?;
+
+        UtxosIterator(range).collect()
+    }
+
+    pub fn verify(rx: &ReadTransaction) -> Result<Vec<UtxoMismatch>, Error> {
+        let table = rx.open_table(Self::DEF)?;
+
+        let mut out = vec![];
+
+        for entry in table.iter()? {
+            let (key, value) = entry?;
+
+            let (hash, idx) = key.value();
+            let txo = TxoRef((*hash).into(), idx);
+
+            let (era, cbor) = value.value();
+
+            let mismatch = match decode_era(era) {
+                Ok(era) => UtxoMismatch::check(&txo, &EraCbor(era, cbor.to_vec())),
+                Err(err) => Some(UtxoMismatch {
+                    txo,
+                    reason: err.to_string(),
+                }),
+            };
+
+            out.extend(mismatch);
+        }
+
+        Ok(out)
+    }
+
+    /// Re-producing an existing utxo with the same body is a no-op; a
+    /// different body is a conflict and aborts the apply, since the write
+    /// transaction is dropped without committing.
     pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
         let mut table = wx.open_table(Self::DEF)?;
 
+        for (txo, v) in delta.produced_utxo.iter() {
+            let k: (&[u8; 32], u32) = (&txo.0, txo.1);
+            let v: (u16, &[u8]) = (v.0.into(), &v.1);
+
+            if let Some(previous) = table.insert(k, v)? {
+                if previous.value() != v {
+                    return Err(Error::BrokenInvariant(BrokenInvariant::ConflictingUtxo(
+                        txo.clone(),
+                    )));
+                }
+            }
+        }
+
+        for (k, _) in delta.undone_utxo.iter() {
+            let k: (&[u8; 32], u32) = (&k.0, k.1);
+            table.remove(k)?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a delta that can't be undone: consumed utxos are removed right
+    /// away and produced ones are inserted without checking for conflicts
+    pub fn apply_immutable(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut table = wx.open_table(Self::DEF)?;
+
         for (k, v) in delta.produced_utxo.iter() {
             let k: (&[u8; 32], u32) = (&k.0, k.1);
             let v: (u16, &[u8]) = (v.0.into(), &v.1);
             table.insert(k, v)?;
         }
 
-        for (k, _) in delta.undone_utxo.iter() {
+        for k in delta.consumed_utxo.keys() {
             let k: (&[u8; 32], u32) = (&k.0, k.1);
             table.remove(k)?;
         }
@@ -160,11 +297,33 @@ impl UtxosTable {
     }
 }
 
+type PParamsValue = (u16, &'static [u8]);
+
+/// Yields the rows of the pparams table, the range keeps the read transaction
+/// it was opened from alive until it's dropped
+pub struct PParamsIterator(Range<'static, u64, PParamsValue>);
+
+impl Iterator for PParamsIterator {
+    type Item = Result<PParamsBody, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.0.next()?;
+
+        let x = x.map_err(Error::from).and_then(|(_, body)| {
+            let (era, cbor) = body.value();
+            let era = decode_era(era)?;
+
+            Ok(PParamsBody(era, Vec::from(cbor)))
+        });
+
+        Some(x)
+    }
+}
+
 pub struct PParamsTable;
 
 impl PParamsTable {
-    pub const DEF: TableDefinition<'static, u64, (u16, &'static [u8])> =
-        TableDefinition::new("pparams");
+    pub const DEF: TableDefinition<'static, u64, PParamsValue> = TableDefinition::new("pparams");
 
     pub fn initialize(wx: &WriteTransaction) -> Result<(), Error> {
         wx.open_table(Self::DEF)?;
@@ -172,19 +331,16 @@ impl PParamsTable {
         Ok(())
     }
 
-    pub fn get_range(rx: &ReadTransaction, until: BlockSlot) -> Result<Vec<PParamsBody>, Error> {
+    /// Streams the rows before `until`, in ascending slot order
+    pub fn stream_range(rx: &ReadTransaction, until: BlockSlot) -> Result<PParamsIterator, Error> {
         let table = rx.open_table(Self::DEF)?;
+        let range = table.range(..until)?;
 
-        let mut out = vec![];
-
-        for item in table.range(..until)? {
-            let (_, body) = item?;
-            let (era, cbor) = body.value();
-            let era = pallas::ledger::traverse::Era::try_from(era).unwrap();
-            out.push(PParamsBody(era, Vec::from(cbor)));
-        }
+        Ok(PParamsIterator(range))
+    }
 
-        Ok(out)
+    pub fn get_range(rx: &ReadTransaction, until: BlockSlot) -> Result<Vec<PParamsBody>, Error> {
+        Self::stream_range(rx, until)?.collect()
     }
 
     pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
@@ -288,6 +444,8 @@ pub struct CursorTable;
 pub struct CursorValue {
     pub hash: Hash<32>,
     pub tombstones: Vec<TxoRef>,
+    /// unknown for cursors written by older versions or repaired by hand
+    pub height: Option<BlockHeight>,
 }
 
 impl CursorTable {
@@ -300,17 +458,19 @@ impl CursorTable {
         Ok(())
     }
 
+    /// Returns up to `limit` cursors before `until`, in ascending slot order
     pub fn get_range(
         rx: &ReadTransaction,
         until: BlockSlot,
+        limit: usize,
     ) -> Result<Vec<(BlockSlot, CursorValue)>, Error> {
         let table = rx.open_table(Self::DEF)?;
 
         let mut out = vec![];
 
-        for entry in table.range(..until)? {
+        for entry in table.range(..until)?.take(limit) {
             let (slot, value) = entry?;
-            let value = bincode::deserialize(value.value()).unwrap();
+            let value = decode_cursor(value.value())?;
 
             out.push((slot.value(), value));
         }
@@ -318,6 +478,62 @@ impl CursorTable {
         Ok(out)
     }
 
+    /// Replaces every cursor with a single entry for `point`, without tombstones
+    pub fn reset(
+        wx: &WriteTransaction,
+        point: &ChainPoint,
+        height: Option<BlockHeight>,
+    ) -> Result<(), Error> {
+        let mut table = wx.open_table(Self::DEF)?;
+
+        table.retain(|_, _| false)?;
+
+        let value = CursorValue {
+            hash: point.1,
+            tombstones: vec![],
+            height,
+        };
+
+        let value = bincode::serialize(&value).unwrap();
+
+        table.insert(point.0, value.as_slice())?;
+
+        Ok(())
+    }
+
+    /// Records the new position of an immutable delta, without tombstones
+    pub fn apply_immutable(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut table = wx.open_table(Self::DEF)?;
+
+        if let Some(ChainPoint(slot, hash)) = delta.new_position.as_ref() {
+            let value = CursorValue {
+                hash: *hash,
+                tombstones: vec![],
+                height: delta.new_height,
+            };
+
+            let value = bincode::serialize(&value).unwrap();
+
+            table.insert(slot, value.as_slice())?;
+        }
+
+        Ok(())
+    }
+
+    /// Counts the cursors before `until`
+    pub fn count_range(rx: &ReadTransaction, until: BlockSlot) -> Result<usize, Error> {
+        let table = rx.open_table(Self::DEF)?;
+
+        let mut count = 0;
+
+        for entry in table.range(..until)? {
+            entry?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
         let mut table = wx.open_table(Self::DEF)?;
 
@@ -325,6 +541,7 @@ impl CursorTable {
             let value = CursorValue {
                 hash: *hash,
                 tombstones: delta.consumed_utxo.keys().cloned().collect_vec(),
+                height: delta.new_height,
             };
 
             let value = bincode::serialize(&value).unwrap();
@@ -347,6 +564,21 @@ impl CursorTable {
         Ok(())
     }
 
+    /// Collects the tombstones of every cursor, the utxos that were consumed
+    /// but are still in the utxo table waiting for finalization
+    pub fn pending_tombstones(rx: &ReadTransaction) -> Result<HashSet<TxoRef>, Error> {
+        let table = rx.open_table(Self::DEF)?;
+
+        let mut out = HashSet::new();
+
+        for entry in table.iter()? {
+            let (_, value) = entry?;
+            out.extend(decode_cursor(value.value())?.tombstones);
+        }
+
+        Ok(out)
+    }
+
     pub fn copy(rx: &ReadTransaction, wx: &WriteTransaction) -> Result<(), Error> {
         let source = rx.open_table(Self::DEF)?;
         let mut target = wx.open_table(Self::DEF)?;
@@ -359,6 +591,21 @@ impl CursorTable {
         Ok(())
     }
 
+    pub fn first(rx: &ReadTransaction) -> Result<Option<(BlockSlot, CursorValue)>, Error> {
+        let table = rx.open_table(Self::DEF)?;
+
+        let first = table.first()?;
+
+        if let Some((slot, value)) = first {
+            let slot = slot.value();
+            let value = decode_cursor(value.value())?;
+
+            Ok(Some((slot, value)))
+        } else {
+            Ok(None)
+        }
+    }
+
     pub fn last(rx: &ReadTransaction) -> Result<Option<(BlockSlot, CursorValue)>, Error> {
         let table = rx.open_table(Self::DEF)?;
 
@@ -366,7 +613,7 @@ impl CursorTable {
 
         if let Some((slot, value)) = last {
             let slot = slot.value();
-            let value = bincode::deserialize(value.value()).unwrap();
+            let value = decode_cursor(value.value())?;
 
             Ok(Some((slot, value)))
         } else {
@@ -375,6 +622,55 @@ impl CursorTable {
     }
 }
 
+/// The deltas of the volatile window of a split store, kept so that they can
+/// be migrated to the immutable db once finalized
+pub struct VolatileDeltasTable;
+
+impl VolatileDeltasTable {
+    pub const DEF: TableDefinition<'static, BlockSlot, &'static [u8]> =
+        TableDefinition::new("volatiledeltas");
+
+    pub fn initialize(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.open_table(Self::DEF)?;
+
+        Ok(())
+    }
+
+    /// Returns the deltas before `until`, in ascending slot order
+    pub fn get_range(
+        rx: &ReadTransaction,
+        until: BlockSlot,
+    ) -> Result<Vec<(BlockSlot, LedgerDelta)>, Error> {
+        let table = rx.open_table(Self::DEF)?;
+
+        let mut out = vec![];
+
+        for entry in table.range(..until)? {
+            let (slot, value) = entry?;
+            let delta = LedgerDelta::from_bincode(value.value()).map_err(Error::DeltaDecoding)?;
+
+            out.push((slot.value(), delta));
+        }
+
+        Ok(out)
+    }
+
+    pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut table = wx.open_table(Self::DEF)?;
+
+        if let Some(ChainPoint(slot, _)) = delta.new_position.as_ref() {
+            let value = bincode::serialize(delta).unwrap();
+            table.insert(slot, value.as_slice())?;
+        }
+
+        if let Some(ChainPoint(slot, _)) = delta.undone_position.as_ref() {
+            table.remove(slot)?;
+        }
+
+        Ok(())
+    }
+}
+
 pub struct FilterIndexes;
 
 struct SplitAddressResult(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<u8>>);
@@ -409,10 +705,10 @@ impl FilterIndexes {
         rx: &ReadTransaction,
         table_def: MultimapTableDefinition<&[u8], UtxosKey>,
         key: &[u8],
-    ) -> Result<HashSet<TxoRef>, Error> {
+    ) -> Result<UtxoSet, Error> {
         let table = rx.open_multimap_table(table_def)?;
 
-        let mut out = HashSet::new();
+        let mut out = UtxoSet::new();
 
         for item in table.get(key)? {
             let item = item?;
@@ -423,42 +719,82 @@ impl FilterIndexes {
         Ok(out)
     }
 
-    pub fn get_by_address(
-        rx: &ReadTransaction,
-        exact_address: &[u8],
-    ) -> Result<HashSet<TxoRef>, Error> {
+    pub fn get_by_address(rx: &ReadTransaction, exact_address: &[u8]) -> Result<UtxoSet, Error> {
         Self::get_by_key(rx, Self::BY_ADDRESS, exact_address)
     }
 
-    pub fn get_by_payment(
-        rx: &ReadTransaction,
-        payment_part: &[u8],
-    ) -> Result<HashSet<TxoRef>, Error> {
+    pub fn get_by_payment(rx: &ReadTransaction, payment_part: &[u8]) -> Result<UtxoSet, Error> {
         Self::get_by_key(rx, Self::BY_PAYMENT, payment_part)
     }
 
-    pub fn get_by_stake(rx: &ReadTransaction, stake_part: &[u8]) -> Result<HashSet<TxoRef>, Error> {
+    pub fn get_by_stake(rx: &ReadTransaction, stake_part: &[u8]) -> Result<UtxoSet, Error> {
         Self::get_by_key(rx, Self::BY_STAKE, stake_part)
     }
 
-    pub fn get_by_policy(rx: &ReadTransaction, policy: &[u8]) -> Result<HashSet<TxoRef>, Error> {
+    pub fn get_by_policy(rx: &ReadTransaction, policy: &[u8]) -> Result<UtxoSet, Error> {
         Self::get_by_key(rx, Self::BY_POLICY, policy)
     }
 
-    pub fn get_by_asset(rx: &ReadTransaction, asset: &[u8]) -> Result<HashSet<TxoRef>, Error> {
+    pub fn get_by_asset(rx: &ReadTransaction, asset: &[u8]) -> Result<UtxoSet, Error> {
         Self::get_by_key(rx, Self::BY_ASSET, asset)
     }
 
+    /// Lists the distinct stake parts present in the index, in key order
+    ///
+    /// Iteration starts right after the `cursor` key (if any) and returns at
+    /// most `limit` items. The second value of the tuple is the cursor to
+    /// request the next page, `None` if there are no more keys.
+    pub fn list_stake_keys(
+        rx: &ReadTransaction,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), Error> {
+        use std::ops::Bound;
+
+        let table = rx.open_multimap_table(Self::BY_STAKE)?;
+
+        let start = match cursor {
+            Some(x) => Bound::Excluded(x),
+            None => Bound::Unbounded,
+        };
+
+        let mut out = Vec::with_capacity(limit);
+        let mut more = false;
+
+        for entry in table.range::<&[u8]>((start, Bound::Unbounded))? {
+            let (key, _) = entry?;
+
+            if out.len() == limit {
+                more = true;
+                break;
+            }
+
+            out.push(key.value().to_vec());
+        }
+
+        let next = match more {
+            true => out.last().cloned(),
+            false => None,
+        };
+
+        Ok((out, next))
+    }
+
     fn split_address(utxo: &MultiEraOutput) -> Result<SplitAddressResult, Error> {
-        use pallas::ledger::addresses::Address;
+        use pallas::ledger::addresses::{Address, ShelleyDelegationPart};
 
         match utxo.address() {
             Ok(address) => match &address {
                 Address::Shelley(x) => {
                     let a = x.to_vec();
+                    // the payment index keys on the bare credential hash so that base and
+                    // enterprise addresses sharing a payment key resolve to the same entry
                     let b = x.payment().to_vec();
-                    let c = x.delegation().to_vec();
-                    Ok(SplitAddressResult(Some(a), Some(b), Some(c)))
+                    let c = match x.delegation() {
+                        ShelleyDelegationPart::Null => None,
+                        d => Some(d.to_vec()),
+                    };
+                    Ok(SplitAddressResult(Some(a), Some(b), c))
                 }
                 Address::Stake(x) => {
                     let a = x.to_vec();
@@ -490,7 +826,7 @@ impl FilterIndexes {
             let v: (&[u8; 32], u32) = (&utxo.0, utxo.1);
 
             // TODO: decoding here is very inefficient
-            let body = MultiEraOutput::try_from(body).unwrap();
+            let body = MultiEraOutput::try_from(body)?;
             let SplitAddressResult(addr, pay, stake) = Self::split_address(&body)?;
 
             if let Some(k) = addr {
@@ -525,7 +861,7 @@ impl FilterIndexes {
             let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
 
             // TODO: decoding here is very inefficient
-            let body = MultiEraOutput::try_from(body).unwrap();
+            let body = MultiEraOutput::try_from(body)?;
 
             let SplitAddressResult(addr, pay, stake) = Self::split_address(&body)?;
 
@@ -579,6 +915,22 @@ impl FilterIndexes {
         Ok(())
     }
 
+    /// Drops every entry of the indexes
+    pub fn clear(wx: &WriteTransaction) -> Result<(), Error> {
+        for def in [
+            Self::BY_ADDRESS,
+            Self::BY_PAYMENT,
+            Self::BY_STAKE,
+            Self::BY_POLICY,
+            Self::BY_ASSET,
+        ] {
+            wx.delete_multimap_table(def)?;
+            wx.open_multimap_table(def)?;
+        }
+
+        Ok(())
+    }
+
     pub fn copy(rx: &ReadTransaction, wx: &WriteTransaction) -> Result<(), Error> {
         Self::copy_table(rx, wx, Self::BY_ADDRESS)?;
         Self::copy_table(rx, wx, Self::BY_PAYMENT)?;
@@ -588,4 +940,487 @@ impl FilterIndexes {
 
         Ok(())
     }
+
+    const INDEXES: [MultimapTableDefinition<'static, &'static [u8], UtxosKey>; 5] = [
+        Self::BY_ADDRESS,
+        Self::BY_PAYMENT,
+        Self::BY_STAKE,
+        Self::BY_POLICY,
+        Self::BY_ASSET,
+    ];
+
+    pub(crate) const ADDRESS: usize = 0;
+    pub(crate) const PAYMENT: usize = 1;
+    pub(crate) const STAKE: usize = 2;
+    pub(crate) const POLICY: usize = 3;
+    pub(crate) const ASSET: usize = 4;
+
+    /// The keys an output is stored under, as positions in `INDEXES`
+    pub(crate) fn index_keys(body: &MultiEraOutput) -> Result<Vec<(usize, Vec<u8>)>, Error> {
+        let SplitAddressResult(addr, pay, stake) = Self::split_address(body)?;
+
+        let mut out = vec![];
+
+        out.extend(addr.map(|k| (Self::ADDRESS, k)));
+        out.extend(pay.map(|k| (Self::PAYMENT, k)));
+        out.extend(stake.map(|k| (Self::STAKE, k)));
+
+        for batch in body.non_ada_assets() {
+            out.push((Self::POLICY, batch.policy().to_vec()));
+
+            for asset in batch.assets() {
+                let mut subject = asset.policy().to_vec();
+                subject.extend(asset.name());
+
+                out.push((Self::ASSET, subject));
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Cross-checks the indexes against the utxo set
+    ///
+    /// Utxos in `pending` were consumed but not finalized yet, they're no
+    /// longer indexed even if still in the utxo table. Utxos that can't be
+    /// decoded are skipped, `UtxosTable::verify` reports them. The expected
+    /// entries are held in memory, this is meant for diagnostics rather than
+    /// for routine use.
+    pub fn verify(
+        rx: &ReadTransaction,
+        pending: &HashSet<TxoRef>,
+    ) -> Result<Vec<IndexInconsistency>, Error> {
+        let mut expected = BTreeSet::new();
+
+        for entry in UtxosTable::iter(rx)? {
+            let (txo, body) = entry?;
+
+            if pending.contains(&txo) {
+                continue;
+            }
+
+            let Ok(output) = MultiEraOutput::try_from(&body) else {
+                continue;
+            };
+
+            let Ok(keys) = Self::index_keys(&output) else {
+                continue;
+            };
+
+            for (index, key) in keys {
+                expected.insert((index, key, txo.clone()));
+            }
+        }
+
+        let mut actual = BTreeSet::new();
+
+        for (index, def) in Self::INDEXES.into_iter().enumerate() {
+            let table = rx.open_multimap_table(def)?;
+
+            for entry in table.range::<&[u8]>(..)? {
+                let (key, values) = entry?;
+
+                for value in values {
+                    let value = value?;
+                    let (hash, idx) = value.value();
+                    let txo = TxoRef((*hash).into(), idx);
+
+                    actual.insert((index, key.value().to_vec(), txo));
+                }
+            }
+        }
+
+        let inconsistency =
+            |(index, key, txo): &(usize, Vec<u8>, TxoRef), kind| IndexInconsistency {
+                index: Self::INDEXES[*index].name().to_owned(),
+                key: key.clone(),
+                txo: txo.clone(),
+                kind,
+            };
+
+        let dangling = actual
+            .difference(&expected)
+            .map(|x| inconsistency(x, InconsistencyKind::Dangling));
+
+        let unindexed = expected
+            .difference(&actual)
+            .map(|x| inconsistency(x, InconsistencyKind::Unindexed));
+
+        Ok(dangling.chain(unindexed).collect())
+    }
+}
+
+/// An optional index of utxos by the hash of the datum they carry
+///
+/// Outputs with a datum hash are keyed by that hash, outputs with an inline
+/// datum are keyed by the hash of the inline datum, so both can be found with
+/// the same query. The table only exists if the index was enabled, which is
+/// why it isn't part of the schema hash.
+pub struct DatumIndex;
+
+impl DatumIndex {
+    pub const BY_DATUM: MultimapTableDefinition<'static, &'static [u8], UtxosKey> =
+        MultimapTableDefinition::new("bydatum");
+
+    pub fn initialize(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.open_multimap_table(Self::BY_DATUM)?;
+
+        Ok(())
+    }
+
+    pub fn is_enabled(wx: &WriteTransaction) -> Result<bool, Error> {
+        let found = wx
+            .list_multimap_tables()?
+            .any(|t| t.name() == Self::BY_DATUM.name());
+
+        Ok(found)
+    }
+
+    fn datum_key(utxo: &MultiEraOutput) -> Option<Hash<32>> {
+        use pallas::ledger::primitives::babbage::PseudoDatumOption;
+
+        match utxo.datum()? {
+            PseudoDatumOption::Hash(x) => Some(x),
+            PseudoDatumOption::Data(x) => {
+                Some(pallas::crypto::hash::Hasher::<256>::hash(x.0.raw_cbor()))
+            }
+        }
+    }
+
+    pub fn get_by_hash(rx: &ReadTransaction, hash: &[u8]) -> Result<UtxoSet, Error> {
+        let table = match rx.open_multimap_table(Self::BY_DATUM) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Err(Error::QueryNotSupported),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut out = UtxoSet::new();
+
+        for item in table.get(hash)? {
+            let item = item?;
+            let (hash, idx) = item.value();
+            out.insert(TxoRef((*hash).into(), idx));
+        }
+
+        Ok(out)
+    }
+
+    pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut table = wx.open_multimap_table(Self::BY_DATUM)?;
+
+        let trackable = delta
+            .produced_utxo
+            .iter()
+            .chain(delta.recovered_stxi.iter());
+
+        for (utxo, body) in trackable {
+            let v: (&[u8; 32], u32) = (&utxo.0, utxo.1);
+            let body = MultiEraOutput::try_from(body)?;
+
+            if let Some(k) = Self::datum_key(&body) {
+                table.insert(k.as_slice(), v)?;
+            }
+        }
+
+        let forgettable = delta.consumed_utxo.iter().chain(delta.undone_utxo.iter());
+
+        for (stxi, body) in forgettable {
+            let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
+            let body = MultiEraOutput::try_from(body)?;
+
+            if let Some(k) = Self::datum_key(&body) {
+                table.remove(k.as_slice(), v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops every entry of the index, keeping it enabled
+    pub fn clear(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.delete_multimap_table(Self::BY_DATUM)?;
+        wx.open_multimap_table(Self::BY_DATUM)?;
+
+        Ok(())
+    }
+
+    pub fn copy(rx: &ReadTransaction, wx: &WriteTransaction) -> Result<(), Error> {
+        let source = match rx.open_multimap_table(Self::BY_DATUM) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut target = wx.open_multimap_table(Self::BY_DATUM)?;
+
+        for entry in source.range::<&[u8]>(..)? {
+            let (key, values) = entry?;
+            for value in values {
+                let value = value?;
+                target.insert(key.value(), value.value())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An optional index of utxos by the hash of the reference script they carry
+///
+/// Like the datum index, the table only exists if the index was enabled and
+/// it isn't part of the schema hash.
+pub struct ScriptRefIndex;
+
+impl ScriptRefIndex {
+    pub const BY_SCRIPT_REF: MultimapTableDefinition<'static, &'static [u8], UtxosKey> =
+        MultimapTableDefinition::new("byscriptref");
+
+    pub fn initialize(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.open_multimap_table(Self::BY_SCRIPT_REF)?;
+
+        Ok(())
+    }
+
+    pub fn is_enabled(wx: &WriteTransaction) -> Result<bool, Error> {
+        let found = wx
+            .list_multimap_tables()?
+            .any(|t| t.name() == Self::BY_SCRIPT_REF.name());
+
+        Ok(found)
+    }
+
+    pub fn get_by_hash(rx: &ReadTransaction, hash: &[u8]) -> Result<UtxoSet, Error> {
+        let table = match rx.open_multimap_table(Self::BY_SCRIPT_REF) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Err(Error::QueryNotSupported),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut out = UtxoSet::new();
+
+        for item in table.get(hash)? {
+            let item = item?;
+            let (hash, idx) = item.value();
+            out.insert(TxoRef((*hash).into(), idx));
+        }
+
+        Ok(out)
+    }
+
+    pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut table = wx.open_multimap_table(Self::BY_SCRIPT_REF)?;
+
+        let trackable = delta
+            .produced_utxo
+            .iter()
+            .chain(delta.recovered_stxi.iter());
+
+        for (utxo, body) in trackable {
+            let v: (&[u8; 32], u32) = (&utxo.0, utxo.1);
+
+            if let Some(k) = crate::ledger::reference_script_hash(body)? {
+                table.insert(k.as_slice(), v)?;
+            }
+        }
+
+        let forgettable = delta.consumed_utxo.iter().chain(delta.undone_utxo.iter());
+
+        for (stxi, body) in forgettable {
+            let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
+
+            if let Some(k) = crate::ledger::reference_script_hash(body)? {
+                table.remove(k.as_slice(), v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops every entry of the index, keeping it enabled
+    pub fn clear(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.delete_multimap_table(Self::BY_SCRIPT_REF)?;
+        wx.open_multimap_table(Self::BY_SCRIPT_REF)?;
+
+        Ok(())
+    }
+
+    pub fn copy(rx: &ReadTransaction, wx: &WriteTransaction) -> Result<(), Error> {
+        let source = match rx.open_multimap_table(Self::BY_SCRIPT_REF) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut target = wx.open_multimap_table(Self::BY_SCRIPT_REF)?;
+
+        for entry in source.range::<&[u8]>(..)? {
+            let (key, values) = entry?;
+            for value in values {
+                let value = value?;
+                target.insert(key.value(), value.value())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An optional index of unspent utxos by the slot of the block that produced
+/// them
+///
+/// Consuming a utxo only tells us its ref, so a second table keeps the
+/// producing slot of each indexed utxo. Those entries outlive the consumption
+/// until the slot that consumed the utxo is compacted, which is what allows an
+/// undo to put recovered utxos back at their original slot. Like the other
+/// optional indexes, the tables only exist if the index was enabled and they
+/// aren't part of the schema hash.
+pub struct SlotIndex;
+
+impl SlotIndex {
+    pub const BY_SLOT: MultimapTableDefinition<'static, BlockSlot, UtxosKey> =
+        MultimapTableDefinition::new("byslot");
+
+    pub const SLOT_OF: TableDefinition<'static, UtxosKey, BlockSlot> =
+        TableDefinition::new("slotbyutxo");
+
+    pub fn initialize(wx: &WriteTransaction) -> Result<(), Error> {
+        wx.open_multimap_table(Self::BY_SLOT)?;
+        wx.open_table(Self::SLOT_OF)?;
+
+        Ok(())
+    }
+
+    pub fn is_enabled(wx: &WriteTransaction) -> Result<bool, Error> {
+        let found = wx
+            .list_multimap_tables()?
+            .any(|t| t.name() == Self::BY_SLOT.name());
+
+        Ok(found)
+    }
+
+    pub fn get_range(
+        rx: &ReadTransaction,
+        from: BlockSlot,
+        to: BlockSlot,
+    ) -> Result<UtxoSet, Error> {
+        let table = match rx.open_multimap_table(Self::BY_SLOT) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Err(Error::QueryNotSupported),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut out = UtxoSet::new();
+
+        for entry in table.range(from..=to)? {
+            let (_, values) = entry?;
+
+            for item in values {
+                let item = item?;
+                let (hash, idx) = item.value();
+                out.insert(TxoRef((*hash).into(), idx));
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn apply(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        let mut by_slot = wx.open_multimap_table(Self::BY_SLOT)?;
+        let mut slot_of = wx.open_table(Self::SLOT_OF)?;
+
+        if let Some(ChainPoint(slot, _)) = delta.new_position.as_ref() {
+            for utxo in delta.produced_utxo.keys() {
+                let v: (&[u8; 32], u32) = (&utxo.0, utxo.1);
+                by_slot.insert(slot, v)?;
+                slot_of.insert(v, slot)?;
+            }
+        }
+
+        for stxi in delta.consumed_utxo.keys() {
+            let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
+            let slot = slot_of.get(v)?.map(|x| x.value());
+
+            if let Some(slot) = slot {
+                by_slot.remove(slot, v)?;
+            }
+        }
+
+        for utxo in delta.undone_utxo.keys() {
+            let v: (&[u8; 32], u32) = (&utxo.0, utxo.1);
+            let slot = slot_of.remove(v)?.map(|x| x.value());
+
+            if let Some(slot) = slot {
+                by_slot.remove(slot, v)?;
+            }
+        }
+
+        for stxi in delta.recovered_stxi.keys() {
+            let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
+            let slot = slot_of.get(v)?.map(|x| x.value());
+
+            if let Some(slot) = slot {
+                by_slot.insert(slot, v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies a delta that can't be undone, the producing slot of consumed
+    /// utxos is dropped right away
+    pub fn apply_immutable(wx: &WriteTransaction, delta: &LedgerDelta) -> Result<(), Error> {
+        Self::apply(wx, delta)?;
+
+        let mut slot_of = wx.open_table(Self::SLOT_OF)?;
+
+        for stxi in delta.consumed_utxo.keys() {
+            let v: (&[u8; 32], u32) = (&stxi.0, stxi.1);
+            slot_of.remove(v)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compact(
+        wx: &WriteTransaction,
+        _slot: BlockSlot,
+        tombstone: &[TxoRef],
+    ) -> Result<(), Error> {
+        let mut slot_of = wx.open_table(Self::SLOT_OF)?;
+
+        for txo in tombstone {
+            let v: (&[u8; 32], u32) = (&txo.0, txo.1);
+            slot_of.remove(v)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn copy(rx: &ReadTransaction, wx: &WriteTransaction) -> Result<(), Error> {
+        let source = match rx.open_multimap_table(Self::BY_SLOT) {
+            Ok(x) => x,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(()),
+            Err(x) => return Err(x.into()),
+        };
+
+        let mut target = wx.open_multimap_table(Self::BY_SLOT)?;
+
+        for entry in source.range::<BlockSlot>(..)? {
+            let (key, values) = entry?;
+            for value in values {
+                let value = value?;
+                target.insert(key.value(), value.value())?;
+            }
+        }
+
+        let source = rx.open_table(Self::SLOT_OF)?;
+        let mut target = wx.open_table(Self::SLOT_OF)?;
+
+        for entry in source.iter()? {
+            let (k, v) = entry?;
+            target.insert(k.value(), v.value())?;
+        }
+
+        Ok(())
+    }
 }
@@ -37,6 +37,16 @@ impl LedgerStore {
         tables::BlocksTable::last(&rx)
     }
 
+    /// The blocks table doesn't record heights, the height is always unknown
+    pub fn cursor_with_height(&self) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, Error> {
+        Ok(self.cursor()?.map(|x| (x, None)))
+    }
+
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let rx = self.db().begin_read()?;
+        tables::BlocksTable::first(&rx)
+    }
+
     pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), Error> {
         let mut wx = self.db().begin_write()?;
         wx.set_durability(Durability::Eventual);
@@ -53,9 +63,13 @@ impl LedgerStore {
         Ok(())
     }
 
-    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), Error> {
+    /// Compacts the slots before `until` in a single transaction
+    ///
+    /// Returns the amount of slots that were compacted.
+    pub fn finalize(&mut self, until: BlockSlot) -> Result<usize, Error> {
         let rx = self.db().begin_read()?;
         let tss = tables::TombstonesTable::get_range(&rx, until)?;
+        let compacted = tss.len();
 
         let mut wx = self.db().begin_write()?;
         wx.set_durability(Durability::Eventual);
@@ -68,7 +82,12 @@ impl LedgerStore {
 
         wx.commit()?;
 
-        Ok(())
+        Ok(compacted)
+    }
+
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let rx = self.db().begin_read()?;
+        tables::UtxosTable::get_by_tx(&rx, tx_hash)
     }
 
     pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
@@ -85,6 +104,24 @@ impl LedgerStore {
         let rx = self.db().begin_read()?;
         tables::PParamsTable::get_range(&rx, until)
     }
+
+    pub fn stream_pparams(&self, until: BlockSlot) -> Result<tables::PParamsIterator, Error> {
+        let rx = self.db().begin_read()?;
+        tables::PParamsTable::stream_range(&rx, until)
+    }
+
+    pub fn iter_utxos(&self) -> Result<tables::LiveUtxosIterator, Error> {
+        let rx = self.db().begin_read()?;
+
+        let consumed = tables::TombstonesTable::get_range(&rx, BlockSlot::MAX)?
+            .into_iter()
+            .flat_map(|(_, txos)| txos)
+            .collect();
+
+        let utxos = tables::UtxosTable::iter(&rx)?;
+
+        Ok(tables::LiveUtxosIterator::new(vec![utxos], consumed))
+    }
 }
 
 impl From<Database> for LedgerStore {
@@ -1,4 +1,5 @@
 use ::redb::{Database, Durability};
+use itertools::Itertools;
 use std::sync::Arc;
 
 use crate::state::*;
@@ -44,15 +45,47 @@ impl LedgerStore {
         Ok(last)
     }
 
+    pub fn cursor_with_height(&self) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, Error> {
+        let rx = self.db().begin_read()?;
+
+        let last = tables::CursorTable::last(&rx)?.map(|(k, v)| (ChainPoint(k, v.hash), v.height));
+
+        Ok(last)
+    }
+
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let rx = self.db().begin_read()?;
+
+        let first = tables::CursorTable::first(&rx)?.map(|(k, v)| ChainPoint(k, v.hash));
+
+        Ok(first)
+    }
+
     pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), Error> {
         let mut wx = self.db().begin_write()?;
         wx.set_durability(Durability::Eventual);
 
+        let datum_index = tables::DatumIndex::is_enabled(&wx)?;
+        let script_ref_index = tables::ScriptRefIndex::is_enabled(&wx)?;
+        let slot_index = tables::SlotIndex::is_enabled(&wx)?;
+
         for delta in deltas {
             tables::CursorTable::apply(&wx, delta)?;
             tables::UtxosTable::apply(&wx, delta)?;
             tables::PParamsTable::apply(&wx, delta)?;
             tables::FilterIndexes::apply(&wx, delta)?;
+
+            if datum_index {
+                tables::DatumIndex::apply(&wx, delta)?;
+            }
+
+            if script_ref_index {
+                tables::ScriptRefIndex::apply(&wx, delta)?;
+            }
+
+            if slot_index {
+                tables::SlotIndex::apply(&wx, delta)?;
+            }
         }
 
         wx.commit()?;
@@ -60,16 +93,116 @@ impl LedgerStore {
         Ok(())
     }
 
-    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), Error> {
+    /// Compacts the slots before `until`, committing every `batch` slots
+    ///
+    /// Slots are compacted in ascending order, so an interrupted finalize
+    /// leaves the store finalized up to the last committed batch. `progress`
+    /// is called after each commit with the slots compacted so far and the
+    /// total to compact.
+    pub fn finalize(
+        &mut self,
+        until: BlockSlot,
+        batch: usize,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error> {
+        let batch = batch.max(1);
+
         let rx = self.db().begin_read()?;
-        let cursors = tables::CursorTable::get_range(&rx, until)?;
+        let total = tables::CursorTable::count_range(&rx, until)?;
+        drop(rx);
+
+        let mut done = 0;
+
+        loop {
+            let rx = self.db().begin_read()?;
+            let cursors = tables::CursorTable::get_range(&rx, until, batch)?;
+            drop(rx);
+
+            if cursors.is_empty() {
+                break;
+            }
+
+            let exhausted = cursors.len() < batch;
+            let compacted = cursors.len();
+
+            let mut wx = self.db().begin_write()?;
+            wx.set_durability(Durability::Eventual);
+
+            let slot_index = tables::SlotIndex::is_enabled(&wx)?;
+
+            for (slot, value) in cursors {
+                tables::CursorTable::compact(&wx, slot)?;
+                tables::UtxosTable::compact(&wx, slot, &value.tombstones)?;
+
+                if slot_index {
+                    tables::SlotIndex::compact(&wx, slot, &value.tombstones)?;
+                }
+            }
+
+            wx.commit()?;
+
+            done += compacted;
+            progress(done, total);
 
+            if exhausted {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn repair_cursor(&mut self, point: &ChainPoint) -> Result<(), Error> {
+        let wx = self.db().begin_write()?;
+        tables::CursorTable::reset(&wx, point, None)?;
+        wx.commit()?;
+
+        Ok(())
+    }
+
+    /// Rebuilds the filter indexes, and the optional ones that are enabled,
+    /// from the current utxo set
+    ///
+    /// The slot index is left as-is, the utxo set doesn't know the slots that
+    /// produced each utxo.
+    pub fn rebuild_indexes(&self) -> Result<(), Error> {
         let mut wx = self.db().begin_write()?;
-        wx.set_durability(Durability::Eventual);
+        wx.set_durability(Durability::Immediate);
+
+        let datum_index = tables::DatumIndex::is_enabled(&wx)?;
+        let script_ref_index = tables::ScriptRefIndex::is_enabled(&wx)?;
 
-        for (slot, value) in cursors {
-            tables::CursorTable::compact(&wx, slot)?;
-            tables::UtxosTable::compact(&wx, slot, &value.tombstones)?;
+        tables::FilterIndexes::clear(&wx)?;
+
+        if datum_index {
+            tables::DatumIndex::clear(&wx)?;
+        }
+
+        if script_ref_index {
+            tables::ScriptRefIndex::clear(&wx)?;
+        }
+
+        let rx = self.db().begin_read()?;
+
+        let utxo_chunks = tables::UtxosTable::iter(&rx)?.chunks(1000);
+
+        for chunk in utxo_chunks.into_iter() {
+            let chunk: Vec<_> = chunk.try_collect()?;
+
+            let delta = LedgerDelta {
+                produced_utxo: chunk.into_iter().collect(),
+                ..Default::default()
+            };
+
+            tables::FilterIndexes::apply(&wx, &delta)?;
+
+            if datum_index {
+                tables::DatumIndex::apply(&wx, &delta)?;
+            }
+
+            if script_ref_index {
+                tables::ScriptRefIndex::apply(&wx, &delta)?;
+            }
         }
 
         wx.commit()?;
@@ -77,6 +210,13 @@ impl LedgerStore {
         Ok(())
     }
 
+    pub fn verify_indexes(&self) -> Result<Vec<IndexInconsistency>, Error> {
+        let rx = self.db().begin_read()?;
+        let pending = tables::CursorTable::pending_tombstones(&rx)?;
+
+        tables::FilterIndexes::verify(&rx, &pending)
+    }
+
     pub fn copy(&self, target: &Self) -> Result<(), Error> {
         let rx = self.db().begin_read()?;
         let wx = target.db().begin_write()?;
@@ -85,12 +225,20 @@ impl LedgerStore {
         tables::UtxosTable::copy(&rx, &wx)?;
         tables::PParamsTable::copy(&rx, &wx)?;
         tables::FilterIndexes::copy(&rx, &wx)?;
+        tables::DatumIndex::copy(&rx, &wx)?;
+        tables::ScriptRefIndex::copy(&rx, &wx)?;
+        tables::SlotIndex::copy(&rx, &wx)?;
 
         wx.commit()?;
 
         Ok(())
     }
 
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let rx = self.db().begin_read()?;
+        tables::UtxosTable::get_by_tx(&rx, tx_hash)
+    }
+
     pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
         // exit early before opening a read tx in case there's nothing to fetch
         if refs.is_empty() {
@@ -106,6 +254,19 @@ impl LedgerStore {
         tables::PParamsTable::get_range(&rx, until)
     }
 
+    pub fn stream_pparams(&self, until: BlockSlot) -> Result<tables::PParamsIterator, Error> {
+        let rx = self.db().begin_read()?;
+        tables::PParamsTable::stream_range(&rx, until)
+    }
+
+    pub fn iter_utxos(&self) -> Result<tables::LiveUtxosIterator, Error> {
+        let rx = self.db().begin_read()?;
+        let consumed = tables::CursorTable::pending_tombstones(&rx)?;
+        let utxos = tables::UtxosTable::iter(&rx)?;
+
+        Ok(tables::LiveUtxosIterator::new(vec![utxos], consumed))
+    }
+
     pub fn get_utxos_by_address(&self, address: &[u8]) -> Result<UtxoSet, Error> {
         let rx = self.db().begin_read()?;
         tables::FilterIndexes::get_by_address(&rx, address)
@@ -121,6 +282,67 @@ impl LedgerStore {
         tables::FilterIndexes::get_by_stake(&rx, stake)
     }
 
+    pub fn list_stake_credentials(
+        &self,
+        cursor: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<(Vec<Vec<u8>>, Option<Vec<u8>>), Error> {
+        let rx = self.db().begin_read()?;
+        tables::FilterIndexes::list_stake_keys(&rx, cursor.as_deref(), limit)
+    }
+
+    pub fn enable_datum_index(&self) -> Result<(), Error> {
+        let mut wx = self.db().begin_write()?;
+        wx.set_durability(Durability::Immediate);
+
+        tables::DatumIndex::initialize(&wx)?;
+
+        wx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn get_utxos_by_datum_hash(&self, hash: &[u8]) -> Result<UtxoSet, Error> {
+        let rx = self.db().begin_read()?;
+        tables::DatumIndex::get_by_hash(&rx, hash)
+    }
+
+    pub fn enable_slot_index(&self) -> Result<(), Error> {
+        let mut wx = self.db().begin_write()?;
+        wx.set_durability(Durability::Immediate);
+
+        tables::SlotIndex::initialize(&wx)?;
+
+        wx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn get_utxos_produced_in_range(
+        &self,
+        from: BlockSlot,
+        to: BlockSlot,
+    ) -> Result<UtxoSet, Error> {
+        let rx = self.db().begin_read()?;
+        tables::SlotIndex::get_range(&rx, from, to)
+    }
+
+    pub fn enable_script_ref_index(&self) -> Result<(), Error> {
+        let mut wx = self.db().begin_write()?;
+        wx.set_durability(Durability::Immediate);
+
+        tables::ScriptRefIndex::initialize(&wx)?;
+
+        wx.commit()?;
+
+        Ok(())
+    }
+
+    pub fn get_utxos_by_script_ref(&self, hash: &[u8]) -> Result<UtxoSet, Error> {
+        let rx = self.db().begin_read()?;
+        tables::ScriptRefIndex::get_by_hash(&rx, hash)
+    }
+
     pub fn get_utxos_by_policy(&self, policy: &[u8]) -> Result<UtxoSet, Error> {
         let rx = self.db().begin_read()?;
         tables::FilterIndexes::get_by_policy(&rx, policy)
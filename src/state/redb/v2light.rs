@@ -44,6 +44,22 @@ impl LedgerStore {
         Ok(last)
     }
 
+    pub fn cursor_with_height(&self) -> Result<Option<(ChainPoint, Option<BlockHeight>)>, Error> {
+        let rx = self.db().begin_read()?;
+
+        let last = tables::CursorTable::last(&rx)?.map(|(k, v)| (ChainPoint(k, v.hash), v.height));
+
+        Ok(last)
+    }
+
+    pub fn first_cursor(&self) -> Result<Option<ChainPoint>, Error> {
+        let rx = self.db().begin_read()?;
+
+        let first = tables::CursorTable::first(&rx)?.map(|(k, v)| ChainPoint(k, v.hash));
+
+        Ok(first)
+    }
+
     pub fn apply(&mut self, deltas: &[LedgerDelta]) -> Result<(), Error> {
         let mut wx = self.db().begin_write()?;
         wx.set_durability(Durability::Eventual);
@@ -59,18 +75,62 @@ impl LedgerStore {
         Ok(())
     }
 
-    pub fn finalize(&mut self, until: BlockSlot) -> Result<(), Error> {
+    /// Compacts the slots before `until`, committing every `batch` slots
+    ///
+    /// Slots are compacted in ascending order, so an interrupted finalize
+    /// leaves the store finalized up to the last committed batch. `progress`
+    /// is called after each commit with the slots compacted so far and the
+    /// total to compact.
+    pub fn finalize(
+        &mut self,
+        until: BlockSlot,
+        batch: usize,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error> {
+        let batch = batch.max(1);
+
         let rx = self.db().begin_read()?;
-        let cursors = tables::CursorTable::get_range(&rx, until)?;
+        let total = tables::CursorTable::count_range(&rx, until)?;
+        drop(rx);
 
-        let mut wx = self.db().begin_write()?;
-        wx.set_durability(Durability::Eventual);
+        let mut done = 0;
+
+        loop {
+            let rx = self.db().begin_read()?;
+            let cursors = tables::CursorTable::get_range(&rx, until, batch)?;
+            drop(rx);
+
+            if cursors.is_empty() {
+                break;
+            }
+
+            let exhausted = cursors.len() < batch;
+            let compacted = cursors.len();
 
-        for (slot, value) in cursors {
-            tables::CursorTable::compact(&wx, slot)?;
-            tables::UtxosTable::compact(&wx, slot, &value.tombstones)?;
+            let mut wx = self.db().begin_write()?;
+            wx.set_durability(Durability::Eventual);
+
+            for (slot, value) in cursors {
+                tables::CursorTable::compact(&wx, slot)?;
+                tables::UtxosTable::compact(&wx, slot, &value.tombstones)?;
+            }
+
+            wx.commit()?;
+
+            done += compacted;
+            progress(done, total);
+
+            if exhausted {
+                break;
+            }
         }
 
+        Ok(())
+    }
+
+    pub fn repair_cursor(&mut self, point: &ChainPoint) -> Result<(), Error> {
+        let wx = self.db().begin_write()?;
+        tables::CursorTable::reset(&wx, point, None)?;
         wx.commit()?;
 
         Ok(())
@@ -89,6 +149,11 @@ impl LedgerStore {
         Ok(())
     }
 
+    pub fn get_utxos_by_tx(&self, tx_hash: &[u8; 32]) -> Result<UtxoMap, Error> {
+        let rx = self.db().begin_read()?;
+        tables::UtxosTable::get_by_tx(&rx, tx_hash)
+    }
+
     pub fn get_utxos(&self, refs: Vec<TxoRef>) -> Result<UtxoMap, Error> {
         // exit early before opening a read tx in case there's nothing to fetch
         if refs.is_empty() {
@@ -104,6 +169,19 @@ impl LedgerStore {
         tables::PParamsTable::get_range(&rx, until)
     }
 
+    pub fn stream_pparams(&self, until: BlockSlot) -> Result<tables::PParamsIterator, Error> {
+        let rx = self.db().begin_read()?;
+        tables::PParamsTable::stream_range(&rx, until)
+    }
+
+    pub fn iter_utxos(&self) -> Result<tables::LiveUtxosIterator, Error> {
+        let rx = self.db().begin_read()?;
+        let consumed = tables::CursorTable::pending_tombstones(&rx)?;
+        let utxos = tables::UtxosTable::iter(&rx)?;
+
+        Ok(tables::LiveUtxosIterator::new(vec![utxos], consumed))
+    }
+
     /// Upgrades a v2-light store to v2 by adding indexes
     ///
     /// This method will fail if the store has been cloned and those instances
@@ -131,6 +209,7 @@ impl LedgerStore {
                 recovered_stxi: Default::default(),
                 undone_utxo: Default::default(),
                 new_pparams: Default::default(),
+                new_height: Default::default(),
             };
 
             tables::FilterIndexes::apply(&wx, &delta)?;
@@ -1,13 +1,69 @@
 use gasket::framework::*;
 use pallas::ledger::configs::{byron, shelley};
-use pallas::ledger::traverse::MultiEraBlock;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 use tracing::{debug, info};
 
+use crate::ledger::network::NetworkParams;
 use crate::wal::{self, LogValue, WalReader as _};
 use crate::{ledger, prelude::*};
 
 pub type UpstreamPort = gasket::messaging::InputPort<RollEvent>;
 
+/// Controls how many blocks are committed to the ledger in a single write
+///
+/// The WAL is always written before the ledger and it's the source of truth
+/// when recovering: on startup, the ledger stage looks up the ledger cursor
+/// in the WAL and replays every entry after it. Batching blocks only delays
+/// the ledger commit, a crash loses the uncommitted part of the batch which
+/// is then replayed from the WAL, so both stores converge on the same point.
+///
+/// Batches only form while catching up, once the stage reaches the end of the
+/// WAL the pending blocks are committed right away. Undos and the origin are
+/// never batched, pending blocks are committed before them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommitPolicy {
+    /// Max number of blocks applied in a single ledger commit
+    pub max_blocks: usize,
+
+    /// Max time (in milliseconds) a block can wait in a batch before commit
+    pub max_interval_ms: u64,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        Self {
+            max_blocks: 1,
+            max_interval_ms: 5_000,
+        }
+    }
+}
+
+impl CommitPolicy {
+    /// Tells if a batch of the given size and age should be committed
+    pub fn is_due(&self, pending: usize, age: Duration) -> bool {
+        pending >= self.max_blocks.max(1) || age >= Duration::from_millis(self.max_interval_ms)
+    }
+}
+
+/// Finds the WAL sequence from which the ledger needs to be replayed
+fn resume_seq(
+    wal: &crate::wal::redb::WalStore,
+    ledger: &crate::state::LedgerStore,
+) -> Result<wal::LogSeq, WorkerError> {
+    let cursor = ledger.cursor().or_panic()?;
+
+    info!(?cursor, "cursor found");
+
+    let point: wal::ChainPoint = cursor
+        .map(pallas::network::miniprotocols::Point::from)
+        .unwrap_or(pallas::network::miniprotocols::Point::Origin)
+        .into();
+
+    wal.assert_point(&point).or_panic()
+}
+
 #[derive(Stage)]
 #[stage(name = "ledger", unit = "RollEvent", worker = "Worker")]
 pub struct Stage {
@@ -15,7 +71,10 @@ pub struct Stage {
     ledger: crate::state::LedgerStore,
     byron: byron::GenesisFile,
     shelley: shelley::GenesisFile,
+    network: NetworkParams,
     mempool: crate::mempool::Mempool, // Add this line
+    delta_limits: ledger::DeltaLimits,
+    commit_policy: CommitPolicy,
 
     pub upstream: UpstreamPort,
 
@@ -33,6 +92,9 @@ impl Stage {
         mempool: crate::mempool::Mempool,
         byron: byron::GenesisFile,
         shelley: shelley::GenesisFile,
+        network: NetworkParams,
+        delta_limits: ledger::DeltaLimits,
+        commit_policy: CommitPolicy,
     ) -> Self {
         Self {
             wal,
@@ -40,6 +102,9 @@ impl Stage {
             mempool,
             byron,
             shelley,
+            network,
+            delta_limits,
+            commit_policy,
             upstream: Default::default(),
             block_count: Default::default(),
             wal_count: Default::default(),
@@ -49,7 +114,7 @@ impl Stage {
     fn process_origin(&mut self) -> Result<(), WorkerError> {
         info!("applying origin");
 
-        let delta = crate::ledger::compute_origin_delta(&self.byron);
+        let delta = crate::ledger::compute_genesis_delta(&self.byron, &self.shelley).or_panic()?;
         self.ledger.apply(&[delta]).or_panic()?;
 
         Ok(())
@@ -60,28 +125,44 @@ impl Stage {
 
         info!(slot, "undoing block");
 
-        let block = MultiEraBlock::decode(body).or_panic()?;
+        let block = crate::ledger::decode_block(body).or_panic()?;
         let context = crate::state::load_slice_for_block(&block, &self.ledger, &[]).or_panic()?;
 
         let delta = crate::ledger::compute_undo_delta(&block, context).or_panic()?;
-        self.ledger.apply(&[delta]).or_panic()?;
+        self.ledger.rollback(&[delta]).or_panic()?;
 
         self.mempool.undo_block(&block);
 
         Ok(())
     }
 
-    fn process_apply(&mut self, block: &wal::RawBlock) -> Result<(), WorkerError> {
-        let wal::RawBlock { slot, body, .. } = block;
-
-        info!(slot, "applying block");
+    /// Applies a batch of blocks to the ledger in a single commit
+    fn process_apply(&mut self, blocks: &[wal::RawBlock]) -> Result<(), WorkerError> {
+        if blocks.is_empty() {
+            return Ok(());
+        }
 
-        let block = MultiEraBlock::decode(body).or_panic()?;
+        let from = blocks.first().map(|x| x.slot);
+        let to = blocks.last().map(|x| x.slot);
+        info!(from, to, count = blocks.len(), "applying blocks");
 
-        crate::state::apply_block_batch([&block], &mut self.ledger, &self.byron, &self.shelley)
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|x| crate::ledger::decode_block(&x.body))
+            .collect::<Result<_, _>>()
             .or_panic()?;
 
-        self.mempool.apply_block(&block);
+        crate::state::apply_block_batch(
+            &blocks,
+            &mut self.ledger,
+            &self.network,
+            &self.delta_limits,
+        )
+        .or_panic()?;
+
+        for block in blocks.iter() {
+            self.mempool.apply_block(block);
+        }
 
         Ok(())
     }
@@ -89,7 +170,7 @@ impl Stage {
     fn process_wal(&mut self, log: wal::LogValue) -> Result<(), WorkerError> {
         match log {
             LogValue::Mark(wal::ChainPoint::Origin) => self.process_origin(),
-            LogValue::Apply(x) => self.process_apply(&x),
+            LogValue::Apply(x) => self.process_apply(&[x]),
             LogValue::Undo(x) => self.process_undo(&x),
             // we can skip marks since we know they have been already applied
             LogValue::Mark(..) => Ok(()),
@@ -102,16 +183,7 @@ pub struct Worker(wal::LogSeq);
 #[async_trait::async_trait(?Send)]
 impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
-        let cursor = stage.ledger.cursor().or_panic()?;
-
-        info!(?cursor, "cursor found");
-
-        let point = match cursor {
-            Some(ledger::ChainPoint(s, h)) => wal::ChainPoint::Specific(s, h),
-            None => wal::ChainPoint::Origin,
-        };
-
-        let seq = stage.wal.assert_point(&point).or_panic()?;
+        let seq = resume_seq(&stage.wal, &stage.ledger)?;
 
         info!(seq, "wal sequence found");
 
@@ -137,12 +209,191 @@ impl gasket::framework::Worker<Stage> for Worker {
         // TODO: analyze scenario where we're too far behind and this for loop takes
         // longer that the allocated policy timeout.
 
+        let mut pending = vec![];
+        let mut pending_since = Instant::now();
+        let mut last_seq = None;
+
         for (seq, log) in iter {
             debug!(seq, "processing wal entry");
-            stage.process_wal(log)?;
+            last_seq = Some(seq);
+
+            match log {
+                LogValue::Apply(block) => {
+                    if pending.is_empty() {
+                        pending_since = Instant::now();
+                    }
+
+                    pending.push(block);
+                }
+                other => {
+                    stage.process_apply(&pending)?;
+                    pending.clear();
+
+                    stage.process_wal(other)?;
+                }
+            }
+
+            if stage
+                .commit_policy
+                .is_due(pending.len(), pending_since.elapsed())
+            {
+                stage.process_apply(&pending)?;
+                pending.clear();
+            }
+
+            if pending.is_empty() {
+                self.0 = seq;
+            }
+        }
+
+        // we reached the end of the wal, nothing to wait for
+        stage.process_apply(&pending)?;
+
+        if let Some(seq) = last_seq {
             self.0 = seq;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gasket::framework::Worker as _;
+    use pallas::ledger::traverse::MultiEraBlock;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::ledger::{ChainPoint, LedgerDelta};
+    use crate::state::{memory, LedgerStore};
+    use crate::wal::WalWriter as _;
+
+    #[test]
+    fn commit_policy_thresholds() {
+        let policy = CommitPolicy {
+            max_blocks: 10,
+            max_interval_ms: 1_000,
+        };
+
+        assert!(!policy.is_due(0, Duration::ZERO));
+        assert!(!policy.is_due(9, Duration::from_millis(999)));
+        assert!(policy.is_due(10, Duration::ZERO));
+        assert!(policy.is_due(1, Duration::from_secs(1)));
+
+        // the default keeps committing every block
+        assert!(CommitPolicy::default().is_due(1, Duration::ZERO));
+    }
+
+    /// A chain of empty byron blocks, one for each slot starting at zero
+    ///
+    /// The header of the dummy block is patched with the slot of each block so
+    /// that the WAL points match the slot and hash the ledger derives from the
+    /// block body.
+    fn chained_blocks(quantity: u8) -> Vec<wal::RawBlock> {
+        // slot id (epoch 0, slot 0) followed by the 64-byte key of the issuer
+        const SLOT_ID: [u8; 6] = [0x84, 0x82, 0x00, 0x00, 0x58, 0x40];
+
+        let template = wal::testing::dummy_block_from_slot(0).body;
+
+        let offset = template
+            .windows(SLOT_ID.len())
+            .position(|x| x == SLOT_ID)
+            .unwrap();
+
+        (0..quantity)
+            .map(|slot| {
+                // slots under 24 are encoded in the initial byte of the uint
+                assert!(slot < 24);
+
+                let mut body = template.clone();
+                body[offset + 3] = slot;
+
+                let block = MultiEraBlock::decode(&body).unwrap();
+                let (slot, hash, era) = (block.slot(), block.hash(), block.era());
+
+                wal::RawBlock {
+                    slot,
+                    hash,
+                    era,
+                    body,
+                }
+            })
+            .collect()
+    }
+
+    fn test_stage(
+        wal: &wal::redb::WalStore,
+        ledger: &LedgerStore,
+        commit_policy: CommitPolicy,
+    ) -> Stage {
+        let path = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("examples")
+            .join("sync-mainnet");
+
+        Stage::new(
+            wal.clone(),
+            ledger.clone(),
+            crate::mempool::Mempool::new(),
+            byron::from_file(&path.join("byron.json")).unwrap(),
+            shelley::from_file(&path.join("shelley.json")).unwrap(),
+            NetworkParams::mainnet(),
+            Default::default(),
+            commit_policy,
+        )
+    }
+
+    #[tokio::test]
+    async fn stores_converge_after_crash_between_commits() {
+        let blocks = chained_blocks(10);
+
+        let mut wal = wal::testing::empty_db();
+        wal.roll_forward(blocks.clone().into_iter()).unwrap();
+
+        let ledger: LedgerStore = memory::LedgerStore::new().into();
+
+        let policy = CommitPolicy {
+            max_blocks: 4,
+            max_interval_ms: 60_000,
+        };
+
+        // the first batch made it to the ledger, the process crashed before
+        // committing the second one
+        let mut crashed = test_stage(&wal, &ledger, policy.clone());
+        crashed.process_apply(&blocks[..4]).unwrap();
+        drop(crashed);
+
+        let last = &blocks[3];
+        assert_eq!(
+            ledger.cursor().unwrap(),
+            Some(ChainPoint(last.slot, last.hash))
+        );
+
+        let commits = Arc::new(Mutex::new(vec![]));
+        let seen = commits.clone();
+
+        ledger.register_observer(Arc::new(move |deltas: &[LedgerDelta]| {
+            seen.lock().unwrap().push(deltas.len());
+        }));
+
+        // on restart, the stage resumes from the ledger cursor and replays the
+        // rest of the WAL in batches
+        let mut stage = test_stage(&wal, &ledger, policy);
+        let mut worker = Worker::bootstrap(&stage).await.unwrap();
+
+        worker
+            .execute(&RollEvent::TipChanged, &mut stage)
+            .await
+            .unwrap();
+
+        assert_eq!(*commits.lock().unwrap(), vec![4, 2]);
+
+        let (tip_seq, wal_tip) = wal.find_tip().unwrap().unwrap();
+        let ledger_tip = ledger.cursor().unwrap().unwrap();
+
+        assert_eq!(
+            wal_tip,
+            wal::ChainPoint::Specific(ledger_tip.0, ledger_tip.1)
+        );
+        assert_eq!(worker.0, tip_seq);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::ledger::network::NetworkParams;
+use crate::ledger::DeltaLimits;
 use crate::state::LedgerStore;
 use crate::wal::redb::WalStore;
 use crate::{mempool::Mempool, prelude::*};
@@ -13,12 +15,22 @@ pub mod submit;
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub pull_batch_size: Option<usize>,
+
+    /// Size guards applied to each block delta before writing it to the ledger
+    #[serde(default)]
+    pub delta_limits: DeltaLimits,
+
+    /// How often blocks read from the WAL are committed to the ledger
+    #[serde(default)]
+    pub commit_policy: apply::CommitPolicy,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             pull_batch_size: Some(100),
+            delta_limits: Default::default(),
+            commit_policy: Default::default(),
         }
     }
 }
@@ -54,6 +66,8 @@ pub fn pipeline(
     mempool: Mempool,
     retries: &Option<gasket::retries::Policy>,
 ) -> Result<Vec<gasket::runtime::Tether>, Error> {
+    let network = NetworkParams::from_genesis(&byron, &shelley).map_err(Error::config)?;
+
     let mut pull = pull::Stage::new(
         upstream.peer_address.clone(),
         upstream.network_magic,
@@ -63,7 +77,16 @@ pub fn pipeline(
 
     let mut roll = roll::Stage::new(wal.clone());
 
-    let mut apply = apply::Stage::new(wal.clone(), ledger, mempool.clone(), byron, shelley);
+    let mut apply = apply::Stage::new(
+        wal.clone(),
+        ledger,
+        mempool.clone(),
+        byron,
+        shelley,
+        network,
+        config.delta_limits.clone(),
+        config.commit_policy.clone(),
+    );
 
     let submit = submit::Stage::new(
         upstream.peer_address.clone(),
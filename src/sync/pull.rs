@@ -1,6 +1,6 @@
 use gasket::framework::*;
 use itertools::Itertools;
-use pallas::ledger::traverse::{MultiEraBlock, MultiEraHeader};
+use pallas::ledger::traverse::MultiEraHeader;
 use pallas::network::facades::PeerClient;
 use pallas::network::miniprotocols::chainsync::{
     HeaderContent, NextResponse, RollbackBuffer, RollbackEffect, Tip,
@@ -237,7 +237,7 @@ impl Stage {
             // TODO: can we avoid decoding in this stage?
 
             let payload = {
-                let decoded = MultiEraBlock::decode(&cbor).or_panic()?;
+                let decoded = crate::ledger::decode_block(&cbor).or_panic()?;
 
                 RawBlock {
                     slot: decoded.slot(),
@@ -51,12 +51,7 @@ impl Stage {
                 self.store.roll_forward(std::iter::once(block)).or_panic()?;
             }
             PullEvent::Rollback(point) => {
-                let point = match point {
-                    pallas::network::miniprotocols::Point::Origin => wal::ChainPoint::Origin,
-                    pallas::network::miniprotocols::Point::Specific(s, h) => {
-                        wal::ChainPoint::Specific(*s, h.as_slice().into())
-                    }
-                };
+                let point = wal::ChainPoint::from(point.clone());
 
                 info!(?point, "rolling back wal");
 
@@ -19,6 +19,15 @@ pub type BlockEra = pallas::ledger::traverse::Era;
 pub type ChainTip = pallas::network::miniprotocols::chainsync::Tip;
 pub type BlockBody = Vec<u8>;
 pub type BlockHeader = Vec<u8>;
+
+/// Position of an entry in the WAL
+///
+/// Sequences are assigned in strictly increasing order as entries are
+/// appended and are never reused, so they identify an entry across restarts
+/// and reconnects. Chain points can't be used for that purpose: the undo of a
+/// block shares the slot and hash of its apply. Consumers that resume from a
+/// known sequence should use [`ReadUtils::skip_processed`] to drop entries
+/// they've already seen.
 pub type LogSeq = u64;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -123,6 +132,40 @@ mod tests {
         hasher.finalize()
     }
 
+    #[test]
+    fn resume_skips_processed_entries() {
+        let mut wal = testing::db_with_dummy_blocks(10);
+
+        wal.roll_back(&ChainPoint::Specific(5, testing::slot_to_hash(5)))
+            .unwrap();
+
+        // the consumer saw everything up to the undo of slot 9 before dropping
+        let seen: Vec<_> = wal.crawl_from(None).unwrap().collect();
+        let (last, _) = seen
+            .iter()
+            .find(|(_, x)| matches!(x, LogValue::Undo(b) if b.slot == 9))
+            .unwrap()
+            .clone();
+
+        // on reconnect it resumes from an earlier sequence, overlapping entries
+        let resumed: Vec<_> = wal
+            .crawl_from(Some(last - 3))
+            .unwrap()
+            .skip_processed(Some(last))
+            .collect();
+
+        let expected: Vec<_> = seen.into_iter().filter(|(seq, _)| *seq > last).collect();
+
+        assert!(!expected.is_empty());
+        assert_eq!(resumed, expected);
+
+        // without a previous sequence, nothing is skipped
+        assert_eq!(
+            wal.crawl_from(None).unwrap().skip_processed(None).count(),
+            wal.crawl_from(None).unwrap().count()
+        );
+    }
+
     #[test]
     fn chainpoint_partial_eq() {
         assert_eq!(ChainPoint::Origin, ChainPoint::Origin);
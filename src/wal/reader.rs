@@ -4,6 +4,13 @@ pub trait ReadUtils<'a> {
     fn filter_apply(self) -> impl Iterator<Item = LogEntry>;
     fn filter_forward(self) -> impl Iterator<Item = LogEntry>;
     fn into_blocks(self) -> impl Iterator<Item = Option<RawBlock>>;
+
+    /// Drops the entries at or below the last sequence already processed
+    ///
+    /// Meant for consumers resuming after a reconnect, which might receive
+    /// again some of the entries they've already seen. Passing `None` means
+    /// nothing was processed yet and every entry is kept.
+    fn skip_processed(self, last: Option<LogSeq>) -> impl Iterator<Item = LogEntry>;
 }
 
 impl<'a, T> ReadUtils<'a> for T
@@ -25,6 +32,10 @@ where
             _ => None,
         })
     }
+
+    fn skip_processed(self, last: Option<LogSeq>) -> impl Iterator<Item = LogEntry> {
+        self.filter(move |(seq, _)| !matches!(last, Some(last) if *seq <= last))
+    }
 }
 
 #[trait_variant::make(Send)]